@@ -0,0 +1,192 @@
+//! A cache of parsed execution logs (`--cache <dir>`).
+//!
+//! Parsing a big execution log takes minutes; the actions in it are saved
+//! (with `bincode`) so that the next session that's given the same log can
+//! load them instead. Entries are keyed on the log's path, size and
+//! modification time and are ignored (and replaced) once the log changes.
+//!
+//! Like the execution logs themselves, a cache entry is read into memory and
+//! is never freed; the loaded actions borrow from it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+
+use super::{ActionContext, BuildAction, LogFormat, Map, Producers};
+
+/// Bump this when the layout of an entry (or of `ActionContext`) changes.
+const FORMAT_VERSION: u32 = 6;
+
+/// Identifies the version of an execution log that a cache entry is for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Key {
+    format_version: u32,
+    /// Entries made with the `json-dump-command` feature also hold the JSON
+    /// for each action.
+    with_json: bool,
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+}
+
+impl Key {
+    /// The key for the execution log at `path`, if it's a file (logs from
+    /// stdin or URLs aren't cached).
+    pub(crate) fn for_log(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok().filter(|m| m.is_file())?;
+
+        Some(Self {
+            format_version: FORMAT_VERSION,
+            with_json: cfg!(feature = "json-dump-command"),
+            path: path.canonicalize().ok()?,
+            len: meta.len(),
+            modified: meta.modified().ok()?,
+        })
+    }
+
+    /// The (canonical) path of the execution log.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The size of the execution log.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Where the entry for this log lives in `dir`.
+    ///
+    /// Only the path is hashed; the rest of the key is checked when the
+    /// entry is loaded.
+    fn entry(&self, dir: &Path) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        self.path.hash(&mut hasher);
+        dir.join(format!("{:016x}.bin", hasher.finish()))
+    }
+}
+
+/// Reads the entry for `key` from `dir`, if there's an up to date one, along
+/// with the format of the log that it was parsed from.
+///
+/// The returned bytes are meant to be handed to [`parse`].
+pub(crate) fn load(dir: &Path, key: &Key) -> Option<(LogFormat, &'static [u8])> {
+    let mut entry = BufReader::new(File::open(key.entry(dir)).ok()?);
+    let stored: Key = bincode::deserialize_from(&mut entry).ok()?;
+    if stored != *key {
+        return None;
+    }
+    let format = bincode::deserialize_from(&mut entry).ok()?;
+
+    let mut rest = Vec::new();
+    entry.read_to_end(&mut rest).ok()?;
+
+    // Like the maps, this is around until we exit:
+    Some((format, Box::leak(rest.into())))
+}
+
+/// An entry, after its key.
+///
+/// Actions are only stored once, no matter how many outputs they have; each
+/// output is stored with the index of the action that produced it.
+#[derive(Serialize, Deserialize)]
+struct Entry<A, S> {
+    actions: Vec<A>,
+    /// The JSON for each action (only with `json-dump-command`).
+    json: Vec<String>,
+    outputs: Vec<(S, usize)>,
+    /// The indices of every action that produced the outputs that have more
+    /// than one.
+    producers: Vec<(S, Vec<usize>)>,
+}
+
+/// Rebuilds the map (and the outputs that have multiple actions) stored in an
+/// entry that was read by [`load`].
+pub(crate) fn parse<'l>(entry: &'l [u8]) -> eyre::Result<(Map<'l>, Producers<'l>)> {
+    let entry: Entry<ActionContext<'l>, &'l str> = bincode::deserialize(entry)?;
+
+    #[cfg(feature = "json-dump-command")]
+    let actions = entry
+        .actions
+        .into_iter()
+        .zip(entry.json.iter())
+        .map(|(a, j)| Ok(Arc::new((a, serde_json::from_str(j)?))))
+        .collect::<eyre::Result<Vec<_>>>()?;
+    #[cfg(not(feature = "json-dump-command"))]
+    let actions: Vec<_> = entry.actions.into_iter().map(|a| Arc::new((a,))).collect();
+
+    let action = |i: usize| {
+        actions
+            .get(i)
+            .cloned()
+            .ok_or_else(|| eyre::eyre!("cache entry is corrupt"))
+    };
+    let map = entry
+        .outputs
+        .into_iter()
+        .map(|(o, i)| Ok((o, action(i)?)))
+        .collect::<eyre::Result<_>>()?;
+    let producers = entry
+        .producers
+        .into_iter()
+        .map(|(o, i)| Ok((o, i.into_iter().map(action).collect::<eyre::Result<_>>()?)))
+        .collect::<eyre::Result<_>>()?;
+
+    Ok((map, producers))
+}
+
+/// Saves the parsed execution log described by `key` (a log in `format`) to
+/// `dir`.
+pub(crate) fn store<'m>(
+    dir: &Path,
+    key: &Key,
+    format: LogFormat,
+    map: &'m Map<'m>,
+    producers: &'m Producers<'m>,
+) -> eyre::Result<()> {
+    let mut entry = Entry {
+        actions: Vec::new(),
+        json: Vec::new(),
+        outputs: Vec::with_capacity(map.len()),
+        producers: Vec::with_capacity(producers.len()),
+    };
+    let mut indices = HashMap::new();
+    let mut index = |entry: &mut Entry<_, _>, action: &'m Arc<BuildAction<'m>>| {
+        *indices.entry(Arc::as_ptr(action)).or_insert_with(|| {
+            entry.actions.push(&action.0);
+            #[cfg(feature = "json-dump-command")]
+            entry.json.push(action.1.to_string());
+            entry.actions.len() - 1
+        })
+    };
+    for (output, action) in map {
+        let idx = index(&mut entry, action);
+        entry.outputs.push((output, idx));
+    }
+    for (output, actions) in producers {
+        let idx = actions.iter().map(|a| index(&mut entry, a)).collect();
+        entry.producers.push((output, idx));
+    }
+
+    // Written to a temporary file first so that an interrupted write never
+    // leaves a truncated entry behind:
+    fs::create_dir_all(dir)?;
+    let path = key.entry(dir);
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    bincode::serialize_into(&mut out, key)?;
+    bincode::serialize_into(&mut out, &format)?;
+    bincode::serialize_into(&mut out, &entry)?;
+    out.flush()?;
+    drop(out);
+
+    fs::rename(tmp, path)?;
+    Ok(())
+}
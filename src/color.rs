@@ -0,0 +1,122 @@
+//! Colored output that can be turned off (see `--color`).
+//!
+//! [`Colorize`] has the same methods as the parts of [`OwoColorize`] that we
+//! use, but the values it produces only emit escape codes while coloring is
+//! enabled; importing it instead of [`OwoColorize`] is all that's needed to
+//! make some output respect `--color`.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use color_eyre::owo_colors::OwoColorize;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turns coloring on or off for everything printed afterwards.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether output is currently colored.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// When to color output (`--color`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when stdout is a terminal and `NO_COLOR` isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub const NAMES: &'static [&'static str] = &["auto", "always", "never"];
+
+    /// Whether this choice means output should be colored.
+    pub fn enabled(self) -> bool {
+        use std::io::IsTerminal;
+
+        match self {
+            Self::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!("unknown color choice `{}`", s)),
+        }
+    }
+}
+
+macro_rules! styles {
+    ($($method:ident => $style:ident),* $(,)?) => {
+        #[derive(Debug, Clone, Copy)]
+        enum Style {
+            $($style,)*
+        }
+
+        pub trait Colorize: Sized {
+            $(
+                fn $method(&self) -> Styled<'_, Self> {
+                    Styled(self, Style::$style)
+                }
+            )*
+        }
+
+        impl<T: fmt::Display> fmt::Display for Styled<'_, T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if !enabled() {
+                    return fmt::Display::fmt(self.0, f);
+                }
+
+                match self.1 {
+                    $(Style::$style => fmt::Display::fmt(&OwoColorize::$method(self.0), f),)*
+                }
+            }
+        }
+
+        impl<T: fmt::Debug> fmt::Debug for Styled<'_, T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if !enabled() {
+                    return fmt::Debug::fmt(self.0, f);
+                }
+
+                match self.1 {
+                    $(Style::$style => fmt::Debug::fmt(&OwoColorize::$method(self.0), f),)*
+                }
+            }
+        }
+    };
+}
+
+/// A value that's printed in some style while coloring is enabled.
+pub struct Styled<'a, T>(&'a T, Style);
+
+styles! {
+    blue => Blue,
+    cyan => Cyan,
+    green => Green,
+    magenta => Magenta,
+    red => Red,
+    yellow => Yellow,
+    bold => Bold,
+    dimmed => Dimmed,
+    underline => Underline,
+    strikethrough => Strikethrough,
+}
+
+impl<T> Colorize for T {}
@@ -0,0 +1,2560 @@
+//! Parsing and comparison of Bazel execution logs.
+//!
+//! This is the library half of `bazel-execlog-cmp`; the REPL is a thin layer
+//! on top of it. An execution log is parsed into a [`Map`] from each output
+//! to the action that produced it (see [`parse_exec_log`]), and the actions
+//! for an output in several logs are compared with [`find_mismatched`] (or,
+//! following mismatched inputs, [`transitive_cmp`]).
+//!
+//! Parsed logs borrow from the bytes they were parsed from where they can, as
+//! do the results of comparing them; `into_owned` copies the results out (see
+//! [`OwnedMismatchSet`]) so that they can outlive the logs.
+
+#![doc(
+    html_root_url = "https://docs.rs/bazel-execlog-cmp/0.1.1", // remember to bump!
+)]
+
+use std::borrow::Cow;
+use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Debug};
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use color_eyre::eyre;
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_json::de::from_slice;
+use smallvec::SmallVec;
+
+mod owned;
+mod proto;
+
+pub use owned::{
+    OwnedDigest, OwnedDivergenceGraph, OwnedMismatch, OwnedMismatchSet, OwnedTransitiveCmp,
+};
+pub use proto::{binary_log_to_hashmap, compact_log_to_hashmap};
+
+/// The hash in a digest, from whichever hash function the build used (see
+/// `Digest::hash_function_name`): 32 bytes for SHA-256 and BLAKE3, 20 for
+/// SHA-1, etc.
+///
+/// Hashes are written as hex in JSON execution logs and as bytes in formats
+/// that aren't self-describing (i.e. the `--cache`).
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hash(SmallVec<[u8; 32]>);
+
+impl Hash {
+    /// Parses a hex encoded hash (of any length).
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        // `from_str_radix` also accepts a sign (i.e. `+f`):
+        if hex.is_empty()
+            || !hex.len().is_multiple_of(2)
+            || !hex.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            return None;
+        }
+
+        hex.as_bytes()
+            .chunks(2)
+            .map(|c| u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok())
+            .collect::<Option<_>>()
+            .map(Self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(SmallVec::from_slice(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(&format_args!("{:x}", self))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HashVisitor;
+
+        impl<'de> Visitor<'de> for HashVisitor {
+            type Value = Hash;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a hex encoded hash")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Hash, E> {
+                Hash::from_hex(v).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Hash, E> {
+                Ok(Hash::from_bytes(v))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HashVisitor)
+        } else {
+            deserializer.deserialize_bytes(HashVisitor)
+        }
+    }
+}
+
+/// The whole hash, in hex.
+impl fmt::LowerHex for Hash {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for x in &self.0 {
+            write!(fmt, "{:02x}", x)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The hash in hex, shortened to the number of digits set with
+/// [`set_hash_len`] (if any).
+impl Debug for Hash {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.shortened(HASH_LEN.load(Ordering::Relaxed)))
+    }
+}
+
+impl Hash {
+    /// The hash in hex, cut down to its first `len` digits; `0` (or a `len`
+    /// that covers the whole hash) gives all of them.
+    fn shortened(&self, len: usize) -> String {
+        let hex = format!("{:x}", self);
+        if len == 0 || len >= hex.len() {
+            hex
+        } else {
+            format!("{}…", &hex[..len])
+        }
+    }
+}
+
+/// How many hex digits of a hash `Debug` shows; `0` for all of them.
+static HASH_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Shortens the hashes shown to people (by `Debug`) to their first `len` hex
+/// digits; `None` shows them in full.
+///
+/// This is only for display: hashes are still compared in full, and
+/// serialized and formatted with `{:x}` in full.
+pub fn set_hash_len(len: Option<usize>) {
+    HASH_LEN.store(len.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// The hash and size of a file.
+///
+/// Digests from different hash functions are never equal, even if their
+/// hashes happen to be.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Digest<'i> {
+    pub hash: Hash,
+    #[serde(rename = "sizeBytes", deserialize_with = "deserialize_size")]
+    pub size_bytes: usize,
+    #[serde(rename = "hashFunctionName")]
+    pub hash_function_name: &'i str,
+}
+
+/// Sizes are strings in JSON execution logs but are plain numbers in formats
+/// that aren't self-describing (i.e. the `--cache`).
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    if deserializer.is_human_readable() {
+        deserialize_number_from_string(deserializer)
+    } else {
+        usize::deserialize(deserializer)
+    }
+}
+
+/// An input or output of an action.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Item<'i> {
+    #[serde(borrow)]
+    pub path: &'i Path,
+    pub digest: Digest<'i>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct EnvVar<'i> {
+    pub name: &'i str,
+    pub value: &'i str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct PlatformProperty<'i> {
+    pub name: &'i str,
+    pub value: &'i str,
+}
+
+/// The execution platform an action ran on (i.e. container image, OS).
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Platform<'i> {
+    #[serde(borrow, default)]
+    pub properties: Vec<PlatformProperty<'i>>,
+}
+
+/// An action (a spawn, in Bazel's terms) from an execution log.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ActionContext<'i> {
+    /// Arguments often have escaped characters (i.e. quotes) in them which
+    /// can't be borrowed from the log as is, hence the `Cow`.
+    #[serde(rename = "commandArgs", borrow)]
+    pub command_args: Vec<Cow<'i, str>>,
+    #[serde(rename = "environmentVariables", borrow)]
+    pub environment_variables: Vec<EnvVar<'i>>,
+    #[serde(borrow, default)]
+    pub platform: Platform<'i>,
+    pub inputs: Vec<Item<'i>>,
+    #[serde(rename = "listedOutputs", borrow)]
+    pub listed_outputs: Vec<&'i str>,
+    pub remotable: bool,
+    pub cacheable: bool,
+    /// The kind of action (i.e. `CppCompile`, `Javac`).
+    #[serde(borrow, default)]
+    pub mnemonic: &'i str,
+    #[serde(rename = "actualOutputs", borrow)]
+    pub actual_outputs: Vec<Item<'i>>,
+    #[serde(borrow, default)]
+    pub status: &'i str,
+    #[serde(rename = "exitCode", default)]
+    pub exit_code: i32,
+    /// Where the action ran (i.e. `linux-sandbox`, `remote` or
+    /// `remote cache hit`).
+    #[serde(borrow, default)]
+    pub runner: &'i str,
+    /// Whether the action's outputs came from a (remote) cache.
+    #[serde(rename = "remoteCacheHit", alias = "cacheHit", default)]
+    pub cache_hit: bool,
+    #[serde(default)]
+    pub metrics: Metrics,
+}
+
+/// How long an action took, from its `metrics`; older versions of Bazel don't
+/// log these (and not every action has all of them).
+///
+/// Timings are never the same in two executions of an action so they don't
+/// make actions unequal: every `Metrics` is equal to every other.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Metrics {
+    /// How long the action took from start to finish.
+    #[serde(rename = "totalTime", default, with = "duration")]
+    pub total_time: Option<Duration>,
+    /// How long the action's process ran for.
+    #[serde(rename = "executionWallTime", default, with = "duration")]
+    pub execution_wall_time: Option<Duration>,
+    /// How long the action waited to run (i.e. for a remote executor).
+    #[serde(rename = "queueTime", default, with = "duration")]
+    pub queue_time: Option<Duration>,
+}
+
+impl PartialEq for Metrics {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Metrics {}
+
+impl PartialOrd for Metrics {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Metrics {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl std::hash::Hash for Metrics {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+
+/// Durations are strings like `"1.500s"` in JSON execution logs (protobuf's
+/// JSON mapping for `Duration`) and are plain `Duration`s in formats that
+/// aren't self-describing (i.e. the `--cache`).
+mod duration {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        d: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            d.map(|d| format!("{}.{:09}s", d.as_secs(), d.subsec_nanos()))
+                .serialize(serializer)
+        } else {
+            d.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        if !deserializer.is_human_readable() {
+            return Option::<Duration>::deserialize(deserializer);
+        }
+
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| {
+                parse(&s).ok_or_else(|| de::Error::custom(format!("`{}` isn't a duration", s)))
+            })
+            .transpose()
+    }
+
+    fn parse(s: &str) -> Option<Duration> {
+        let s = s.strip_suffix('s')?;
+        let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+        if frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let nanos = if frac.is_empty() {
+            0
+        } else {
+            format!("{:0<9}", frac).parse().ok()?
+        };
+        Some(Duration::new(secs.parse().ok()?, nanos))
+    }
+}
+
+pub type Output<'i> = &'i str;
+/// An action, along with its JSON with the `json-dump-command` feature.
+#[cfg(feature = "json-dump-command")]
+pub type BuildAction<'i> = (ActionContext<'i>, serde_json::Value);
+#[cfg(not(feature = "json-dump-command"))]
+pub type BuildAction<'i> = (ActionContext<'i>,);
+
+/// Each output in an execution log and the action that produced it.
+///
+/// Actions with multiple outputs are shared between those outputs' entries.
+pub type Map<'l> = HashMap<Output<'l>, Arc<BuildAction<'l>>>;
+
+/// Every action that produced each of the outputs in an execution log that
+/// more than one action claims (i.e. an output from two configurations that
+/// end up at the same path), in the order they appear in the log.
+///
+/// The `Map` for the log has the last of these.
+pub type Producers<'l> = HashMap<Output<'l>, Vec<Arc<BuildAction<'l>>>>;
+
+/// Records `action` as the producer of each of its outputs in `map`; outputs
+/// that another action has already produced are added to `producers` too.
+pub(crate) fn insert_action<'l>(
+    map: &mut Map<'l>,
+    producers: &mut Producers<'l>,
+    action: Arc<BuildAction<'l>>,
+) {
+    for output in action.0.listed_outputs.iter() {
+        if let Some(prev) = map.insert(*output, action.clone()) {
+            if !Arc::ptr_eq(&prev, &action) {
+                producers
+                    .entry(*output)
+                    .or_insert_with(|| vec![prev])
+                    .push(action.clone());
+            }
+        }
+    }
+}
+
+/// Copies of the strings from an execution log that's parsed as it's read
+/// (`--low-memory`), or of strings that were rewritten (`--normalize-paths`).
+///
+/// Paths are repeated a lot (every action that includes a header lists it) so
+/// each distinct string is only stored once. Like the rest of the parsed logs,
+/// these are never freed.
+#[derive(Default)]
+pub struct Interner(HashSet<&'static str>);
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> &'static str {
+        if let Some(s) = self.0.get(s) {
+            return s;
+        }
+
+        let s = Box::leak(Box::<str>::from(s));
+        self.0.insert(s);
+        s
+    }
+}
+
+impl Item<'_> {
+    fn interned(&self, strings: &mut Interner) -> Item<'static> {
+        Item {
+            path: Path::new(strings.intern(self.path.to_str().unwrap())),
+            digest: Digest {
+                hash: self.digest.hash.clone(),
+                size_bytes: self.digest.size_bytes,
+                hash_function_name: strings.intern(self.digest.hash_function_name),
+            },
+        }
+    }
+}
+
+impl ActionContext<'_> {
+    /// Makes a copy of this action that doesn't borrow from the log it was
+    /// parsed from.
+    pub(crate) fn interned(&self, strings: &mut Interner) -> ActionContext<'static> {
+        ActionContext {
+            command_args: self
+                .command_args
+                .iter()
+                .map(|a| strings.intern(a).into())
+                .collect(),
+            environment_variables: self
+                .environment_variables
+                .iter()
+                .map(|e| EnvVar {
+                    name: strings.intern(e.name),
+                    value: strings.intern(e.value),
+                })
+                .collect(),
+            platform: Platform {
+                properties: self
+                    .platform
+                    .properties
+                    .iter()
+                    .map(|p| PlatformProperty {
+                        name: strings.intern(p.name),
+                        value: strings.intern(p.value),
+                    })
+                    .collect(),
+            },
+            inputs: self.inputs.iter().map(|i| i.interned(strings)).collect(),
+            listed_outputs: self
+                .listed_outputs
+                .iter()
+                .map(|o| strings.intern(o))
+                .collect(),
+            remotable: self.remotable,
+            cacheable: self.cacheable,
+            mnemonic: strings.intern(self.mnemonic),
+            actual_outputs: self
+                .actual_outputs
+                .iter()
+                .map(|i| i.interned(strings))
+                .collect(),
+            status: strings.intern(self.status),
+            exit_code: self.exit_code,
+            runner: strings.intern(self.runner),
+            cache_hit: self.cache_hit,
+            metrics: self.metrics,
+        }
+    }
+}
+
+/// What the configuration directory in `bazel-out/<config>/...` paths is
+/// replaced with by `--normalize-paths`.
+pub const NORMALIZED_CONFIG: &str = "<config>";
+
+/// Replaces the configuration directory in each `bazel-out/<config>/` in `s`
+/// (as in `bazel-out/k8-fastbuild-ST-abc123/bin/foo.o`) with
+/// [`NORMALIZED_CONFIG`], so that an artifact has the same path no matter
+/// which configuration it was built in.
+///
+/// Works on command arguments too (i.e. `-Ibazel-out/k8-opt/bin`).
+pub fn normalize_path(s: &str) -> Cow<'_, str> {
+    const BAZEL_OUT: &str = "bazel-out/";
+
+    if !s.contains(BAZEL_OUT) {
+        return s.into();
+    }
+
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(idx) = rest.find(BAZEL_OUT) {
+        let (head, tail) = rest.split_at(idx + BAZEL_OUT.len());
+        out.push_str(head);
+        // Files right in `bazel-out` (i.e. `stable-status.txt`) are left as is:
+        match tail.find('/') {
+            Some(end) if end > 0 => {
+                out.push_str(NORMALIZED_CONFIG);
+                rest = &tail[end..];
+            }
+            _ => rest = tail,
+        }
+    }
+    out.push_str(rest);
+
+    out.into()
+}
+
+impl<'l> ActionContext<'l> {
+    /// Makes a copy of this action with its paths (and the paths in its
+    /// arguments) normalized by [`normalize_path`].
+    pub fn with_normalized_paths(&self, strings: &mut Interner) -> ActionContext<'l> {
+        let mut path = |p: &'l str| match normalize_path(p) {
+            Cow::Borrowed(p) => p,
+            Cow::Owned(p) => strings.intern(&p),
+        };
+
+        let mut action = self.clone();
+        for item in action.inputs.iter_mut().chain(&mut action.actual_outputs) {
+            item.path = Path::new(path(item.path.to_str().unwrap()));
+        }
+        for output in &mut action.listed_outputs {
+            *output = path(output);
+        }
+        for arg in &mut action.command_args {
+            if let Cow::Owned(a) = normalize_path(arg) {
+                *arg = a.into();
+            }
+        }
+
+        action
+    }
+}
+
+/// Re-keys a parsed execution log on normalized paths (`--normalize-paths`).
+///
+/// Outputs that were only distinct because of their configuration (i.e. a
+/// tool built for both the host and the target) end up with multiple actions;
+/// these are added to `producers`, ordered by their original paths.
+pub fn normalize_paths<'l>(
+    map: Map<'l>,
+    producers: Producers<'l>,
+    strings: &mut Interner,
+) -> (Map<'l>, Producers<'l>) {
+    // Actions are shared by all of their outputs; keep it that way:
+    let mut normalized: HashMap<*const BuildAction<'l>, Arc<BuildAction<'l>>> = HashMap::new();
+
+    // Sorted so that which action ends up in the map for the outputs that
+    // collide doesn't change from run to run:
+    let mut outputs: Vec<_> = map.keys().copied().collect();
+    outputs.sort_unstable();
+
+    let mut all: Producers<'l> = HashMap::with_capacity(map.len());
+    for output in outputs {
+        let actions = match producers.get(output) {
+            Some(actions) => &actions[..],
+            None => std::slice::from_ref(&map[output]),
+        };
+        let output = match normalize_path(output) {
+            Cow::Borrowed(o) => o,
+            Cow::Owned(o) => strings.intern(&o),
+        };
+
+        for action in actions {
+            let action = normalized
+                .entry(Arc::as_ptr(action))
+                .or_insert_with(|| {
+                    Arc::new((
+                        action.0.with_normalized_paths(strings),
+                        #[cfg(feature = "json-dump-command")]
+                        action.1.clone(),
+                    ))
+                })
+                .clone();
+
+            let entry = all.entry(output).or_default();
+            if !entry.iter().any(|a| Arc::ptr_eq(a, &action)) {
+                entry.push(action);
+            }
+        }
+    }
+
+    let mut out = HashMap::with_capacity(all.len());
+    let mut producers = Producers::new();
+    for (output, actions) in all {
+        out.insert(output, actions.last().unwrap().clone());
+        if actions.len() > 1 {
+            producers.insert(output, actions);
+        }
+    }
+
+    (out, producers)
+}
+
+/// Parses a single action (one JSON object) from an execution log.
+///
+/// If `unknown` is given, the names of any fields that `ActionContext` doesn't
+/// model are added to it.
+///
+/// Strings in execution logs aren't always valid UTF-8 (i.e. a file name in
+/// some other encoding that's passed as an argument); these are parsed from a
+/// copy of the object that has the invalid bytes replaced with `U+FFFD`.
+/// Like the rest of the parsed logs, the copy is never freed. The offset of
+/// the first invalid byte in the object is returned alongside the action.
+fn parse_action<'j>(
+    j: &'j [u8],
+    unknown: Option<&mut BTreeSet<String>>,
+) -> eyre::Result<(BuildAction<'j>, Option<usize>)> {
+    let (j, invalid) = match std::str::from_utf8(j) {
+        Ok(_) => (j, None),
+        Err(e) => {
+            let lossy = Box::leak(String::from_utf8_lossy(j).into_owned().into_boxed_str());
+            (lossy.as_bytes(), Some(e.valid_up_to()))
+        }
+    };
+
+    #[cfg(feature = "json-dump-command")]
+    let val = from_slice(j)?;
+    let ctx: ActionContext = if let Some(unknown) = unknown {
+        serde_ignored::deserialize(&mut serde_json::Deserializer::from_slice(j), |p| {
+            unknown.insert(field_name(&p));
+        })?
+    } else {
+        from_slice(j)?
+    };
+
+    Ok((
+        (
+            ctx,
+            #[cfg(feature = "json-dump-command")]
+            val,
+        ),
+        invalid,
+    ))
+}
+
+/// Adds where an object that couldn't be parsed is in its execution log to the
+/// error: the object's index, its byte range and the bytes around the error.
+fn object_error(e: eyre::Report, index: usize, start: usize, j: &[u8]) -> eyre::Report {
+    const SNIPPET: usize = 40;
+
+    // `serde_json` reports a line and column (within the object), not an
+    // offset:
+    let at = match e.downcast_ref::<serde_json::Error>() {
+        Some(e) => {
+            let line: usize = j
+                .split(|b| *b == b'\n')
+                .take(e.line().saturating_sub(1))
+                .map(|l| l.len() + 1)
+                .sum();
+            (line + e.column().saturating_sub(1)).min(j.len())
+        }
+        None => 0,
+    };
+    let snippet =
+        String::from_utf8_lossy(&j[at.saturating_sub(SNIPPET)..(at + SNIPPET).min(j.len())]);
+
+    let msg = format!(
+        "couldn't parse object {} (bytes {}..={}) in the execution log; the error is at byte {}, around: `{}`",
+        index,
+        start,
+        start + j.len().saturating_sub(1),
+        start + at,
+        snippet.split_whitespace().collect::<Vec<_>>().join(" "),
+    );
+    e.wrap_err(msg)
+}
+
+/// Finds where the objects in an execution log end, one byte at a time.
+///
+/// Braces inside of strings (i.e. an argument like `-DFOO={}{}`) are skipped
+/// over so that they aren't mistaken for object boundaries.
+#[derive(Debug, Default)]
+struct ObjectSplitter {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl ObjectSplitter {
+    /// Returns `true` if `b` is the closing brace of a top-level object.
+    fn ends_object(&mut self, b: u8) -> bool {
+        if self.in_string {
+            match b {
+                _ if self.escaped => self.escaped = false,
+                b'\\' => self.escaped = true,
+                b'"' => self.in_string = false,
+                _ => {}
+            }
+
+            return false;
+        }
+
+        match b {
+            b'"' => self.in_string = true,
+            b'{' => self.depth += 1,
+            b'}' => {
+                self.depth = self.depth.saturating_sub(1);
+                return self.depth == 0;
+            }
+            _ => {}
+        }
+
+        false
+    }
+}
+
+/// Parses a JSON execution log; returns the map of its outputs along with the
+/// outputs that were produced by more than one action (and those actions).
+///
+/// This is [`exec_log_to_hashmap`] without progress reporting.
+pub fn parse_exec_log(log: &[u8]) -> eyre::Result<(Map<'_>, Producers<'_>)> {
+    exec_log_to_hashmap(log, &ProgressBar::hidden(), None, &mut Vec::new())
+}
+
+/// Execution logs are tricky since they're composed of concatenated JSON objects.
+///
+/// As in:
+/// ```json
+/// { "foo": true, bar: 8, ... }{ "foo": false, bar: 12, ... }
+/// ```
+///
+/// If `unknown_fields` is given, the names of any fields that `ActionContext`
+/// doesn't model are added to it.
+///
+/// The byte offset of the first invalid UTF-8 sequence in each action that has
+/// one is added to `invalid_utf8`; these actions are still parsed, with the
+/// invalid bytes replaced with `U+FFFD`.
+pub fn exec_log_to_hashmap<'l>(
+    log: &'l [u8],
+    pb: &ProgressBar,
+    unknown_fields: Option<&Mutex<BTreeSet<String>>>,
+    invalid_utf8: &mut Vec<usize>,
+) -> eyre::Result<(Map<'l>, Producers<'l>)> {
+    let mut prev = 0;
+    let mut splitter = ObjectSplitter::default();
+    let mut map = HashMap::new();
+
+    let mut producers = Producers::new();
+    let mut unknown = BTreeSet::new();
+
+    let mut index = 0;
+    let mut process_obj = |j: &'l [u8], start: usize| -> eyre::Result<()> {
+        let (action, invalid) = parse_action(j, unknown_fields.is_some().then_some(&mut unknown))
+            .map_err(|e| object_error(e, index, start, j))?;
+        index += 1;
+        invalid_utf8.extend(invalid.map(|i| start + i));
+
+        insert_action(&mut map, &mut producers, Arc::new(action));
+
+        Ok(())
+    };
+
+    for (curr, b) in log.iter().enumerate() {
+        if splitter.ends_object(*b) {
+            process_obj(&log[prev..=curr], prev)?;
+
+            prev = curr + 1;
+        }
+
+        if (curr + 1) % 10_000 == 0 {
+            pb.inc(10_000);
+        }
+    }
+    if !log[prev..].iter().all(u8::is_ascii_whitespace) {
+        process_obj(&log[prev..], prev)?;
+    }
+
+    if let Some(u) = unknown_fields {
+        u.lock().unwrap().append(&mut unknown);
+    }
+
+    pb.finish();
+
+    Ok((map, producers))
+}
+
+/// Like `exec_log_to_hashmap` but parses each action as soon as it's been read
+/// instead of needing the whole log in memory first.
+///
+/// The returned map doesn't borrow from `log`; only one action's worth of the
+/// log is kept in memory at a time.
+pub fn exec_log_to_hashmap_streaming(
+    mut log: impl BufRead,
+    pb: &ProgressBar,
+    unknown_fields: Option<&Mutex<BTreeSet<String>>>,
+    invalid_utf8: &mut Vec<usize>,
+) -> eyre::Result<(Map<'static>, Producers<'static>)> {
+    let mut buf = Vec::new();
+    // Where `buf` starts in the log:
+    let mut buf_start = 0;
+    let mut read = 0;
+    let mut splitter = ObjectSplitter::default();
+    let mut map = HashMap::new();
+    let mut strings = Interner::default();
+
+    let mut producers = Producers::new();
+    let mut unknown = BTreeSet::new();
+
+    let mut index = 0;
+    let mut process_obj = |j: &[u8], start: usize| -> eyre::Result<()> {
+        let (action, invalid) = parse_action(j, unknown_fields.is_some().then_some(&mut unknown))
+            .map_err(|e| object_error(e, index, start, j))?;
+        index += 1;
+        invalid_utf8.extend(invalid.map(|i| start + i));
+
+        let action = Arc::new((
+            action.0.interned(&mut strings),
+            #[cfg(feature = "json-dump-command")]
+            action.1,
+        ));
+        insert_action(&mut map, &mut producers, action);
+
+        Ok(())
+    };
+
+    loop {
+        let chunk = log.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        let mut start = 0;
+        for (i, b) in chunk.iter().enumerate() {
+            if splitter.ends_object(*b) {
+                buf.extend_from_slice(&chunk[start..=i]);
+                process_obj(&buf, buf_start)?;
+                buf.clear();
+
+                start = i + 1;
+                buf_start = read + start;
+            }
+        }
+        buf.extend_from_slice(&chunk[start..]);
+
+        let len = chunk.len();
+        log.consume(len);
+        pb.inc(len as _);
+        read += len;
+    }
+    if !buf.iter().all(u8::is_ascii_whitespace) {
+        process_obj(&buf, buf_start)?;
+    }
+
+    if let Some(u) = unknown_fields {
+        u.lock().unwrap().append(&mut unknown);
+    }
+
+    pb.finish();
+
+    Ok((map, producers))
+}
+
+/// Turns the path to a field (as reported by `serde_ignored`) into a name like
+/// `inputs[].digest.foo`; array indices are dropped so that a field is only
+/// listed once.
+fn field_name(path: &serde_ignored::Path<'_>) -> String {
+    use serde_ignored::Path::*;
+
+    match path {
+        Root => String::new(),
+        Seq { parent, .. } => format!("{}[]", field_name(parent)),
+        Map { parent, key } => match field_name(parent) {
+            p if p.is_empty() => key.clone(),
+            p => format!("{}.{}", p, key),
+        },
+        Some { parent } | NewtypeStruct { parent } | NewtypeVariant { parent } => {
+            field_name(parent)
+        }
+    }
+}
+
+/// An output, as the name of the artifact whose actions are compared.
+pub type ArtifactName<'l> = &'l str;
+
+/// Finds the keys (i.e. env var names) of the `entries` of `actions` that some
+/// of the actions don't have or that the actions have different values for;
+/// `key_value` picks the key and value out of an entry.
+///
+/// Entries are often listed more than once by an action (inputs especially);
+/// only the first entry for a key in each action counts. The actions are
+/// tallied in parallel and so are the chunks of each action's entries (one
+/// action can have tens of thousands of inputs, i.e. a big link); the tallies
+/// are merged after. The keys are sorted so that the results don't depend on
+/// how the work was split up.
+fn mismatched_keys<'l, T, K, V>(
+    actions: &[&'l ActionContext<'l>],
+    entries: impl Fn(&'l ActionContext<'l>) -> &'l [T] + Sync,
+    key_value: impl Fn(&'l T) -> (K, V) + Sync,
+) -> Vec<(K, MismatchClass)>
+where
+    T: Sync + 'l,
+    K: Copy + Ord + std::hash::Hash + Send,
+    V: Copy + Eq + Send,
+{
+    /// How many of an action's entries are tallied at once.
+    const CHUNK: usize = 4096;
+
+    // For each key: the value the actions agree on (if they do) and how many
+    // of the actions have the key.
+    type Tally<K, V> = HashMap<K, (Option<V>, usize)>;
+
+    let mut keys: Vec<_> = actions
+        .par_iter()
+        .map(|a| {
+            entries(a)
+                .par_chunks(CHUNK)
+                .map(|chunk| {
+                    let mut tally = Tally::new();
+                    for (k, v) in chunk.iter().map(&key_value) {
+                        tally.entry(k).or_insert((Some(v), 1));
+                    }
+                    tally
+                })
+                // Chunks of the same action: the earlier chunk's entry for a
+                // key wins and the key still only counts once.
+                .reduce(Tally::new, |mut a, mut b| {
+                    if a.len() < b.len() {
+                        b.extend(a);
+                        b
+                    } else {
+                        for (k, entry) in b.drain() {
+                            a.entry(k).or_insert(entry);
+                        }
+                        a
+                    }
+                })
+        })
+        .reduce(Tally::new, |mut a, mut b| {
+            if a.len() < b.len() {
+                std::mem::swap(&mut a, &mut b);
+            }
+            for (k, (v, count)) in b {
+                let (val, c) = a.entry(k).or_insert((v, 0));
+                if *val != v {
+                    *val = None;
+                }
+                *c += count;
+            }
+            a
+        })
+        .into_iter()
+        .filter_map(|(k, (v, c))| Some((k, MismatchClass::new(v.is_none(), c != actions.len())?)))
+        .collect();
+    keys.sort_unstable_by_key(|(k, _)| *k);
+    keys
+}
+
+/// Whether a mismatched input or output has a different digest in some of the
+/// logs, is missing from some of them, or both.
+///
+/// These usually mean different things: a missing input is a difference in
+/// the action's dependencies while a differing input is a difference in what
+/// produced it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchClass {
+    /// Present in every log but not the same in all of them.
+    Different,
+    /// Missing from some logs; the same in the rest.
+    Missing,
+    MissingAndDifferent,
+}
+
+impl MismatchClass {
+    fn new(different: bool, missing: bool) -> Option<Self> {
+        match (different, missing) {
+            (true, false) => Some(Self::Different),
+            (false, true) => Some(Self::Missing),
+            (true, true) => Some(Self::MissingAndDifferent),
+            (false, false) => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Different => "(different)",
+            Self::Missing => "(missing from some logs)",
+            Self::MissingAndDifferent => "(missing from some logs, different in others)",
+        }
+    }
+}
+
+/// Something that differs between the actions for `artifact`: an env var, an
+/// input, etc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch<'l, K, V> {
+    /// The output whose actions have the mismatch.
+    pub artifact: ArtifactName<'l>,
+    /// What's mismatched: the name of an env var, the path of an input, etc.
+    pub key: K,
+    /// Whether the item is missing from some of the actions, different in
+    /// some, or both.
+    pub class: MismatchClass,
+    /// The value that each of the compared actions has, along with the label
+    /// of the log that it's from (`None` if the action doesn't have the item).
+    pub values: Vec<(&'l str, Option<V>)>,
+}
+
+impl<'l, K, V: Copy> Mismatch<'l, K, V> {
+    /// The value that the log labelled `log` has; `None` if it's not present
+    /// (or if that log's action wasn't compared).
+    pub fn value(&self, log: &str) -> Option<V> {
+        self.values.iter().find(|(l, _)| *l == log)?.1
+    }
+
+    /// The logs whose value differs from the one that the log labelled
+    /// `baseline` has, along with their values; when one log is treated as
+    /// the reference, these are the logs that deviate from it.
+    ///
+    /// The baseline itself is never included.
+    pub fn deviations<'s>(
+        &'s self,
+        baseline: &'s str,
+    ) -> impl Iterator<Item = (&'l str, Option<V>)> + 's
+    where
+        V: PartialEq,
+    {
+        let reference = self.value(baseline);
+        self.values
+            .iter()
+            .filter(move |(l, v)| *l != baseline && *v != reference)
+            .map(|(l, v)| (*l, *v))
+    }
+}
+
+/// What differs between the actions for one or more artifacts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MismatchSet<'l> {
+    /// Env vars, by name.
+    pub env_vars: Vec<Mismatch<'l, &'l str, &'l str>>,
+    /// Inputs, by path.
+    pub inputs: Vec<Mismatch<'l, &'l Path, &'l Digest<'l>>>,
+    /// Outputs, by path.
+    pub outputs: Vec<Mismatch<'l, &'l Path, &'l Digest<'l>>>,
+    /// The first argument that differs, by index.
+    pub args: Vec<Mismatch<'l, usize, &'l str>>,
+    /// Platform properties, by name.
+    pub platform: Vec<Mismatch<'l, &'l str, &'l str>>,
+    /// Exit codes and statuses.
+    pub exit_status: Vec<Mismatch<'l, (), (i32, &'l str)>>,
+    /// Where the actions ran: their runners and whether they were cache hits.
+    ///
+    /// These often explain the other mismatches but aren't mismatches on their
+    /// own (an action can run locally in one build and come from the cache in
+    /// the next); see [`MismatchSet::is_empty`].
+    pub runner: Vec<Mismatch<'l, (), (&'l str, bool)>>,
+}
+
+impl<'l> MismatchSet<'l> {
+    /// Sorts the mismatches (env vars and platform properties by name, inputs
+    /// and outputs by path, then by artifact) so that they're always listed in
+    /// the same order.
+    pub fn sort(&mut self) {
+        self.env_vars.sort_by_key(|m| (m.key, m.artifact));
+        self.inputs.sort_by_key(|m| (m.key, m.artifact));
+        self.outputs.sort_by_key(|m| (m.key, m.artifact));
+        self.args.sort_by_key(|m| (m.artifact, m.key));
+        self.platform.sort_by_key(|m| (m.key, m.artifact));
+        self.exit_status.sort_by_key(|m| m.artifact);
+        self.runner.sort_by_key(|m| m.artifact);
+    }
+
+    /// Moves all of `other`'s mismatches into this set.
+    pub fn append(&mut self, mut other: Self) {
+        self.env_vars.append(&mut other.env_vars);
+        self.inputs.append(&mut other.inputs);
+        self.outputs.append(&mut other.outputs);
+        self.args.append(&mut other.args);
+        self.platform.append(&mut other.platform);
+        self.exit_status.append(&mut other.exit_status);
+        self.runner.append(&mut other.runner);
+    }
+
+    /// The logs that deviate from the log labelled `baseline` in at least one
+    /// of the mismatches (see [`Mismatch::deviations`]), not counting where
+    /// the actions ran.
+    pub fn deviating_logs(&self, baseline: &str) -> BTreeSet<&'l str> {
+        let mut logs = BTreeSet::new();
+        logs.extend(
+            self.env_vars
+                .iter()
+                .flat_map(|m| m.deviations(baseline))
+                .map(|(l, _)| l),
+        );
+        logs.extend(
+            self.inputs
+                .iter()
+                .flat_map(|m| m.deviations(baseline))
+                .map(|(l, _)| l),
+        );
+        logs.extend(
+            self.outputs
+                .iter()
+                .flat_map(|m| m.deviations(baseline))
+                .map(|(l, _)| l),
+        );
+        logs.extend(
+            self.args
+                .iter()
+                .flat_map(|m| m.deviations(baseline))
+                .map(|(l, _)| l),
+        );
+        logs.extend(
+            self.platform
+                .iter()
+                .flat_map(|m| m.deviations(baseline))
+                .map(|(l, _)| l),
+        );
+        logs.extend(
+            self.exit_status
+                .iter()
+                .flat_map(|m| m.deviations(baseline))
+                .map(|(l, _)| l),
+        );
+        logs
+    }
+
+    /// Whether nothing differs, not counting where the actions ran (their
+    /// `runner`s).
+    pub fn is_empty(&self) -> bool {
+        self.env_vars.is_empty()
+            && self.inputs.is_empty()
+            && self.outputs.is_empty()
+            && self.args.is_empty()
+            && self.platform.is_empty()
+            && self.exit_status.is_empty()
+    }
+}
+
+/// Looks up the value that each of the `actions` has for each of the
+/// mismatched `keys` (as found by [`mismatched_keys`]).
+fn with_values<'l, K, V, I>(
+    artifact: ArtifactName<'l>,
+    keys: Vec<(K, MismatchClass)>,
+    actions: &[(&'l str, &'l ActionContext<'l>)],
+    entries: impl Fn(&'l ActionContext<'l>) -> I,
+) -> Vec<Mismatch<'l, K, V>>
+where
+    K: Copy + Eq + std::hash::Hash,
+    V: Copy,
+    I: Iterator<Item = (K, V)>,
+{
+    if keys.is_empty() {
+        return Vec::new();
+    }
+
+    // Like in `mismatched_keys`, only the first entry for a key counts:
+    let lookups: Vec<HashMap<K, V>> = actions
+        .iter()
+        .map(|(_, a)| {
+            let mut lookup = HashMap::new();
+            for (k, v) in entries(a) {
+                lookup.entry(k).or_insert(v);
+            }
+            lookup
+        })
+        .collect();
+
+    keys.into_iter()
+        .map(|(key, class)| Mismatch {
+            artifact,
+            key,
+            class,
+            values: actions
+                .iter()
+                .zip(&lookups)
+                .map(|((l, _), lookup)| (*l, lookup.get(&key).copied()))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Compares the `actions` (from each log, labelled) that produced `artifact`.
+///
+/// Env vars that are ignored (see [`IgnoredEnv`]) are left out.
+pub fn find_mismatched<'l>(
+    artifact: ArtifactName<'l>,
+    actions: impl Iterator<Item = (&'l String, &'l Arc<BuildAction<'l>>)>,
+    ignored_env: &IgnoredEnv,
+) -> MismatchSet<'l> {
+    let labelled: Vec<(&str, &ActionContext<'l>)> =
+        actions.map(|(l, a)| (l.as_str(), &a.0)).collect();
+    let actions: Vec<&ActionContext<'l>> = labelled.iter().map(|(_, a)| *a).collect();
+    let args: Vec<_> = actions.iter().map(|a| &a.command_args).collect();
+    let exits: Vec<_> = actions.iter().map(|a| (a.exit_code, a.status)).collect();
+    let runners: Vec<_> = actions.iter().map(|a| (a.runner, a.cache_hit)).collect();
+
+    // Actions with lots of inputs (i.e. big links) are slow to compare so the
+    // kinds of entries are compared in parallel (as are the actions and the
+    // chunks of their entries, within each kind):
+    let ((env_vars, platform), (inputs, outputs)) = rayon::join(
+        || {
+            rayon::join(
+                || {
+                    mismatched_keys(
+                        &actions,
+                        |a| &a.environment_variables,
+                        |e| (e.name, e.value),
+                    )
+                },
+                || mismatched_keys(&actions, |a| &a.platform.properties, |p| (p.name, p.value)),
+            )
+        },
+        || {
+            rayon::join(
+                || mismatched_keys(&actions, |a| &a.inputs, |i| (i.path, &i.digest)),
+                || mismatched_keys(&actions, |a| &a.actual_outputs, |o| (o.path, &o.digest)),
+            )
+        },
+    );
+    let env_vars = env_vars
+        .into_iter()
+        .filter(|(k, _)| !ignored_env.suppress(k))
+        .collect();
+
+    // Only the first argument that differs is reported; later arguments
+    // usually differ as a consequence (i.e. an extra flag shifts everything
+    // after it over).
+    let len = args.iter().map(|a| a.len()).max().unwrap_or(0);
+    let first_mismatched_arg = (0..len)
+        .find(|i| args.iter().any(|a| a.get(*i) != args[0].get(*i)))
+        .map(|i| {
+            let values: Vec<_> = labelled
+                .iter()
+                .map(|(l, a)| (*l, a.command_args.get(i).map(|a| a.as_ref())))
+                .collect();
+            let present: Vec<_> = values.iter().filter_map(|(_, v)| *v).collect();
+            let class = MismatchClass::new(
+                present.iter().any(|v| *v != present[0]),
+                present.len() != values.len(),
+            );
+            Mismatch {
+                artifact,
+                key: i,
+                class: class.unwrap(),
+                values,
+            }
+        });
+
+    let mismatched_exit = exits.iter().any(|e| *e != exits[0]).then(|| Mismatch {
+        artifact,
+        key: (),
+        class: MismatchClass::Different,
+        values: labelled
+            .iter()
+            .map(|(l, a)| (*l, Some((a.exit_code, a.status))))
+            .collect(),
+    });
+
+    let mismatched_runner = runners.iter().any(|r| *r != runners[0]).then(|| Mismatch {
+        artifact,
+        key: (),
+        class: MismatchClass::Different,
+        values: labelled
+            .iter()
+            .map(|(l, a)| (*l, Some((a.runner, a.cache_hit))))
+            .collect(),
+    });
+
+    MismatchSet {
+        env_vars: with_values(artifact, env_vars, &labelled, |a| {
+            a.environment_variables.iter().map(|e| (e.name, e.value))
+        }),
+        inputs: with_values(artifact, inputs, &labelled, |a| {
+            a.inputs.iter().map(|i| (i.path, &i.digest))
+        }),
+        outputs: with_values(artifact, outputs, &labelled, |a| {
+            a.actual_outputs.iter().map(|o| (o.path, &o.digest))
+        }),
+        args: first_mismatched_arg.into_iter().collect(),
+        platform: with_values(artifact, platform, &labelled, |a| {
+            a.platform.properties.iter().map(|p| (p.name, p.value))
+        }),
+        exit_status: mismatched_exit.into_iter().collect(),
+        runner: mismatched_runner.into_iter().collect(),
+    }
+}
+
+/// Like [`find_mismatched`] but the results are copied out of the logs (see
+/// [`MismatchSet::into_owned`]) so that they can outlive them.
+pub fn find_mismatched_owned<'l>(
+    artifact: ArtifactName<'l>,
+    actions: impl Iterator<Item = (&'l String, &'l Arc<BuildAction<'l>>)>,
+    ignored_env: &IgnoredEnv,
+) -> OwnedMismatchSet {
+    find_mismatched(artifact, actions, ignored_env).into_owned()
+}
+
+/// The artifacts that `transitive_cmp` compared and the mismatched inputs that
+/// it followed from each.
+#[derive(Debug, Default)]
+pub struct DivergenceGraph<'l> {
+    /// Each artifact whose actions were compared and whether they differed.
+    pub nodes: Vec<(ArtifactName<'l>, bool)>,
+    /// From an artifact to one of its (mismatched) inputs.
+    pub edges: Vec<(ArtifactName<'l>, ArtifactName<'l>)>,
+    /// Compared artifacts whose mismatched inputs weren't followed because
+    /// they're at the depth limit.
+    pub truncated: Vec<ArtifactName<'l>>,
+}
+
+impl DivergenceGraph<'_> {
+    /// Writes this graph in Graphviz's DOT format (i.e. for `dot -Tsvg`).
+    ///
+    /// Artifacts whose actions differ are red and those whose actions match
+    /// are green. Inputs that weren't compared (source files, or files that
+    /// only one log generates) are drawn as red boxes and artifacts at the
+    /// depth limit are dashed.
+    pub fn write_dot(&self, mut out: impl io::Write) -> io::Result<()> {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+
+        writeln!(out, "digraph tcmp {{")?;
+        let compared: HashSet<_> = self.nodes.iter().map(|(n, _)| *n).collect();
+        let truncated: HashSet<_> = self.truncated.iter().collect();
+        for (node, mismatched) in &self.nodes {
+            let color = if *mismatched { "red" } else { "green" };
+            let style = if truncated.contains(node) {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            writeln!(out, "    {} [color={}{}];", quote(node), color, style)?;
+        }
+        let uncompared: BTreeSet<_> = self
+            .edges
+            .iter()
+            .map(|(_, i)| *i)
+            .filter(|i| !compared.contains(i))
+            .collect();
+        for node in uncompared {
+            writeln!(out, "    {} [color=red, shape=box];", quote(node))?;
+        }
+        for (from, to) in &self.edges {
+            writeln!(out, "    {} -> {};", quote(from), quote(to))?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// The results of [`transitive_cmp`].
+#[derive(Debug, Default)]
+pub struct TransitiveCmp<'l> {
+    /// The mismatches in every artifact that was compared; each env var,
+    /// platform property, input and output is only listed for one of the
+    /// artifacts that it was found in.
+    pub mismatches: MismatchSet<'l>,
+    /// The divergent actions that have no inputs at all; these are where the
+    /// search for a root cause bottoms out.
+    pub leaves: Vec<ArtifactName<'l>>,
+    /// All of the mismatches in the actions whose inputs match but whose
+    /// outputs don't (i.e. because the action isn't deterministic or its
+    /// command differs); these are where a divergence starts rather than
+    /// actions that just inherit one from their inputs.
+    pub origins: MismatchSet<'l>,
+    /// The artifacts that were visited.
+    pub graph: DivergenceGraph<'l>,
+}
+
+/// Compares `root` and every artifact that it transitively depends on through
+/// mismatched inputs.
+///
+/// If `filter` is given, only mismatched inputs whose paths match it are
+/// followed. If `max_depth` is given, artifacts more than that many input
+/// edges away from `root` aren't compared.
+///
+/// With `progress`, a spinner with the number of artifacts compared so far is
+/// shown until the traversal is done.
+///
+/// If the command is cancelled (see [`cancelled`]), the traversal stops early
+/// and whatever was compared up to that point is returned.
+///
+/// With `confirm`, the traversal pauses once it has compared that many
+/// artifacts and asks the callback (with how many it's compared) whether to
+/// carry on; if not, it stops early like it does when it's cancelled. It's
+/// only asked once.
+pub fn transitive_cmp<'l>(
+    root: ArtifactName<'l>,
+    maps: &'l [(&'l String, Map<'l>)],
+    filter: Option<&Pattern>,
+    max_depth: Option<usize>,
+    ignored_env: &IgnoredEnv,
+    progress: bool,
+    confirm: Option<(usize, &(dyn Fn(usize) -> bool + Sync))>,
+) -> TransitiveCmp<'l> {
+    let (envs, inps, outs, args, plats, exits, runners) = (
+        Mutex::new(HashMap::new()),
+        Mutex::new(HashMap::new()),
+        Mutex::new(HashMap::new()),
+        Mutex::new(Vec::new()),
+        Mutex::new(HashMap::new()),
+        Mutex::new(Vec::new()),
+        Mutex::new(Vec::new()),
+    );
+    // The depth each compared artifact was last expanded at:
+    let visited = RwLock::new(HashMap::new());
+    let leaves = Mutex::new(Vec::new());
+    let origins = Mutex::new(MismatchSet::default());
+    let graph = Mutex::new(DivergenceGraph::default());
+    let compared = AtomicUsize::new(0);
+
+    let spinner = || {
+        progress.then(|| {
+            ProgressBar::new_spinner().with_style(
+                ProgressStyle::default_spinner()
+                    .template("{spinner:.green} [{elapsed_precise}] compared {pos} artifacts"),
+            )
+        })
+    };
+    // The spinner is taken down while `confirm` is asked:
+    let pb = Mutex::new(spinner());
+    let (limit, asking, declined) = (
+        AtomicUsize::new(confirm.map_or(usize::MAX, |(n, _)| n)),
+        Mutex::new(()),
+        AtomicBool::new(false),
+    );
+    // Called before each artifact is compared, returning whether to go on;
+    // the other threads wait (on `asking`) while one of them asks:
+    let check = || {
+        if declined.load(Ordering::Relaxed) {
+            return false;
+        }
+        if compared.load(Ordering::Relaxed) < limit.load(Ordering::Relaxed) {
+            return true;
+        }
+        let _asking = asking.lock().unwrap();
+        let confirm = match confirm {
+            // Another thread might have asked already:
+            Some((_, confirm)) if limit.load(Ordering::Relaxed) != usize::MAX => confirm,
+            _ => return !declined.load(Ordering::Relaxed),
+        };
+
+        if let Some(pb) = pb.lock().unwrap().take() {
+            pb.finish_and_clear();
+        }
+        let carry_on = confirm(compared.load(Ordering::Relaxed));
+        declined.store(!carry_on, Ordering::Relaxed);
+        limit.store(usize::MAX, Ordering::Relaxed);
+        *pb.lock().unwrap() = spinner();
+        carry_on
+    };
+
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn traverse<'l>(
+        artifact: ArtifactName<'l>,
+        depth: usize,
+        (envs, inps, outs, args, plats, exits, runners): (
+            &Mutex<HashMap<&'l str, Mismatch<'l, &'l str, &'l str>>>,
+            &Mutex<HashMap<&'l Path, Mismatch<'l, &'l Path, &'l Digest<'l>>>>,
+            &Mutex<HashMap<&'l Path, Mismatch<'l, &'l Path, &'l Digest<'l>>>>,
+            &Mutex<Vec<Mismatch<'l, usize, &'l str>>>,
+            &Mutex<HashMap<&'l str, Mismatch<'l, &'l str, &'l str>>>,
+            &Mutex<Vec<Mismatch<'l, (), (i32, &'l str)>>>,
+            &Mutex<Vec<Mismatch<'l, (), (&'l str, bool)>>>,
+        ),
+        maps: &'l [(&'l String, Map<'l>)],
+        visited: &RwLock<HashMap<ArtifactName<'l>, usize>>,
+        compared: &AtomicUsize,
+        leaves: &Mutex<Vec<ArtifactName<'l>>>,
+        origins: &Mutex<MismatchSet<'l>>,
+        graph: &Mutex<DivergenceGraph<'l>>,
+        filter: Option<&Pattern>,
+        max_depth: Option<usize>,
+        ignored_env: &IgnoredEnv,
+        check: &(dyn Fn() -> bool + Sync),
+    ) {
+        // With a depth limit, an artifact that was first reached through a
+        // longer chain of inputs has to be expanded again when a shorter one
+        // turns up (its differences have already been recorded, though).
+        let seen = |d: usize| max_depth.is_none() || d <= depth;
+        if !check()
+            || cancelled()
+            || visited
+                .read()
+                .unwrap()
+                .get(artifact)
+                .is_some_and(|d| seen(*d))
+        {
+            return;
+        }
+
+        // Inputs can be generated in only some of the logs (i.e. in one
+        // configuration); the logs that do have actions for them are still
+        // compared. Source files (and outputs that only one log has) aren't.
+        let actions: Vec<_> = maps
+            .iter()
+            .filter_map(|(f, m)| m.get(artifact).map(|a| (*f, a)))
+            .collect();
+        if actions.len() > 1 {
+            let revisit = match visited.write().unwrap().entry(artifact) {
+                Entry::Occupied(e) if seen(*e.get()) => return,
+                Entry::Occupied(mut e) => {
+                    e.insert(depth);
+                    true
+                }
+                Entry::Vacant(e) => {
+                    e.insert(depth);
+                    compared.fetch_add(1, Ordering::Relaxed);
+                    false
+                }
+            };
+
+            let no_inputs = actions.iter().all(|(_, a)| a.0.inputs.is_empty());
+            let mut mismatches = find_mismatched(artifact, actions.into_iter(), ignored_env);
+            let mismatched_inputs = std::mem::take(&mut mismatches.inputs);
+            if !revisit {
+                let diverged = !mismatches.is_empty();
+                if no_inputs && diverged {
+                    leaves.lock().unwrap().push(artifact);
+                }
+                if mismatched_inputs.is_empty() && !mismatches.outputs.is_empty() {
+                    origins.lock().unwrap().append(mismatches.clone());
+                }
+
+                args.lock().unwrap().extend(mismatches.args);
+                exits.lock().unwrap().extend(mismatches.exit_status);
+                runners.lock().unwrap().extend(mismatches.runner);
+                plats
+                    .lock()
+                    .unwrap()
+                    .extend(mismatches.platform.into_iter().map(|m| (m.key, m)));
+                envs.lock()
+                    .unwrap()
+                    .extend(mismatches.env_vars.into_iter().map(|m| (m.key, m)));
+                outs.lock()
+                    .unwrap()
+                    .extend(mismatches.outputs.into_iter().map(|m| (m.key, m)));
+                inps.lock()
+                    .unwrap()
+                    .extend(mismatched_inputs.iter().map(|m| (m.key, m.clone())));
+
+                let mismatched = diverged || !mismatched_inputs.is_empty();
+                graph.lock().unwrap().nodes.push((artifact, mismatched));
+            }
+
+            let followed: Vec<_> = mismatched_inputs
+                .iter()
+                .map(|m| m.key)
+                .filter(|path| filter.map(|f| f.matches_path(path)).unwrap_or(true))
+                .map(|path| path.to_str().unwrap())
+                .collect();
+            {
+                let mut graph = graph.lock().unwrap();
+                if max_depth == Some(depth) {
+                    // Inputs that would only be compared in one log (or none)
+                    // wouldn't have gone any further anyway:
+                    let compared =
+                        |i: &&str| maps.iter().filter(|(_, m)| m.contains_key(i)).count() > 1;
+                    if followed.iter().any(compared) {
+                        graph.truncated.push(artifact);
+                    }
+                    return;
+                }
+                graph.truncated.retain(|t| *t != artifact);
+                graph.edges.extend(followed.iter().map(|i| (artifact, *i)));
+            }
+
+            rayon::scope(|s| {
+                for path in followed {
+                    s.spawn(move |_| {
+                        traverse(
+                            path,
+                            depth + 1,
+                            (envs, inps, outs, args, plats, exits, runners),
+                            maps,
+                            visited,
+                            compared,
+                            leaves,
+                            origins,
+                            graph,
+                            filter,
+                            max_depth,
+                            ignored_env,
+                            check,
+                        )
+                    });
+                }
+            })
+        }
+    }
+
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        // The traversal happens on rayon's threads; this one just polls how
+        // far along it is:
+        if progress {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    if let Some(pb) = &*pb.lock().unwrap() {
+                        pb.set_position(compared.load(Ordering::Relaxed) as u64);
+                        pb.tick();
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                if let Some(pb) = pb.lock().unwrap().take() {
+                    pb.finish_and_clear();
+                }
+            });
+        }
+
+        traverse(
+            root,
+            0,
+            (&envs, &inps, &outs, &args, &plats, &exits, &runners),
+            maps,
+            &visited,
+            &compared,
+            &leaves,
+            &origins,
+            &graph,
+            filter,
+            max_depth,
+            ignored_env,
+            &check,
+        );
+        done.store(true, Ordering::Relaxed);
+    });
+
+    let mut leaves = leaves.into_inner().unwrap();
+    leaves.sort_unstable();
+    let mut graph = graph.into_inner().unwrap();
+    graph.nodes.sort_unstable();
+    graph.edges.sort_unstable();
+    graph.edges.dedup();
+    graph.truncated.sort_unstable();
+
+    TransitiveCmp {
+        mismatches: MismatchSet {
+            env_vars: envs.into_inner().unwrap().into_values().collect(),
+            inputs: inps.into_inner().unwrap().into_values().collect(),
+            outputs: outs.into_inner().unwrap().into_values().collect(),
+            args: args.into_inner().unwrap(),
+            platform: plats.into_inner().unwrap().into_values().collect(),
+            exit_status: exits.into_inner().unwrap(),
+            runner: runners.into_inner().unwrap(),
+        },
+        leaves,
+        origins: origins.into_inner().unwrap(),
+        graph,
+    }
+}
+
+/// Set (with [`cancel`]) when the running command should stop, i.e. when the
+/// user hits Ctrl-C.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Asks the running command to stop, returning whether it had already been
+/// asked to.
+pub fn cancel() -> bool {
+    CANCELLED.swap(true, Ordering::Relaxed)
+}
+
+/// Whether the running command has been cancelled.
+///
+/// Commands that can take a while check this and stop early.
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Clears the cancellation flag, returning whether it was set.
+pub fn reset_cancelled() -> bool {
+    CANCELLED.swap(false, Ordering::Relaxed)
+}
+
+/// The env vars that are left out of comparisons (`--ignore-env` and
+/// `ignore env`).
+///
+/// The names of the env vars whose mismatches were left out are kept so that
+/// commands can say what they didn't show.
+pub struct IgnoredEnv {
+    patterns: RwLock<Vec<Pattern>>,
+    suppressed: Mutex<BTreeSet<String>>,
+}
+
+impl IgnoredEnv {
+    pub fn new(patterns: Vec<Pattern>) -> Self {
+        Self {
+            patterns: RwLock::new(patterns),
+            suppressed: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn add(&self, pattern: Pattern) {
+        self.patterns.write().unwrap().push(pattern);
+    }
+
+    pub fn patterns(&self) -> Vec<Pattern> {
+        self.patterns.read().unwrap().clone()
+    }
+
+    /// Whether mismatches in the env var `name` are ignored; if so, `name` is
+    /// remembered (see [`IgnoredEnv::take_suppressed`]).
+    pub fn suppress(&self, name: &str) -> bool {
+        let ignored = self
+            .patterns
+            .read()
+            .unwrap()
+            .iter()
+            .any(|p| p.matches(name));
+        if ignored {
+            self.suppressed.lock().unwrap().insert(name.to_owned());
+        }
+
+        ignored
+    }
+
+    /// The env vars whose mismatches have been ignored since this was last
+    /// called.
+    pub fn take_suppressed(&self) -> BTreeSet<String> {
+        std::mem::take(&mut self.suppressed.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// A SHA-256 digest whose hash is `hash` repeated (i.e. `'a'` for
+    /// `aaaa...`).
+    fn digest(hash: char) -> Value {
+        json!({
+            "hash": hash.to_string().repeat(64),
+            "sizeBytes": "1",
+            "hashFunctionName": "SHA-256",
+        })
+    }
+
+    /// An action that produces `output` (with the digest for `hash`) from
+    /// `inputs`, with the env vars in `env`.
+    fn action(output: &str, hash: char, inputs: &[(&str, char)], env: &[(&str, &str)]) -> Value {
+        json!({
+            "commandArgs": ["cc", "-c"],
+            "environmentVariables": env
+                .iter()
+                .map(|(n, v)| json!({ "name": n, "value": v }))
+                .collect::<Vec<_>>(),
+            "inputs": inputs
+                .iter()
+                .map(|(p, h)| json!({ "path": p, "digest": digest(*h) }))
+                .collect::<Vec<_>>(),
+            "listedOutputs": [output],
+            "remotable": true,
+            "cacheable": true,
+            "actualOutputs": [{ "path": output, "digest": digest(hash) }],
+        })
+    }
+
+    /// An execution log with `actions` in it, as Bazel writes them.
+    fn log(actions: &[Value]) -> Vec<u8> {
+        actions
+            .iter()
+            .map(|a| serde_json::to_string_pretty(a).unwrap())
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Parses `logs`, labelling each with the matching one of `labels`.
+    fn parse<'l>(labels: &'l [String], logs: &'l [Vec<u8>]) -> Vec<(&'l String, Map<'l>)> {
+        labels
+            .iter()
+            .zip(logs)
+            .map(|(l, log)| (l, parse_exec_log(log).unwrap().0))
+            .collect()
+    }
+
+    fn labels(n: usize) -> Vec<String> {
+        (b'a'..).take(n).map(|l| (l as char).to_string()).collect()
+    }
+
+    /// Compares the actions for `artifact` in each of the `maps`.
+    fn compare<'l>(artifact: &'l str, maps: &'l [(&'l String, Map<'l>)]) -> MismatchSet<'l> {
+        find_mismatched(
+            artifact,
+            maps.iter().map(|(l, m)| (*l, &m[artifact])),
+            &IgnoredEnv::new(Vec::new()),
+        )
+    }
+
+    #[test]
+    fn short_hashes_still_tell_digests_apart() {
+        let logs = [
+            log(&[action("out", 'a', &[("in", '1')], &[])]),
+            log(&[action("out", 'b', &[("in", '2')], &[])]),
+        ];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+        let mismatches = compare("out", &maps);
+
+        let input = &mismatches.inputs[0];
+        let hashes: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|l| &input.value(l).unwrap().hash)
+            .collect();
+        assert_eq!(hashes[0].shortened(8), format!("{}…", "1".repeat(8)));
+        assert_eq!(hashes[1].shortened(8), format!("{}…", "2".repeat(8)));
+        assert_eq!(hashes[0].shortened(0), "1".repeat(64));
+        assert_eq!(hashes[0].shortened(100), "1".repeat(64));
+    }
+
+    #[test]
+    fn owned_mismatches_outlive_the_logs() {
+        let owned = {
+            let logs = [
+                log(&[action("out", 'a', &[("in", '1')], &[])]),
+                log(&[action("out", 'b', &[("in", '2')], &[])]),
+            ];
+            let labels = labels(2);
+            let maps = parse(&labels, &logs);
+            compare("out", &maps).into_owned()
+        };
+
+        assert_eq!(owned.inputs.len(), 1);
+        let input = &owned.inputs[0];
+        assert_eq!(
+            (input.artifact.as_str(), &input.key, input.class),
+            ("out", &PathBuf::from("in"), MismatchClass::Different)
+        );
+        assert_eq!(
+            input.value("b").unwrap().hash,
+            Hash::from_hex(&"2".repeat(64)).unwrap()
+        );
+        assert_eq!(owned.outputs.len(), 1);
+        assert!(owned.env_vars.is_empty() && owned.args.is_empty());
+    }
+
+    #[test]
+    fn owned_mismatches_match_borrowed_ones() {
+        let logs = [
+            log(&[action("out", 'a', &[("x", '1'), ("y", '2')], &[("V", "1")])]),
+            log(&[action("out", 'b', &[("x", '1'), ("z", '3')], &[("V", "2")])]),
+        ];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+        let ignored = IgnoredEnv::new(Vec::new());
+        let actions = || maps.iter().map(|(l, m)| (*l, &m["out"]));
+
+        let mut borrowed = find_mismatched("out", actions(), &ignored);
+        let mut owned = find_mismatched_owned("out", actions(), &ignored);
+        borrowed.sort();
+        owned.inputs.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(borrowed.into_owned(), owned);
+
+        let paths: Vec<_> = owned
+            .inputs
+            .iter()
+            .map(|m| m.key.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["y", "z"]);
+        assert_eq!(owned.env_vars[0].value("b").map(String::as_str), Some("2"));
+    }
+
+    #[test]
+    fn owned_transitive_cmp_outlives_the_logs() {
+        let owned = {
+            let logs = [
+                log(&[
+                    action("out", 'a', &[("mid", 'c')], &[]),
+                    action("mid", 'c', &[("src", '1')], &[]),
+                ]),
+                log(&[
+                    action("out", 'b', &[("mid", 'd')], &[]),
+                    action("mid", 'd', &[("src", '1')], &[]),
+                ]),
+            ];
+            let labels = labels(2);
+            let maps = parse(&labels, &logs);
+            let ignored = IgnoredEnv::new(Vec::new());
+            transitive_cmp("out", &maps, None, None, &ignored, false, None).into_owned()
+        };
+
+        let mut nodes = owned.graph.nodes.clone();
+        nodes.sort();
+        assert_eq!(nodes, [("mid".to_owned(), true), ("out".to_owned(), true)]);
+        // `mid`'s inputs match but its output doesn't:
+        assert_eq!(owned.origins.outputs.len(), 1);
+        assert_eq!(owned.origins.outputs[0].artifact, "mid");
+        assert_eq!(owned.graph.edges, [("out".to_owned(), "mid".to_owned())]);
+    }
+
+    #[test]
+    fn hashes_from_hex() {
+        let sha256 = Hash::from_hex(&"0f".repeat(32)).unwrap();
+        assert_eq!(sha256.as_bytes(), [0x0f; 32]);
+        // SHA-1:
+        let sha1 = Hash::from_hex("da39a3ee5e6b4b0d3255bfef95601890afd80709").unwrap();
+        assert_eq!(sha1.as_bytes().len(), 20);
+        assert_eq!(
+            format!("{:x}", sha1),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(Hash::from_hex("ABcd").unwrap().as_bytes(), [0xab, 0xcd]);
+
+        for bad in ["", "a", "abc", "+f", "0x", "-1", "zz", " a", "é0"] {
+            assert_eq!(Hash::from_hex(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn env_vars_listed_twice_count_once() {
+        let logs = [
+            log(&[action(
+                "out",
+                'a',
+                &[],
+                &[("V", "1"), ("V", "1"), ("W", "1")],
+            )]),
+            log(&[action("out", 'a', &[], &[("V", "1")])]),
+        ];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+
+        // `V` is in both actions (once in one and twice in the other) and so
+        // matches; `W` is only in one:
+        let env_vars = compare("out", &maps).env_vars;
+        assert_eq!(env_vars.len(), 1);
+        assert_eq!(
+            (env_vars[0].key, env_vars[0].class),
+            ("W", MismatchClass::Missing)
+        );
+
+        // Only the first entry counts, even if a later one has another value:
+        let logs = [
+            log(&[action("out", 'a', &[], &[("V", "1"), ("V", "2")])]),
+            log(&[action("out", 'a', &[], &[("V", "1")])]),
+        ];
+        let maps = parse(&labels, &logs);
+        assert!(compare("out", &maps).is_empty());
+    }
+
+    /// [`mismatched_keys`], one action (and entry) at a time.
+    fn mismatched_keys_sequentially<'l, K, V, I>(
+        actions: &[&'l ActionContext<'l>],
+        entries: impl Fn(&'l ActionContext<'l>) -> I,
+    ) -> Vec<(K, MismatchClass)>
+    where
+        K: Copy + Ord + std::hash::Hash,
+        V: Copy + Eq,
+        I: Iterator<Item = (K, V)>,
+    {
+        let mut tally: BTreeMap<K, (Option<V>, usize)> = BTreeMap::new();
+        for a in actions {
+            let mut seen = HashSet::new();
+            for (k, v) in entries(a).filter(|(k, _)| seen.insert(*k)) {
+                let (val, count) = tally.entry(k).or_insert((Some(v), 0));
+                if *val != Some(v) {
+                    *val = None;
+                }
+                *count += 1;
+            }
+        }
+
+        tally
+            .into_iter()
+            .filter_map(|(k, (v, c))| {
+                Some((k, MismatchClass::new(v.is_none(), c != actions.len())?))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_tally_matches_sequential_one() {
+        const HEX: &[u8] = b"0123456789abcdef";
+
+        // Enough logs and entries for the tally to be split up (across
+        // actions and within them), with entries that are missing from some
+        // logs, different in some, both, or listed twice (in different
+        // chunks):
+        let names: Vec<_> = (0..5_000).map(|i| format!("in/{:04}", i)).collect();
+        let logs: Vec<_> = (0..8)
+            .map(|l| {
+                let mut inputs: Vec<_> = names
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| (i * 7 + l) % 5 != 0)
+                    .map(|(i, n)| (n.as_str(), HEX[(i % 3 + l % 2 * (i % 2)) % 16] as char))
+                    .collect();
+                inputs.insert(0, ("in/dup", '1'));
+                inputs.push(("in/dup", HEX[l] as char));
+                let value = (l % 3).to_string();
+                let env = [("SAME", "1"), ("ODD", &value), ("SAME", &value)];
+                let env = if l == 3 { &env[..1] } else { &env[..] };
+                log(&[action("out", HEX[l % 2] as char, &inputs, env)])
+            })
+            .collect();
+        let labels = labels(logs.len());
+        let maps = parse(&labels, &logs);
+
+        let found = compare("out", &maps);
+        assert!(found.inputs.len() > 100);
+        assert_eq!(found.env_vars.len(), 1);
+        assert!(!found.inputs.iter().any(|m| m.key == Path::new("in/dup")));
+
+        let labelled: Vec<_> = maps
+            .iter()
+            .map(|(l, m)| (l.as_str(), &m["out"].0))
+            .collect();
+        let actions: Vec<_> = labelled.iter().map(|(_, a)| *a).collect();
+        let expected = MismatchSet {
+            env_vars: with_values(
+                "out",
+                mismatched_keys_sequentially(&actions, |a| {
+                    a.environment_variables.iter().map(|e| (e.name, e.value))
+                }),
+                &labelled,
+                |a| a.environment_variables.iter().map(|e| (e.name, e.value)),
+            ),
+            inputs: with_values(
+                "out",
+                mismatched_keys_sequentially(&actions, |a| {
+                    a.inputs.iter().map(|i| (i.path, &i.digest))
+                }),
+                &labelled,
+                |a| a.inputs.iter().map(|i| (i.path, &i.digest)),
+            ),
+            outputs: with_values(
+                "out",
+                mismatched_keys_sequentially(&actions, |a| {
+                    a.actual_outputs.iter().map(|o| (o.path, &o.digest))
+                }),
+                &labelled,
+                |a| a.actual_outputs.iter().map(|o| (o.path, &o.digest)),
+            ),
+            ..found.clone()
+        };
+
+        // Without sorting; the order should be the same too:
+        assert_eq!(found, expected);
+    }
+
+    /// How long comparing a big link (50k inputs, in 2 logs) takes, one entry
+    /// at a time and in parallel (on one thread and on all of them).
+    ///
+    /// Run with `cargo test --release --lib tally_benchmark -- --ignored
+    /// --nocapture`.
+    #[test]
+    #[ignore]
+    fn tally_benchmark() {
+        const RUNS: u32 = 20;
+
+        let logs: Vec<_> = (0..2)
+            .map(|l| {
+                let names: Vec<_> = (0..50_000).map(|i| format!("in/{:05}", i)).collect();
+                let inputs: Vec<_> = names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, n)| (n.as_str(), if i % 100 == 0 && l == 1 { '2' } else { '1' }))
+                    .collect();
+                log(&[action("out", 'a', &inputs, &[])])
+            })
+            .collect();
+        let labels = labels(logs.len());
+        let maps = parse(&labels, &logs);
+        let actions: Vec<_> = maps.iter().map(|(_, m)| &m["out"].0).collect();
+
+        let time = |f: &dyn Fn() -> usize| {
+            let start = std::time::Instant::now();
+            for _ in 0..RUNS {
+                assert_eq!(f(), 500);
+            }
+            start.elapsed() / RUNS
+        };
+        let sequential = time(&|| {
+            mismatched_keys_sequentially(&actions, |a| a.inputs.iter().map(|i| (i.path, &i.digest)))
+                .len()
+        });
+        let parallel =
+            || time(&|| mismatched_keys(&actions, |a| &a.inputs, |i| (i.path, &i.digest)).len());
+        let one_thread = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(parallel);
+        let all_threads = parallel();
+
+        println!(
+            "sequential: {:?}, parallel on 1 thread: {:?}, parallel on {} threads: {:?}",
+            sequential,
+            one_thread,
+            rayon::current_num_threads(),
+            all_threads
+        );
+    }
+
+    #[test]
+    fn streaming_parse_matches_buffered_one() {
+        let log = log(&[
+            action("x", 'a', &[("src", '1')], &[("V", "1")]),
+            action("y", 'b', &[("x", 'a')], &[]),
+            action("z", 'c', &[("y", 'b'), ("src", '1')], &[("V", "2")]),
+        ]);
+        let (buffered, _) = parse_exec_log(&log).unwrap();
+
+        // Objects (and strings in them) are split across reads:
+        let reader = std::io::BufReader::with_capacity(7, std::io::Cursor::new(&log));
+        let (streamed, _) =
+            exec_log_to_hashmap_streaming(reader, &ProgressBar::hidden(), None, &mut Vec::new())
+                .unwrap();
+
+        assert_eq!(streamed.len(), 3);
+        for (output, action) in &buffered {
+            assert_eq!(streamed[output], *action, "{}", output);
+        }
+    }
+
+    #[test]
+    fn braces_in_strings_dont_split_objects() {
+        let mut a = action("out", 'a', &[], &[("TEMPLATE", "}{}{")]);
+        a["commandArgs"] = json!(["echo", "-DFOO={}{}", "\"}{\"", "\\"]);
+        let log = log(&[a, action("other", 'b', &[], &[])]);
+
+        let (map, _) = parse_exec_log(&log).unwrap();
+        assert_eq!(map.len(), 2);
+        let out = &map["out"].0;
+        assert_eq!(out.environment_variables[0].value, "}{}{");
+        assert_eq!(out.command_args, ["echo", "-DFOO={}{}", "\"}{\"", "\\"]);
+
+        let (streamed, _) = exec_log_to_hashmap_streaming(
+            log.as_slice(),
+            &ProgressBar::hidden(),
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(streamed.len(), 2);
+    }
+
+    #[test]
+    fn differing_args_are_reported() {
+        let mut a = action("out", 'a', &[], &[]);
+        let mut b = a.clone();
+        a["commandArgs"] = json!(["cc", "-O2", "-c", "foo.c"]);
+        b["commandArgs"] = json!(["cc", "-O3", "-c", "foo.c"]);
+        let logs = [log(&[a]), log(&[b])];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+
+        let found = compare("out", &maps);
+        assert_eq!(found.args.len(), 1);
+        let arg = &found.args[0];
+        assert_eq!((arg.key, arg.class), (1, MismatchClass::Different));
+        assert_eq!((arg.value("a"), arg.value("b")), (Some("-O2"), Some("-O3")));
+        assert!(found.env_vars.is_empty() && found.inputs.is_empty());
+    }
+
+    #[test]
+    fn differing_platform_properties_are_reported() {
+        let mut a = action("out", 'a', &[], &[]);
+        let mut b = a.clone();
+        let mut c = a.clone();
+        a["platform"] = json!({ "properties": [
+            { "name": "OSFamily", "value": "Linux" },
+            { "name": "container-image", "value": "docker://img" },
+        ]});
+        b["platform"] = json!({ "properties": [
+            { "name": "OSFamily", "value": "Windows" },
+            { "name": "container-image", "value": "docker://img" },
+        ]});
+        c["platform"] = json!({ "properties": [
+            { "name": "OSFamily", "value": "Linux" },
+        ]});
+        let logs = [log(&[a]), log(&[b]), log(&[c])];
+        let labels = labels(3);
+        let maps = parse(&labels, &logs);
+
+        let mut found = compare("out", &maps);
+        found.sort();
+        let platform: Vec<_> = found
+            .platform
+            .iter()
+            .map(|m| (m.key, m.class, m.value("a"), m.value("b"), m.value("c")))
+            .collect();
+        assert_eq!(
+            platform,
+            [
+                (
+                    "OSFamily",
+                    MismatchClass::Different,
+                    Some("Linux"),
+                    Some("Windows"),
+                    Some("Linux")
+                ),
+                (
+                    "container-image",
+                    MismatchClass::Missing,
+                    Some("docker://img"),
+                    Some("docker://img"),
+                    None
+                ),
+            ]
+        );
+        assert!(!found.is_empty());
+    }
+
+    #[test]
+    fn differing_exit_codes_are_reported() {
+        let a = action("out", 'a', &[], &[]);
+        let mut b = a.clone();
+        b["exitCode"] = json!(137);
+        b["status"] = json!("NON_ZERO_EXIT");
+        let logs = [log(&[a]), log(&[b])];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+
+        let found = compare("out", &maps);
+        assert_eq!(found.exit_status.len(), 1);
+        let exit = &found.exit_status[0];
+        assert_eq!(exit.value("a"), Some((0, "")));
+        assert_eq!(exit.value("b"), Some((137, "NON_ZERO_EXIT")));
+        assert!(!found.is_empty());
+    }
+
+    #[test]
+    fn divergence_graph_as_dot() {
+        let logs = [
+            log(&[
+                action("out", '1', &[("x.o", '2'), ("y.o", '3')], &[]),
+                action("x.o", '2', &[("x.c", '4')], &[]),
+                action("y.o", '3', &[("y.c", '5')], &[]),
+            ]),
+            log(&[
+                action("out", '6', &[("x.o", '7'), ("y.o", '3')], &[]),
+                action("x.o", '7', &[("x.c", '8')], &[]),
+                action("y.o", '3', &[("y.c", '5')], &[]),
+            ]),
+        ];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+        let ignored = IgnoredEnv::new(Vec::new());
+        let graph = transitive_cmp("out", &maps, None, None, &ignored, false, None).graph;
+
+        let mut dot = Vec::new();
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        let lines: Vec<_> = dot.lines().map(str::trim).collect();
+        assert_eq!((lines[0], *lines.last().unwrap()), ("digraph tcmp {", "}"));
+
+        // `y.o` matches so it isn't visited; `x.c` is a source file:
+        let nodes: Vec<_> = lines.iter().filter(|l| l.ends_with("];")).collect();
+        assert_eq!(nodes.len(), 3);
+        assert!(nodes.contains(&&r#""out" [color=red];"#));
+        assert!(nodes.contains(&&r#""x.o" [color=red];"#));
+        assert!(nodes.contains(&&r#""x.c" [color=red, shape=box];"#));
+        let mut edges: Vec<_> = lines.iter().filter(|l| l.contains("->")).collect();
+        edges.sort();
+        assert_eq!(edges, [&r#""out" -> "x.o";"#, &r#""x.o" -> "x.c";"#]);
+    }
+
+    #[test]
+    fn traversal_stops_at_the_depth_limit() {
+        // `l0` <- `l1` <- ... <- `l4` <- `src`, all different in the second
+        // log:
+        let chain = |hash: char, src: char| {
+            let levels = ["l0", "l1", "l2", "l3", "l4", "src"];
+            let actions: Vec<_> = levels
+                .windows(2)
+                .map(|w| {
+                    let input = if w[1] == "src" { src } else { hash };
+                    action(w[0], hash, &[(w[1], input)], &[])
+                })
+                .collect();
+            log(&actions)
+        };
+        let logs = [chain('a', '1'), chain('b', '2')];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+        let ignored = IgnoredEnv::new(Vec::new());
+
+        let graph = transitive_cmp("l0", &maps, None, Some(2), &ignored, false, None).graph;
+        let mut nodes: Vec<_> = graph.nodes.iter().map(|(n, _)| *n).collect();
+        nodes.sort_unstable();
+        assert_eq!(nodes, ["l0", "l1", "l2"]);
+        assert_eq!(graph.truncated, ["l2"]);
+
+        let graph = transitive_cmp("l0", &maps, None, None, &ignored, false, None).graph;
+        assert_eq!(graph.nodes.len(), 5);
+        assert!(graph.truncated.is_empty());
+    }
+
+    #[test]
+    fn traversal_asks_before_going_too_far() {
+        let chain = |hash: char, src: char| {
+            let levels = ["l0", "l1", "l2", "l3", "l4", "src"];
+            let actions: Vec<_> = levels
+                .windows(2)
+                .map(|w| {
+                    let input = if w[1] == "src" { src } else { hash };
+                    action(w[0], hash, &[(w[1], input)], &[])
+                })
+                .collect();
+            log(&actions)
+        };
+        let logs = [chain('a', '1'), chain('b', '2')];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+        let ignored = IgnoredEnv::new(Vec::new());
+
+        for carry_on in [false, true] {
+            let asked = Mutex::new(Vec::new());
+            let confirm = |n| {
+                asked.lock().unwrap().push(n);
+                carry_on
+            };
+            let graph = transitive_cmp(
+                "l0",
+                &maps,
+                None,
+                None,
+                &ignored,
+                false,
+                Some((2, &confirm)),
+            )
+            .graph;
+            assert_eq!(asked.into_inner().unwrap(), [2]);
+            assert_eq!(graph.nodes.len(), if carry_on { 5 } else { 2 });
+        }
+    }
+
+    #[test]
+    fn normalized_paths_line_up_across_configs() {
+        assert_eq!(
+            normalize_path("-Ibazel-out/k8-opt/bin -Ibazel-out/stable-status.txt"),
+            "-Ibazel-out/<config>/bin -Ibazel-out/stable-status.txt"
+        );
+
+        let built_in = |config: &str| {
+            let path = |p: &str| format!("bazel-out/{}/bin/{}", config, p);
+            log(&[action(&path("out"), 'a', &[(&path("gen.h"), '1')], &[])])
+        };
+        let logs = [built_in("k8-fastbuild-ST-abc123"), built_in("k8-opt")];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+
+        // Each log has its own outputs (and inputs):
+        let out = "bazel-out/k8-opt/bin/out";
+        assert!(!maps[0].1.contains_key(out) && maps[1].1.contains_key(out));
+
+        let mut strings = Interner::default();
+        let maps: Vec<_> = maps
+            .into_iter()
+            .map(|(l, m)| (l, normalize_paths(m, Producers::new(), &mut strings).0))
+            .collect();
+        let out = "bazel-out/<config>/bin/out";
+        assert!(maps.iter().all(|(_, m)| m.contains_key(out)));
+        let found = compare(out, &maps);
+        assert!(found.is_empty(), "{:?}", found);
+        assert_eq!(
+            maps[1].1[out].0.inputs[0].path,
+            Path::new("bazel-out/<config>/bin/gen.h")
+        );
+    }
+
+    #[test]
+    fn mismatches_are_classified() {
+        let logs = [
+            log(&[action(
+                "out",
+                'a',
+                &[("same", '1'), ("changed", '2'), ("dropped", '3')],
+                &[("SAME", "1"), ("CHANGED", "1"), ("DROPPED", "1")],
+            )]),
+            log(&[action(
+                "out",
+                'b',
+                &[("same", '1'), ("changed", '4'), ("added", '5')],
+                &[("SAME", "1"), ("CHANGED", "2"), ("ADDED", "1")],
+            )]),
+        ];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+        let mut found = compare("out", &maps);
+        found.sort();
+
+        let env: Vec<_> = found.env_vars.iter().map(|m| (m.key, m.class)).collect();
+        assert_eq!(
+            env,
+            [
+                ("ADDED", MismatchClass::Missing),
+                ("CHANGED", MismatchClass::Different),
+                ("DROPPED", MismatchClass::Missing),
+            ]
+        );
+        let changed = &found.env_vars[1];
+        assert_eq!(changed.artifact, "out");
+        assert_eq!(
+            (changed.value("a"), changed.value("b")),
+            (Some("1"), Some("2"))
+        );
+        assert_eq!(found.env_vars[0].value("a"), None);
+
+        let inputs: Vec<_> = found
+            .inputs
+            .iter()
+            .map(|m| (m.key.to_str().unwrap(), m.class))
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                ("added", MismatchClass::Missing),
+                ("changed", MismatchClass::Different),
+                ("dropped", MismatchClass::Missing),
+            ]
+        );
+        assert_eq!(
+            found.inputs[1].value("b").unwrap().hash,
+            Hash::from_hex(&"4".repeat(64)).unwrap()
+        );
+
+        assert_eq!(found.outputs.len(), 1);
+        assert_eq!(found.outputs[0].class, MismatchClass::Different);
+        assert!(found.args.is_empty() && found.platform.is_empty());
+        assert!(found.exit_status.is_empty() && found.runner.is_empty());
+    }
+
+    #[test]
+    fn sorted_mismatches_are_stable() {
+        let names: Vec<_> = (0..32).map(|i| format!("V{}", i)).collect();
+        let env = |v| names.iter().map(|n| (n.as_str(), v)).collect::<Vec<_>>();
+        let inputs = |h| names.iter().map(|n| (n.as_str(), h)).collect::<Vec<_>>();
+        let logs = [
+            log(&[action("out", 'a', &inputs('1'), &env("1"))]),
+            log(&[action("out", 'b', &inputs('2'), &env("2"))]),
+        ];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+
+        let sorted = || {
+            let mut found = compare("out", &maps);
+            found.sort();
+            found
+        };
+        let first = sorted();
+        let keys: Vec<_> = first.env_vars.iter().map(|m| m.key).collect();
+        let mut expected: Vec<_> = names.iter().map(String::as_str).collect();
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+        let paths: Vec<_> = first
+            .inputs
+            .iter()
+            .map(|m| m.key.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, expected);
+
+        for _ in 0..8 {
+            assert_eq!(sorted(), first);
+        }
+    }
+
+    #[test]
+    fn divergence_starts_at_the_deepest_differing_output() {
+        // Only `deep` (whose inputs match) isn't reproducible; `mid` and `out`
+        // just inherit the difference:
+        let logs = [
+            log(&[
+                action("out", 'a', &[("mid", 'c'), ("other", '5')], &[]),
+                action("mid", 'c', &[("deep", 'e')], &[]),
+                action("deep", 'e', &[("src", '1')], &[]),
+                action("other", '5', &[("src", '1')], &[]),
+            ]),
+            log(&[
+                action("out", 'b', &[("mid", 'd'), ("other", '5')], &[]),
+                action("mid", 'd', &[("deep", 'f')], &[]),
+                action("deep", 'f', &[("src", '1')], &[]),
+                action("other", '5', &[("src", '1')], &[]),
+            ]),
+        ];
+        let labels = labels(2);
+        let maps = parse(&labels, &logs);
+        let ignored = IgnoredEnv::new(Vec::new());
+        let found = transitive_cmp("out", &maps, None, None, &ignored, false, None);
+
+        let origins: BTreeSet<_> = found.origins.outputs.iter().map(|m| m.artifact).collect();
+        assert_eq!(origins, BTreeSet::from(["deep"]));
+        assert!(found.origins.inputs.is_empty());
+        let mut nodes = found.graph.nodes.clone();
+        nodes.sort_unstable();
+        assert_eq!(nodes, [("deep", true), ("mid", true), ("out", true)]);
+    }
+
+    #[test]
+    fn differing_runners_are_reported() {
+        let mut a = action("out", 'a', &[], &[]);
+        a["runner"] = json!("remote cache hit");
+        a["remoteCacheHit"] = json!(true);
+        let mut b = a.clone();
+        b["runner"] = json!("linux-sandbox");
+        b["remoteCacheHit"] = json!(false);
+        let logs = [log(&[a.clone()]), log(&[b]), log(&[a])];
+        let labels = labels(3);
+        let maps = parse(&labels, &logs);
+
+        let found = compare("out", &maps);
+        assert_eq!(found.runner.len(), 1);
+        let runner = &found.runner[0];
+        assert_eq!(runner.value("a"), Some(("remote cache hit", true)));
+        assert_eq!(runner.value("b"), Some(("linux-sandbox", false)));
+        // Where the actions ran doesn't make them differ by itself:
+        assert!(found.is_empty());
+
+        let found = compare("out", &maps[..1]);
+        assert!(found.runner.is_empty());
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let mut log = log(&[
+            action("x", 'a', &[], &[]),
+            action("y", 'b', &[], &[("V", "caf\u{e9}")]),
+        ]);
+        // `é` in Latin-1:
+        let at = log
+            .windows(2)
+            .position(|w| w == "\u{e9}".as_bytes())
+            .unwrap();
+        log.splice(at..at + 2, [0xe9]);
+
+        let mut invalid = Vec::new();
+        let (buffered, _) =
+            exec_log_to_hashmap(&log, &ProgressBar::hidden(), None, &mut invalid).unwrap();
+        assert_eq!(invalid, [at]);
+        assert_eq!(buffered.len(), 2);
+        let env = &buffered["y"].0.environment_variables[0];
+        assert_eq!(env.value, "caf\u{fffd}");
+
+        let mut invalid = Vec::new();
+        let reader = std::io::BufReader::with_capacity(7, std::io::Cursor::new(&log));
+        let (streamed, _) =
+            exec_log_to_hashmap_streaming(reader, &ProgressBar::hidden(), None, &mut invalid)
+                .unwrap();
+        assert_eq!(invalid, [at]);
+        assert_eq!(streamed["y"], buffered["y"]);
+    }
+
+    #[test]
+    fn parse_errors_say_where_they_are() {
+        let first = log(&[action("x", 'a', &[], &[])]);
+        let second = String::from_utf8(log(&[action("y", 'b', &[], &[])]))
+            .unwrap()
+            .replacen("true", "tru", 1);
+        let mut bad = first.clone();
+        bad.extend(second.as_bytes());
+        bad.extend(log(&[action("z", 'c', &[], &[])]));
+
+        let (start, end) = (first.len(), first.len() + second.len() - 1);
+        let at = start + second.find("tru").unwrap();
+        let check = |e: eyre::Report| {
+            let msg = format!("{:#}", e);
+            assert!(msg.contains("object 1 "), "{}", msg);
+            assert!(
+                msg.contains(&format!("(bytes {}..={})", start, end)),
+                "{}",
+                msg
+            );
+            let near = |b: usize| msg.contains(&format!("at byte {},", b));
+            assert!((at..=at + "tru,".len()).any(near), "{}", msg);
+            assert!(msg.contains("\"cacheable\": tru,"), "{}", msg);
+        };
+
+        check(parse_exec_log(&bad).unwrap_err());
+        let reader = std::io::BufReader::with_capacity(7, std::io::Cursor::new(&bad));
+        check(
+            exec_log_to_hashmap_streaming(reader, &ProgressBar::hidden(), None, &mut Vec::new())
+                .unwrap_err(),
+        );
+    }
+}
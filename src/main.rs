@@ -1,163 +1,314 @@
-#![doc(
-    html_root_url = "https://docs.rs/bazel-execlog-cmp/0.1.1", // remember to bump!
-)]
-
-use std::collections::{HashMap, HashSet};
-use std::env::args_os;
-use std::fmt::{self, Debug};
-use std::fs::read_to_string;
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{read_to_string, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
 use std::mem::forget;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, SystemTime};
 
-use color_eyre::eyre::{self, eyre};
-use color_eyre::owo_colors::OwoColorize;
+use bazel_execlog_cmp::{
+    binary_log_to_hashmap, cancel, cancelled, compact_log_to_hashmap, exec_log_to_hashmap,
+    exec_log_to_hashmap_streaming, find_mismatched, normalize_path, normalize_paths,
+    reset_cancelled, set_hash_len, transitive_cmp, ActionContext, ArtifactName, BuildAction,
+    Digest, Hash, IgnoredEnv, Interner, Map, Mismatch, MismatchClass, MismatchSet, Producers,
+    TransitiveCmp,
+};
+use color_eyre::eyre::{self, WrapErr};
+use flate2::read::MultiGzDecoder;
 use fuzzy_matcher::{clangd::ClangdMatcher, FuzzyMatcher};
-use indicatif::{MultiProgress, ProgressBar, ProgressIterator, ProgressStyle};
+use glob::Pattern;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use prettydiff::basic::DiffOp;
 use rayon::prelude::*;
+use regex::RegexBuilder;
 use rustyline::{
     completion::{Candidate, Completer},
     highlight::Highlighter,
     hint::Hinter,
-    validate::Validator,
+    history::History,
+    validate::{ValidationContext, ValidationResult, Validator},
     CompletionType, Config, Editor, Helper,
 };
 use serde::{Deserialize, Serialize};
-use serde_aux::field_attributes::deserialize_number_from_string;
-use serde_json::de::from_slice;
+use structopt::StructOpt;
+use terminal_size::Width;
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-struct Sha256(#[serde(with = "hex_serde")] [u8; 32]);
+mod cache;
+mod color;
+mod pager;
 
-impl Debug for Sha256 {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for x in &self.0 {
-            write!(fmt, "{:02x}", x)?;
+use color::{ColorChoice, Colorize};
+use pager::terminal_size;
+
+/// Splits a command line into words like a (very) minimal shell would: words
+/// are separated by spaces, double quotes make spaces part of a word, and a
+/// backslash escapes the next character.
+///
+/// Each word comes with the span of `line` it was read from. A missing closing
+/// quote (or a trailing backslash) is tolerated so that partial lines can be
+/// completed; the returned `bool` reports it.
+fn split_args(line: &str) -> (Vec<(Range<usize>, String)>, bool) {
+    let mut words = Vec::new();
+    let mut word: Option<(Range<usize>, String)> = None;
+    let (mut quoted, mut escaped) = (false, false);
+
+    for (i, c) in line.char_indices() {
+        if c == ' ' && !quoted && !escaped {
+            words.extend(word.take());
+            continue;
         }
 
-        Ok(())
+        let (span, w) = word.get_or_insert_with(|| (i..i, String::new()));
+        span.end = i + c.len_utf8();
+        match c {
+            _ if escaped => {
+                w.push(c);
+                escaped = false;
+            }
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c => w.push(c),
+        }
     }
-}
+    words.extend(word);
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-struct Digest<'i> {
-    hash: Sha256,
-    #[serde(
-        rename = "sizeBytes",
-        deserialize_with = "deserialize_number_from_string"
-    )]
-    size_bytes: usize,
-    #[serde(rename = "hashFunctionName")]
-    hash_function_name: &'i str,
+    (words, quoted || escaped)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-struct Item<'i> {
-    #[serde(borrow)]
-    path: &'i Path,
-    digest: Digest<'i>,
+/// Quotes `arg` if it needs to be for [`split_args`] to read it as one word.
+fn quote_arg(arg: &str) -> Cow<'_, str> {
+    if arg.contains(&[' ', '"', '\\'][..]) {
+        let escaped = arg.replace('\\', "\\\\").replace('"', "\\\"");
+        Cow::Owned(format!("\"{}\"", escaped))
+    } else {
+        Cow::Borrowed(arg)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-struct EnvVar<'i> {
-    name: &'i str,
-    value: &'i str,
+struct ExecLogHelper<'l> {
+    map: &'l [(&'l String, Map<'l>)],
+    output_paths: OnceCell<Vec<&'l str>>,
+    input_paths: OnceCell<Vec<&'l str>>,
+    mnemonics: OnceCell<Vec<&'l str>>,
+    env_vars: OnceCell<Vec<&'l str>>,
+    fuzzy_matcher: ClangdMatcher,
+    /// Whether paths are completed by fuzzy matching (instead of by prefix).
+    fuzzy: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-struct ActionContext<'i> {
-    #[serde(rename = "environmentVariables", borrow)]
-    environment_variables: Vec<EnvVar<'i>>,
-    inputs: Vec<Item<'i>>,
-    #[serde(rename = "listedOutputs", borrow)]
-    listed_outputs: Vec<&'i str>,
-    remotable: bool,
-    cacheable: bool,
-    #[serde(rename = "actualOutputs", borrow)]
-    actual_outputs: Vec<Item<'i>>,
-}
+impl<'l> ExecLogHelper<'l> {
+    fn new(map: &'l [(&'l String, Map<'l>)], fuzzy: bool) -> Self {
+        Self {
+            map,
+            output_paths: OnceCell::new(),
+            input_paths: OnceCell::new(),
+            mnemonics: OnceCell::new(),
+            env_vars: OnceCell::new(),
+            fuzzy_matcher: ClangdMatcher::default().smart_case().use_cache(true),
+            fuzzy,
+        }
+    }
 
-type Output<'i> = &'i str;
-#[cfg(feature = "json-dump-command")]
-type BuildAction<'i> = (ActionContext<'i>, serde_json::Value);
-#[cfg(not(feature = "json-dump-command"))]
-type BuildAction<'i> = (ActionContext<'i>,);
+    /// The (deduplicated) output paths in every execution log; an output that
+    /// only some of the logs have can still be completed.
+    fn output_paths(&self) -> &[&'l str] {
+        self.output_paths.get_or_init(|| {
+            self.map
+                .iter()
+                .flat_map(|(_, m)| m.keys().copied())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect()
+        })
+    }
 
-type Map<'l> = HashMap<Output<'l>, Arc<BuildAction<'l>>>;
+    /// The (deduplicated) input paths of every action in every execution log.
+    ///
+    /// This is built on first use; most sessions never complete an input path.
+    fn input_paths(&self) -> &[&'l str] {
+        self.input_paths.get_or_init(|| {
+            self.map
+                .iter()
+                .flat_map(|(_, m)| m.values())
+                .flat_map(|a| a.0.inputs.iter())
+                .map(|i| i.path.to_str().unwrap())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect()
+        })
+    }
 
-/// Execution logs are tricky since they're composed of concatenated JSON objects.
-///
-/// As in:
-/// ```json
-/// { "foo": true, bar: 8, ... }{ "foo": false, bar: 12, ... }
-/// ```
-fn exec_log_to_hashmap<'l>(
-    log: &'l [u8],
-    pb: &ProgressBar,
-) -> eyre::Result<(Map<'l>, HashSet<&'l str>)> {
-    let mut prev = 0;
-    let mut curr = 0;
-    let mut map = HashMap::new();
-
-    let mut outputs_with_multiple_actions = HashSet::new();
-
-    let mut process_obj = |j| -> eyre::Result<()> {
-        #[cfg(feature = "json-dump-command")]
-        let val = from_slice(j)?;
-        let ctx: ActionContext = from_slice(j)?;
+    /// The (deduplicated) mnemonics of the actions in every execution log.
+    fn mnemonics(&self) -> &[&'l str] {
+        self.mnemonics.get_or_init(|| {
+            let mut mnemonics: Vec<_> = self
+                .map
+                .iter()
+                .flat_map(|(_, m)| m.values())
+                .map(|a| a.0.mnemonic)
+                .filter(|m| !m.is_empty())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            mnemonics.sort_unstable();
+            mnemonics
+        })
+    }
 
-        let action = Arc::new((
-            ctx,
-            #[cfg(feature = "json-dump-command")]
-            val,
-        ));
-        for output in action.0.listed_outputs.iter() {
-            if map.insert(*output, action.clone()).is_some() {
-                outputs_with_multiple_actions.insert(*output);
+    /// The (deduplicated) names of the env vars of the actions in every
+    /// execution log.
+    fn env_vars(&self) -> &[&'l str] {
+        self.env_vars.get_or_init(|| {
+            let mut names: Vec<_> = self
+                .map
+                .iter()
+                .flat_map(|(_, m)| m.values())
+                .flat_map(|a| a.0.environment_variables.iter())
+                .map(|e| e.name)
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            names.sort_unstable();
+            names
+        })
+    }
+
+    /// The paths (or, for `mnemonic` and `ignore env`, the mnemonics and env
+    /// var names) that argument `arg` (counting from 1) to `command` can be
+    /// (or `None` if the command doesn't take a path).
+    fn paths_for(
+        &self,
+        command: &str,
+        arg: usize,
+    ) -> Option<Box<dyn Iterator<Item = &'l str> + '_>> {
+        if command == "mnemonic" {
+            Some(Box::new(self.mnemonics().iter().copied()))
+        } else if command == "ignore" {
+            match arg {
+                1 => Some(Box::new(std::iter::once("env"))),
+                2 => Some(Box::new(self.env_vars().iter().copied())),
+                _ => None,
             }
+        } else if Self::takes_input_path(command, arg) {
+            Some(Box::new(self.input_paths().iter().copied()))
+        } else if Self::takes_output_path(command, arg) {
+            Some(Box::new(self.output_paths().iter().copied()))
+        } else {
+            None
         }
+    }
 
-        Ok(())
-    };
+    /// Whether argument `arg` to `command` is an input path.
+    fn takes_input_path(command: &str, arg: usize) -> bool {
+        type C<'l> = ExecLogCompletionCandidate<'l>;
 
-    while curr + 1 < log.len() {
-        if let b"}{" = &log[curr..][..2] {
-            process_obj(&log[prev..=curr])?;
+        C::INPUT_COMMANDS.contains(&command) || (command == "path" && arg == 2)
+    }
 
-            prev = curr + 1;
-        }
+    /// Whether argument `arg` to `command` is an output path.
+    fn takes_output_path(command: &str, arg: usize) -> bool {
+        type C<'l> = ExecLogCompletionCandidate<'l>;
 
-        curr += 1;
-        if curr % 10_000 == 0 {
-            pb.inc(10_000);
-        }
+        command != "mnemonic"
+            && !(command == "verify" && arg == 2)
+            && !Self::takes_input_path(command, arg)
+            && C::COMMANDS.contains(&command)
+            && !C::NO_PATH_COMMANDS.contains(&command)
     }
-    process_obj(&log[prev..])?;
 
-    pb.finish();
+    /// Marks `display` (a suggestion for `output`) with the number of
+    /// execution logs that have the output, if some of them don't.
+    fn annotate(&self, output: &str, display: String) -> String {
+        let count = self
+            .map
+            .iter()
+            .filter(|(_, m)| m.contains_key(output))
+            .count();
+        if count == self.map.len() {
+            display
+        } else {
+            let note = format!("(in {}/{} logs)", count, self.map.len());
+            format!("{} {}", display, note.dimmed())
+        }
+    }
 
-    Ok((map, outputs_with_multiple_actions))
-}
+    /// Swaps out a fuzzy query argument (`<command> ~<query>`) for the path it
+    /// matches.
+    ///
+    /// Lines without a fuzzy query are returned as is. If the query doesn't
+    /// match exactly one path, the candidates are printed and `None` is
+    /// returned.
+    fn resolve_fuzzy_query(&self, line: String) -> Option<String> {
+        let (command, query, rest) = match line.split_once(" ~") {
+            Some((command, arg)) if !command.contains(' ') => {
+                let (query, rest) = arg.split_once(' ').unwrap_or((arg, ""));
+                (command, query, rest)
+            }
+            _ => return Some(line),
+        };
+        let paths = match self.paths_for(command, 1) {
+            Some(p) => p,
+            None => return Some(line),
+        };
 
-struct ExecLogHelper<'l> {
-    map: &'l [(&'l String, Map<'l>)],
-    fuzzy_matcher: ClangdMatcher,
-}
+        let mut matches: Vec<_> = paths
+            .filter_map(|p| self.fuzzy_matcher.fuzzy_match(p, query).map(|s| (s, p)))
+            .collect();
+        if let Some((_, exact)) = matches.iter().find(|(_, p)| *p == query) {
+            matches = vec![(0, *exact)];
+        }
 
-impl<'l> ExecLogHelper<'l> {
-    fn new(map: &'l [(&'l String, Map<'l>)]) -> Self {
-        Self {
-            map,
-            fuzzy_matcher: ClangdMatcher::default().smart_case().use_cache(true),
+        match matches.len() {
+            0 => eprintln!("`{}` didn't match anything", query),
+            1 => {
+                let path = matches[0].1;
+                println!("{} {}", "~>".dimmed(), path.blue());
+                let path = quote_arg(path);
+                return Some(if rest.is_empty() {
+                    format!("{} {}", command, path)
+                } else {
+                    format!("{} {} {}", command, path, rest)
+                });
+            }
+            n => {
+                matches.sort_by_key(|(score, p)| (std::cmp::Reverse(*score), *p));
+                eprintln!("`{}` matched {} paths; please be more specific:", query, n);
+                for (_, p) in matches.iter().take(10) {
+                    eprintln!("  - {}", p);
+                }
+                if n > 10 {
+                    eprintln!("  ...and {} more", n - 10);
+                }
+            }
         }
+
+        None
     }
 }
 
 impl<'l> Helper for ExecLogHelper<'l> {}
 
-impl<'l> Validator for ExecLogHelper<'l> {}
+impl<'l> Validator for ExecLogHelper<'l> {
+    fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
+        Ok(validate_line(ctx.input()))
+    }
+}
+
+/// Lines with an unterminated quote (or a trailing backslash) carry on onto
+/// the next line instead of being run; see [`repl`].
+fn validate_line(line: &str) -> ValidationResult {
+    match split_args(line) {
+        (_, true) => ValidationResult::Incomplete,
+        (_, false) => ValidationResult::Valid(None),
+    }
+}
 
 impl<'l> Highlighter for ExecLogHelper<'l> {}
 
@@ -167,7 +318,8 @@ impl<'l> Hinter for ExecLogHelper<'l> {
 
 enum ExecLogCompletionCandidate<'l> {
     CommandCompletion(&'static str),
-    OutputSuggestion(String, &'l str),
+    /// The text shown and the (quoted, if necessary) path that is inserted.
+    PathSuggestion(String, Cow<'l, str>),
 }
 
 impl<'l> ExecLogCompletionCandidate<'l> {
@@ -181,22 +333,74 @@ impl<'l> ExecLogCompletionCandidate<'l> {
         #[cfg(feature = "json-dump-command")]
         "json",
         "view",
+        "info",
+        "timing",
         "diff",
+        "manifest-diff",
+        "logs",
+        "stats",
+        "intra-cmp",
+        "replay",
+        "mnemonic",
+        "list",
+        "summary",
+        "grep",
+        "rdeps",
+        "path",
+        "ignore",
+        "verify",
+        "baseline",
+        "reproducible",
+        "nonreproducible",
+        "dupes",
+        "dup-outputs",
+        "find-hash",
+        "export-md",
+        "reload",
+        "load",
+        "label",
+    ];
+
+    /// Commands that don't take a path from the execution logs (and so don't
+    /// get completions).
+    const NO_PATH_COMMANDS: &'static [&'static str] = &[
+        "quit",
+        "help",
+        "manifest-diff",
+        "logs",
+        "stats",
+        "replay",
+        "summary",
+        "grep",
+        "ignore",
+        "baseline",
+        "reproducible",
+        "nonreproducible",
+        "dupes",
+        "dup-outputs",
+        "find-hash",
+        "export-md",
+        "reload",
+        "load",
+        "label",
     ];
+
+    /// Commands whose argument is an input path rather than an output path.
+    const INPUT_COMMANDS: &'static [&'static str] = &["rdeps"];
 }
 
 impl<'l> Candidate for ExecLogCompletionCandidate<'l> {
     fn display(&self) -> &str {
         match self {
             Self::CommandCompletion(c) => c,
-            Self::OutputSuggestion(p, _) => p,
+            Self::PathSuggestion(p, _) => p,
         }
     }
 
     fn replacement(&self) -> &str {
         match self {
             Self::CommandCompletion(c) => c,
-            Self::OutputSuggestion(_, p) => p,
+            Self::PathSuggestion(_, p) => p,
         }
     }
 }
@@ -210,6 +414,9 @@ impl<'l> Completer for ExecLogHelper<'l> {
         _pos: usize,
         _ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
+        /// How many paths are suggested at most.
+        const MAX_CANDIDATES: usize = 50;
+
         if !line.contains(' ') {
             let mut v = Vec::new();
             for c in Self::Candidate::COMMANDS {
@@ -219,22 +426,65 @@ impl<'l> Completer for ExecLogHelper<'l> {
             }
 
             Ok((0, v))
-        } else if Self::Candidate::COMMANDS.contains(&line.split(' ').next().unwrap())
-            && !(line.starts_with("quit ") || line.starts_with('q') || line.starts_with("help"))
-        {
-            let path = line.split_once(" ").map(|(_, p)| p).unwrap_or("");
-            let idx = line.find(' ').unwrap() + 1;
+        } else {
+            let (words, _) = split_args(line);
+            let command = words.first().map(|(_, c)| c.as_str()).unwrap_or("");
 
-            let mut matches: Vec<_> = self.map[0]
-                .1
-                .keys()
-                .filter_map(|k| {
+            // Complete the last argument (commands like `intra-cmp` take more
+            // than one path), unless the line ends with a new, empty one:
+            let (arg, idx, path) = match words.last() {
+                Some((span, w)) if words.len() > 1 && span.end == line.len() => {
+                    (words.len() - 1, span.start, w.as_str())
+                }
+                _ => (words.len(), line.len(), ""),
+            };
+
+            let keys = match self.paths_for(command, arg) {
+                Some(k) => k,
+                None => return Ok((0, vec![])),
+            };
+            let suggestion = |display, k| {
+                let display = if Self::takes_output_path(command, arg) {
+                    self.annotate(k, display)
+                } else {
+                    display
+                };
+                ExecLogCompletionCandidate::PathSuggestion(display, quote_arg(k))
+            };
+
+            // Prefix matching is much cheaper than fuzzy matching (and lets
+            // rustyline fill in the common prefix) which matters over slow
+            // connections:
+            if !self.fuzzy {
+                let mut matches: Vec<_> = keys.filter(|k| k.starts_with(path)).collect();
+                matches.sort_unstable();
+                matches.truncate(MAX_CANDIDATES);
+
+                return Ok((
+                    idx,
+                    matches
+                        .into_iter()
+                        .map(|k| suggestion(k.to_owned(), k))
+                        .collect(),
+                ));
+            }
+
+            // Only the best matches are kept so they have to be ranked first;
+            // the (more expensive) match positions are only needed for those:
+            let mut matches: Vec<_> = keys
+                .filter_map(|k| self.fuzzy_matcher.fuzzy_match(k, path).map(|s| (s, k)))
+                .collect();
+            matches.sort_unstable_by_key(|(score, k)| (std::cmp::Reverse(*score), *k));
+            matches.truncate(MAX_CANDIDATES);
+
+            let matches = matches
+                .into_iter()
+                .filter_map(|(_, k)| {
                     self.fuzzy_matcher
-                        .fuzzy_indices(*k, path)
-                        .map(|res| (res, *k))
+                        .fuzzy_indices(k, path)
+                        .map(|res| (res, k))
                 })
-                .take(50)
-                .map(|((score, indices), k)| {
+                .map(|((_, indices), k)| {
                     let mut s = String::new();
                     let mut curr_idx = 0;
 
@@ -247,139 +497,791 @@ impl<'l> Completer for ExecLogHelper<'l> {
                         }
                     }
 
-                    (score, ExecLogCompletionCandidate::OutputSuggestion(s, k))
+                    suggestion(s, k)
                 })
                 .collect();
-            matches.sort_by_key(|(score, _)| *score);
 
-            Ok((idx, matches.into_iter().map(|(_, o)| o).collect()))
-        } else {
-            Ok((0, vec![]))
+            Ok((idx, matches))
         }
     }
 }
 
-type ArtifactName<'l> = &'l str;
+/// Options that control how mismatches are printed.
+#[derive(Debug, Clone, Copy)]
+struct PrintOpts {
+    /// Don't shorten long paths and values to fit the terminal.
+    no_wrap: bool,
+    /// List each distinct value of a mismatched item once, along with the
+    /// logs that have it, instead of listing the value for every log.
+    group_values: bool,
+    /// How mismatches are printed.
+    format: OutputFormat,
+    /// Mark each mismatched input as `[generated]` (the output of some
+    /// action) or `[source]`; for `tcmp` and `edges`, where it shows which
+    /// inputs are worth digging into further.
+    mark_generated: bool,
+    /// The number of columns that the execution logs' labels are padded to;
+    /// see [`label_width`].
+    label_width: usize,
+}
 
-fn find_mismatched<'l>(
-    artifact: ArtifactName<'l>,
-    actions: impl Iterator<Item = (&'l String, &'l Arc<BuildAction<'l>>)>,
-) -> (
-    impl Iterator<Item = (ArtifactName<'l>, &'l str)>, // env vars
-    impl Iterator<Item = (ArtifactName<'l>, &'l Path)>, // inputs
-    impl Iterator<Item = (ArtifactName<'l>, &'l Path)>, // outputs
+/// How the results of commands like `cmp` are printed (`--format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Colored text for people.
+    Human,
+    /// A `MismatchReport`, as JSON (one per line).
+    Json,
+    /// A `MismatchReport`, as CSV.
+    Csv,
+}
+
+impl OutputFormat {
+    const NAMES: &'static [&'static str] = &["human", "json", "csv"];
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("unknown output format `{}`", s)),
+        }
+    }
+}
+
+/// Which action to use for an output that more than one action in an
+/// execution log produced (`--multi`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MultiPolicy {
+    /// The first one in the log.
+    First,
+    /// The last one in the log.
+    Last,
+    /// The last one in the log, except that `cmp` compares every one of them.
+    All,
+}
+
+impl MultiPolicy {
+    const NAMES: &'static [&'static str] = &["first", "last", "all"];
+}
+
+impl std::str::FromStr for MultiPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "all" => Ok(Self::All),
+            _ => Err(format!("unknown policy `{}`", s)),
+        }
+    }
+}
+
+impl PrintOpts {
+    /// The number of columns that output should fit in, if it should be
+    /// constrained at all.
+    ///
+    /// Output that isn't going to a terminal is left alone.
+    fn width(&self) -> Option<usize> {
+        if self.no_wrap {
+            return None;
+        }
+
+        terminal_size().map(|(Width(w), _)| w as usize)
+    }
+}
+
+/// The number of columns that fits the longest of `labels`, up to `max`
+/// (`--label-width`); longer labels are cut off.
+fn label_width<'a>(labels: impl Iterator<Item = &'a String>, max: usize) -> usize {
+    labels
+        .map(|l| l.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(max)
+}
+
+/// Shortens `s` to fit in `width` columns (if given) by replacing its middle
+/// with `…`.
+///
+/// More of the end is kept than the start so that the file name at the end of
+/// a path survives, as in `bazel-out/…/foo.o`.
+fn elide(s: &str, width: Option<usize>) -> Cow<'_, str> {
+    let len = s.chars().count();
+    let width = match width {
+        Some(w) if len > w => w.max(2),
+        _ => return s.into(),
+    };
+
+    let head = (width - 1) / 3;
+    let tail = width - 1 - head;
+    let mut out: String = s.chars().take(head).collect();
+    out.push('…');
+    out.extend(s.chars().skip(len - tail));
+
+    out.into()
+}
+
+/// Formats a size for people while keeping the exact number of bytes, as in
+/// `1.20 GiB (1288490188 B)`. Sizes under 1 KiB are just `1023 B`.
+fn human_size(bytes: usize) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let (mut size, mut unit) = (bytes as f64 / 1024.0, 0);
+    // Moves up a unit for anything that would be rounded to `1024.00`:
+    while size >= 1023.995 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.2} {} ({} B)", size, UNITS[unit], bytes)
+}
+
+/// Describes where an action ran, as in `linux-sandbox` or
+/// `remote (cache hit)`.
+fn render_runner(runner: &str, cache_hit: bool) -> String {
+    let runner = if runner.is_empty() {
+        "<unknown runner>"
+    } else {
+        runner
+    };
+    // Bazel's runner for these is already `remote cache hit`:
+    if cache_hit && !runner.contains("cache hit") {
+        format!("{} (cache hit)", runner)
+    } else {
+        runner.to_owned()
+    }
+}
+
+/// Formats a digest for people, as in
+/// `{Size: 9.58 KiB (9809 B), SHA-256: 9316…}`.
+///
+/// The hash is shortened to `--hash-len` digits, if that's set. If it still
+/// wouldn't fit in `width` columns, the middle of the hash is left out (as in
+/// `9316ab…c0ffee`); hashes are never cut down to less than `MIN_HASH`
+/// characters though.
+fn render_digest(digest: &Digest<'_>, width: Option<usize>) -> String {
+    const MIN_HASH: usize = 12;
+
+    let size = human_size(digest.size_bytes);
+    let hash = format!("{:?}", digest.hash);
+    let len = hash.chars().count();
+    let rest = "{Size: , : }".len() + size.chars().count() + digest.hash_function_name.len();
+    let hash = match width {
+        Some(w) if rest + len > w && len > MIN_HASH => {
+            let keep = w.saturating_sub(rest).max(MIN_HASH) - 1;
+            let (head, tail) = (keep / 2, keep - keep / 2);
+            let chars: Vec<_> = hash.chars().collect();
+            // Hashes that `--hash-len` shortened are already missing their
+            // end:
+            if hash.ends_with('…') {
+                chars[..keep].iter().chain(['…'].iter()).collect()
+            } else {
+                let (head, tail) = (&chars[..head], &chars[len - tail..]);
+                head.iter().chain(['…'].iter()).chain(tail).collect()
+            }
+        }
+        _ => hash,
+    };
+
+    format!(
+        "{}Size: {}, {}: {}{}",
+        "{".dimmed(),
+        size.yellow(),
+        digest.hash_function_name,
+        hash.yellow(),
+        "}".dimmed()
+    )
+}
+
+/// A mismatch class's label, colored to tell the classes apart.
+fn colored_label(class: MismatchClass) -> String {
+    match class {
+        MismatchClass::Different => class.label().yellow().to_string(),
+        MismatchClass::Missing => class.label().red().to_string(),
+        MismatchClass::MissingAndDifferent => class.label().magenta().to_string(),
+    }
+}
+
+/// Prints the value that each execution log has for a mismatched item.
+///
+/// When grouping, logs that share a value are listed together under it (in
+/// the order the values are first seen); with many logs this makes it much
+/// easier to see which logs are the odd ones out.
+///
+/// With a `baseline`, its value is taken as the reference: it's listed first
+/// and every other log either matches it or differs from it.
+fn print_values<'f, T: PartialEq>(
+    values: impl Iterator<Item = (&'f String, Option<T>)>,
+    render: impl Fn(Option<&T>, Option<usize>) -> String,
+    width: Option<usize>,
+    label_width: usize,
+    group: bool,
+    baseline: Option<&str>,
 ) {
-    let mut env_vars: HashMap<&str, (&str, usize)> = HashMap::new();
-    let mut inputs: HashMap<&Path, (&Digest, usize)> = HashMap::new();
-    let mut outputs: HashMap<&Path, (&Digest, usize)> = HashMap::new();
+    let mut values: Vec<_> = values.collect();
+    let reference = match baseline.and_then(|b| values.iter().position(|(f, _)| *f == b)) {
+        Some(idx) => {
+            let reference = values.remove(idx);
+            values.insert(0, reference);
+            Some(0)
+        }
+        None => None,
+    };
+
+    if !group {
+        for (i, (f, v)) in values.iter().enumerate() {
+            let value = match reference {
+                Some(r) if i == r => format!(
+                    "{} {}",
+                    render(
+                        v.as_ref(),
+                        width.map(|w| w.saturating_sub(label_width + 17))
+                    ),
+                    "(baseline)".dimmed()
+                ),
+                Some(r) if *v == values[r].1 => "matches".green().to_string(),
+                Some(_) => format!(
+                    "{} {}",
+                    "differs:".red(),
+                    render(
+                        v.as_ref(),
+                        width.map(|w| w.saturating_sub(label_width + 15))
+                    )
+                ),
+                None => render(v.as_ref(), width.map(|w| w.saturating_sub(label_width + 6))),
+            };
+            println!("    {:>w$.w$}: {}", f.dimmed(), value, w = label_width);
+        }
+
+        return;
+    }
+
+    let mut groups: Vec<(Option<T>, Vec<&String>)> = Vec::new();
+    for (f, v) in values {
+        match groups.iter_mut().find(|(g, _)| *g == v) {
+            Some((_, logs)) => logs.push(f),
+            None => groups.push((v, vec![f])),
+        }
+    }
+
+    for (v, logs) in groups {
+        println!(
+            "    {}",
+            render(v.as_ref(), width.map(|w| w.saturating_sub(4)))
+        );
+        println!(
+            "      {} {}",
+            format!(
+                "in {} log{}:",
+                logs.len(),
+                if logs.len() == 1 { "" } else { "s" }
+            )
+            .dimmed(),
+            logs.iter()
+                .map(|f| if Some(f.as_str()) == baseline {
+                    format!("{} {}", f, "(baseline)".dimmed())
+                } else {
+                    f.to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
+/// The mismatches found by `find_mismatched` (or `transitive_cmp`), in a form
+/// that's meant for other tools (`--format json`).
+#[derive(Debug, Serialize)]
+struct MismatchReport<'l> {
+    /// The log that the others are compared against, if there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline: Option<&'l str>,
+    mismatches: Vec<ReportedMismatch<'l>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReportedMismatch<'l> {
+    /// The output whose action has the mismatch.
+    artifact: ArtifactName<'l>,
+    /// What's mismatched: `env`, `platform`, `argument`, `exit`, `runner`,
+    /// `input` or `output`.
+    kind: &'static str,
+    /// The name of the env var or platform property, the index of the
+    /// argument, or the path of the input or output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    /// For inputs and outputs: whether the item is missing from some logs,
+    /// different in some, or both.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    class: Option<MismatchClass>,
+    /// The value in each log (`null` if it's not present).
+    values: BTreeMap<&'l str, Option<MismatchValue<'l>>>,
+    /// With a baseline: the logs whose value differs from the baseline's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    differs: Option<Vec<&'l str>>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+enum MismatchValue<'l> {
+    Text(&'l str),
+    Digest(&'l Digest<'l>),
+    Exit {
+        #[serde(rename = "exitCode")]
+        exit_code: i32,
+        status: &'l str,
+    },
+    Runner {
+        runner: &'l str,
+        #[serde(rename = "cacheHit")]
+        cache_hit: bool,
+    },
+}
+
+impl fmt::Display for MismatchValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text(t) => write!(f, "{}", t),
+            Self::Digest(d) => write!(f, "{:x}/{}", d.hash, d.size_bytes),
+            Self::Exit {
+                exit_code,
+                status: "",
+            } => write!(f, "{}", exit_code),
+            Self::Exit { exit_code, status } => write!(f, "{} ({})", exit_code, status),
+            Self::Runner { runner, cache_hit } => {
+                write!(f, "{}", render_runner(runner, *cache_hit))
+            }
+        }
+    }
+}
+
+impl<'l> MismatchReport<'l> {
+    fn new(
+        found: MismatchSet<'l>,
+        maps: &'l [(&'l String, Map<'l>)],
+        baseline: Option<&str>,
+    ) -> Self {
+        let baseline = baseline.and_then(|b| maps.iter().find(|(f, _)| *f == b));
+        let baseline = baseline.map(|(f, _)| f.as_str());
+
+        let mut mismatches = Vec::new();
+        let mut push = |artifact, kind, field, class, value: &dyn Fn(&str) -> Option<_>| {
+            let values: BTreeMap<_, _> = maps.iter().map(|(f, _)| (f.as_str(), value(f))).collect();
+            let differs = baseline.map(|b| {
+                let reference = values.get(b).and_then(|v| v.as_ref());
+                values
+                    .iter()
+                    .filter(|(l, v)| **l != b && v.as_ref() != reference)
+                    .map(|(l, _)| *l)
+                    .collect()
+            });
+            mismatches.push(ReportedMismatch {
+                artifact,
+                kind,
+                field,
+                class,
+                values,
+                differs,
+            })
+        };
+
+        for m in found.env_vars {
+            push(m.artifact, "env", Some(m.key.to_owned()), None, &|l| {
+                Some(MismatchValue::Text(m.value(l)?))
+            });
+        }
+        for m in found.platform {
+            push(m.artifact, "platform", Some(m.key.to_owned()), None, &|l| {
+                Some(MismatchValue::Text(m.value(l)?))
+            });
+        }
+        for m in found.args {
+            push(
+                m.artifact,
+                "argument",
+                Some(m.key.to_string()),
+                None,
+                &|l| Some(MismatchValue::Text(m.value(l)?)),
+            );
+        }
+        for m in found.exit_status {
+            push(m.artifact, "exit", None, None, &|l| {
+                let (exit_code, status) = m.value(l)?;
+                Some(MismatchValue::Exit { exit_code, status })
+            });
+        }
+        for m in found.runner {
+            push(m.artifact, "runner", None, None, &|l| {
+                let (runner, cache_hit) = m.value(l)?;
+                Some(MismatchValue::Runner { runner, cache_hit })
+            });
+        }
+        for (kind, found) in [("input", found.inputs), ("output", found.outputs)] {
+            for m in found {
+                let field = m.key.to_string_lossy().into_owned();
+                push(m.artifact, kind, Some(field), Some(m.class), &|l| {
+                    Some(MismatchValue::Digest(m.value(l)?))
+                });
+            }
+        }
+
+        Self {
+            baseline,
+            mismatches,
+        }
+    }
+
+    /// Prints the mismatches as CSV (`--format csv`); see [`Self::write_csv`].
+    fn print_csv(&self) {
+        let _ = self.write_csv(io::stdout().lock());
+    }
 
-    // TODO: DRY
-    let mut num_files = 0;
-    for (_, a) in actions {
-        for e in a.0.environment_variables.iter() {
-            let (val, count) = env_vars.entry(e.name).or_insert((e.value, 0));
-            if *val == e.value {
-                *count += 1;
+    /// Writes the mismatches as CSV, with one row for each log's value of each
+    /// mismatched item.
+    ///
+    /// Values that aren't present are empty (and have `present` set to
+    /// `false`); digests are written as `<hash>/<size>`.
+    fn write_csv(&self, mut out: impl io::Write) -> io::Result<()> {
+        writeln!(out, "artifact,kind,field,log_name,value,present")?;
+        for m in &self.mismatches {
+            for (log, v) in &m.values {
+                let value = v.as_ref().map(|v| v.to_string()).unwrap_or_default();
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{}",
+                    csv_field(m.artifact),
+                    m.kind,
+                    csv_field(m.field.as_deref().unwrap_or("")),
+                    csv_field(log),
+                    csv_field(&value),
+                    v.is_some(),
+                )?;
             }
         }
+        Ok(())
+    }
 
-        // `HashSet` for dedupe; inputs get listed multiple times, sometimes
-        for i in a.0.inputs.iter().collect::<HashSet<_>>().iter() {
-            let (val, count) = inputs.entry(i.path).or_insert((&i.digest, 0));
-            if *val == &i.digest {
-                *count += 1;
+    /// The number of mismatches of each kind (see [`ReportedMismatch::kind`]),
+    /// in the order that `export-md` lists them.
+    fn counts(&self) -> [(&'static str, usize); 7] {
+        let mut counts = [
+            ("env", 0),
+            ("platform", 0),
+            ("argument", 0),
+            ("exit", 0),
+            ("input", 0),
+            ("output", 0),
+            ("runner", 0),
+        ];
+        for m in &self.mismatches {
+            if let Some((_, c)) = counts.iter_mut().find(|(k, _)| *k == m.kind) {
+                *c += 1;
             }
         }
+        counts
+    }
 
-        for o in a.0.actual_outputs.iter() {
-            let (val, count) = outputs.entry(o.path).or_insert((&o.digest, 0));
-            if *val == &o.digest {
-                *count += 1;
+    /// Writes the mismatches as a Markdown table with a row for each
+    /// mismatched item and a column for each log's value (for `export-md`).
+    fn write_markdown(&self, mut out: impl io::Write) -> io::Result<()> {
+        let logs: Vec<_> = match self.mismatches.first() {
+            Some(m) => m.values.keys().copied().collect(),
+            None => return Ok(()),
+        };
+
+        write!(out, "| Kind | Field |")?;
+        for l in &logs {
+            if Some(*l) == self.baseline {
+                write!(out, " {} (baseline) |", md_code(l))?;
+            } else {
+                write!(out, " {} |", md_code(l))?;
             }
         }
+        writeln!(out)?;
+        writeln!(out, "|---|---|{}", "---|".repeat(logs.len()))?;
+        for m in &self.mismatches {
+            write!(
+                out,
+                "| {} | {} |",
+                m.kind,
+                m.field.as_deref().map(md_code).unwrap_or_default()
+            )?;
+            for v in m.values.values() {
+                match v {
+                    Some(v) => write!(out, " {} |", md_code(&v.to_string()))?,
+                    None => write!(out, " *not present* |")?,
+                }
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
 
-        num_files += 1;
+/// Formats `s` as inline code for a Markdown table cell.
+fn md_code(s: &str) -> String {
+    let s = s.replace('|', "\\|");
+    if s.contains('`') {
+        format!("`` {} ``", s)
+    } else {
+        format!("`{}`", s)
     }
+}
 
-    let mismatched_env_vars = env_vars
-        .into_iter()
-        .filter(move |(_, (_, c))| *c != num_files)
-        .map(move |(k, _)| (artifact, k));
-    let mismatched_inputs = inputs
-        .into_iter()
-        .filter(move |(_, (_, c))| *c != num_files)
-        .map(move |(k, _)| (artifact, k));
-    let mismatched_outputs = outputs
-        .into_iter()
-        .filter(move |(_, (_, c))| *c != num_files)
-        .map(move |(k, _)| (artifact, k));
+/// Quotes `s` for use as a CSV field, if it needs it.
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
 
-    (mismatched_env_vars, mismatched_inputs, mismatched_outputs)
+/// Whether `path` is the output of an action in any of the execution logs (as
+/// opposed to a source file); for `[generated]` and `[source]`.
+fn is_generated(maps: &[(&String, Map<'_>)], path: &str) -> bool {
+    // The maps are keyed by every action's listed outputs so they double as
+    // the set of all known outputs:
+    maps.iter().any(|(_, map)| map.contains_key(path))
 }
 
+/// Prints the mismatches found by `find_mismatched` (or `transitive_cmp`);
+/// returns whether there were any.
+///
+/// With a `baseline` (the label of one of the logs), each of the other logs'
+/// values is shown as matching or differing from the baseline's.
 fn print_mismatched<'l>(
-    (env, inp, out): (
-        impl Iterator<Item = (ArtifactName<'l>, &'l str)> + 'l, // env vars
-        impl Iterator<Item = (ArtifactName<'l>, &'l Path)> + 'l, // inputs
-        impl Iterator<Item = (ArtifactName<'l>, &'l Path)> + 'l, // outputs
-    ),
+    mut mismatches: MismatchSet<'l>,
     maps: &'l [(&'l String, Map<'l>)],
-) {
+    opts: &PrintOpts,
+    baseline: Option<&str>,
+) -> bool {
+    // The comparisons are done in parallel (and with `HashMap`s); sort so
+    // that the output is the same every time:
+    mismatches.sort();
+
+    if opts.format != OutputFormat::Human {
+        // Differences in where the actions ran are reported but don't count:
+        let mismatched = !mismatches.is_empty();
+        let report = MismatchReport::new(mismatches, maps, baseline);
+        if opts.format == OutputFormat::Csv {
+            report.print_csv();
+        } else {
+            println!("{}", serde_json::to_string(&report).unwrap());
+        }
+        return mismatched;
+    }
+
+    // Printing every mismatch can take a while; stop (with whatever has been
+    // printed so far) if the command is cancelled:
+    let MismatchSet {
+        env_vars: env,
+        inputs: inp,
+        outputs: out,
+        args,
+        platform,
+        exit_status: exit,
+        runner,
+    } = mismatches;
+    let (env, inp, out, args, platform, exit, runner) = (
+        env.into_iter().take_while(|_| !cancelled()),
+        inp.into_iter().take_while(|_| !cancelled()),
+        out.into_iter().take_while(|_| !cancelled()),
+        args.into_iter().take_while(|_| !cancelled()),
+        platform.into_iter().take_while(|_| !cancelled()),
+        exit.into_iter().take_while(|_| !cancelled()),
+        runner.into_iter().take_while(|_| !cancelled()),
+    );
     let mut mismatched = false;
+    let width = opts.width();
 
     let mut mismatched_env_vars = env.peekable();
     if mismatched_env_vars.peek().is_some() {
         mismatched = true;
         println!("\n{}:", "Environment Variable Mismatches".bold());
     }
-    for (artifact, env_name) in mismatched_env_vars {
-        println!("  ${}", env_name.blue());
-        for (f, m) in maps.iter() {
-            print!("    {:>20.20}: ", f.dimmed());
-            if let Some(v) = m[artifact]
-                .0
-                .environment_variables
-                .iter()
-                .find(|e| e.name == env_name)
-            {
-                println!("{}", v.value.yellow());
-            } else {
-                println!("{}", "<not present>".red());
-            }
-        }
+    for m in mismatched_env_vars {
+        println!(
+            "  ${}",
+            elide(m.key, width.map(|w| w.saturating_sub(3))).blue()
+        );
+        print_values(
+            maps.iter().map(|(f, _)| (*f, m.value(f))),
+            |v, width| match v {
+                Some(v) => elide(v, width).yellow().to_string(),
+                None => "<not present>".red().to_string(),
+            },
+            width,
+            opts.label_width,
+            opts.group_values,
+            baseline,
+        );
+    }
+
+    let mut mismatched_platform = platform.peekable();
+    if mismatched_platform.peek().is_some() {
+        mismatched = true;
+        println!("\n{}:", "Platform Property Mismatches".bold());
+    }
+    for m in mismatched_platform {
+        println!(
+            "  {}",
+            elide(m.key, width.map(|w| w.saturating_sub(2))).blue()
+        );
+        print_values(
+            maps.iter().map(|(f, _)| (*f, m.value(f))),
+            |v, width| match v {
+                Some(v) => elide(v, width).yellow().to_string(),
+                None => "<not present>".red().to_string(),
+            },
+            width,
+            opts.label_width,
+            opts.group_values,
+            baseline,
+        );
+    }
+
+    let mut mismatched_args = args.peekable();
+    if mismatched_args.peek().is_some() {
+        mismatched = true;
+        println!("\n{}:", "Command Argument Mismatches".bold());
+    }
+    for m in mismatched_args {
+        println!(
+            "  `{}` {}",
+            elide(m.artifact, width.map(|w| w.saturating_sub(20))).blue(),
+            format!("(argument {})", m.key).dimmed()
+        );
+        print_values(
+            maps.iter().map(|(f, _)| (*f, m.value(f))),
+            |v, width| match v {
+                Some(v) => elide(v, width).yellow().to_string(),
+                None => "<not present>".red().to_string(),
+            },
+            width,
+            opts.label_width,
+            opts.group_values,
+            baseline,
+        );
+    }
+
+    let mut mismatched_exit = exit.peekable();
+    if mismatched_exit.peek().is_some() {
+        mismatched = true;
+        println!("\n{}:", "Exit Status Mismatches".bold());
+    }
+    for m in mismatched_exit {
+        println!(
+            "  `{}`",
+            elide(m.artifact, width.map(|w| w.saturating_sub(4))).blue()
+        );
+        print_values(
+            maps.iter().map(|(f, _)| (*f, m.value(f))),
+            |v, width| match v {
+                Some((code, "")) => format!("exit code {}", code).yellow().to_string(),
+                Some((code, status)) => elide(&format!("exit code {} ({})", code, status), width)
+                    .yellow()
+                    .to_string(),
+                None => "<not present>".red().to_string(),
+            },
+            width,
+            opts.label_width,
+            opts.group_values,
+            baseline,
+        );
+    }
+
+    // Not a mismatch as such, but often the explanation for the ones below:
+    let mut mismatched_runner = runner.peekable();
+    let ran_differently = mismatched_runner.peek().is_some();
+    if ran_differently {
+        println!("\n{}:", "Runner Differences".bold());
+    }
+    for m in mismatched_runner {
+        println!(
+            "  `{}`",
+            elide(m.artifact, width.map(|w| w.saturating_sub(4))).blue()
+        );
+        print_values(
+            maps.iter().map(|(f, _)| (*f, m.value(f))),
+            |v, _| match v {
+                Some((runner, cache_hit)) => render_runner(runner, *cache_hit).yellow().to_string(),
+                None => "<not present>".red().to_string(),
+            },
+            width,
+            opts.label_width,
+            opts.group_values,
+            baseline,
+        );
     }
 
     fn item_mismatch_printer<'l>(
-        it: impl Iterator<Item = (ArtifactName<'l>, &'l Path)>,
+        it: impl Iterator<Item = Mismatch<'l, &'l Path, &'l Digest<'l>>>,
         name: &'static str,
-        ctx_to_item_vec: impl Fn(&'l ActionContext<'l>) -> &'l Vec<Item<'l>>,
         maps: &'l [(&'l String, Map<'l>)],
+        opts: &PrintOpts,
+        baseline: Option<&str>,
+        mark_generated: bool,
         mismatched: &mut bool,
     ) {
+        let (width, group) = (opts.width(), opts.group_values);
         let mut it = it.peekable();
         if it.peek().is_some() {
             *mismatched = true;
             println!("\n{}:", name.bold());
         }
-        for (artifact, path) in it {
-            println!("  `{}`", path.display().blue());
-            for (f, m) in maps.iter() {
-                print!("    {:>20.20}: ", f.dimmed());
-                if let Some(v) = ctx_to_item_vec(&m[artifact].0)
-                    .iter()
-                    .find(|i| i.path == path)
-                {
-                    println!(
-                        "{}Bytes: {:10}, {}: {}{}",
-                        "{".dimmed(),
-                        v.digest.size_bytes.yellow(),
-                        v.digest.hash_function_name,
-                        format!("{:?}", v.digest.hash).yellow(),
-                        "}".dimmed()
-                    );
-                } else {
-                    println!("{}", "<not present>".red());
-                }
+        for m in it {
+            let path_str = m.key.to_string_lossy();
+            let origin = if !mark_generated {
+                None
+            } else if is_generated(maps, &path_str) {
+                Some("[generated]".cyan().to_string())
+            } else {
+                Some("[source]".dimmed().to_string())
+            };
+            let label_width =
+                m.class.label().chars().count() + 1 + if mark_generated { 12 } else { 0 };
+            println!(
+                "  `{}` {}{}",
+                elide(&path_str, width.map(|w| w.saturating_sub(4 + label_width))).blue(),
+                colored_label(m.class),
+                origin.map(|o| format!(" {}", o)).unwrap_or_default()
+            );
+            let functions: BTreeSet<_> = m
+                .values
+                .iter()
+                .filter_map(|(_, d)| Some((*d)?.hash_function_name))
+                .collect();
+            print_values(
+                maps.iter().map(|(f, _)| (*f, m.value(f))),
+                |v, width| match v {
+                    Some(d) => render_digest(d, width),
+                    None => "<not present>".red().to_string(),
+                },
+                width,
+                opts.label_width,
+                group,
+                baseline,
+            );
+            if functions.len() > 1 {
+                // Lined up with the values (which are indented less when
+                // grouped):
+                println!(
+                    "{}{}",
+                    if group { "    " } else { "     " },
+                    "(these digests are from different hash functions; comparing them is not meaningful)"
+                        .yellow()
+                );
             }
         }
     }
@@ -387,30 +1289,224 @@ fn print_mismatched<'l>(
     item_mismatch_printer(
         inp,
         "Input Mismatches",
-        |a| &a.inputs,
         maps,
+        opts,
+        baseline,
+        opts.mark_generated,
         &mut mismatched,
     );
     item_mismatch_printer(
         out,
         "Output Mismatches",
-        |a| &a.actual_outputs,
         maps,
+        opts,
+        baseline,
+        false,
         &mut mismatched,
     );
 
     if !mismatched {
+        if ran_differently {
+            println!();
+        }
         println!("{}", "No mismatches!".green());
     }
+
+    mismatched
+}
+
+/// Prints the number of inputs each action has, if they differ.
+///
+/// This is a quick way to tell whether the action graph itself changed (i.e.
+/// an input was added or removed) or just the contents of some inputs.
+fn print_input_count_mismatch(actions: &[(&String, &Arc<BuildAction<'_>>)], label_width: usize) {
+    let counts: Vec<_> = actions
+        .iter()
+        .map(|(f, a)| {
+            (
+                f,
+                a.0.inputs
+                    .iter()
+                    .map(|i| i.path)
+                    .collect::<HashSet<_>>()
+                    .len(),
+            )
+        })
+        .collect();
+    if counts.iter().all(|(_, c)| *c == counts[0].1) {
+        return;
+    }
+
+    println!("\n{}:", "Input Count Mismatch".bold());
+    for (f, c) in counts {
+        println!(
+            "    {:>w$.w$}: {} inputs",
+            f.dimmed(),
+            c.yellow(),
+            w = label_width
+        );
+    }
+}
+
+/// Colors the field name at the start of a line of `{:#?}` output (i.e. the
+/// `inputs` in `    inputs: [`) so that the structure of a big dump is easier
+/// to follow.
+fn highlight_field_name(line: &str) -> Cow<'_, str> {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    match trimmed.split_once(':') {
+        Some((name, rest))
+            if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+        {
+            format!("{}{}:{}", indent, name.cyan(), rest).into()
+        }
+        _ => line.into(),
+    }
+}
+
+/// Writes a line-by-line diff of `old` and `new` to `out`.
+///
+/// Removed lines are red (and struck through), added lines are green, and
+/// field names in the unchanged lines are highlighted.
+fn write_unified_diff(out: &mut dyn io::Write, old: &str, new: &str) -> io::Result<()> {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+
+    let removed = |out: &mut dyn io::Write, a: &[&str]| {
+        a.iter()
+            .try_for_each(|l| writeln!(out, "{}", l.red().strikethrough()))
+    };
+    let added = |out: &mut dyn io::Write, b: &[&str]| {
+        b.iter().try_for_each(|l| writeln!(out, "{}", l.green()))
+    };
+    for op in prettydiff::basic::diff(&old, &new) {
+        match op {
+            DiffOp::Equal(a) => a
+                .iter()
+                .try_for_each(|l| writeln!(out, "{}", highlight_field_name(l)))?,
+            DiffOp::Remove(a) => removed(out, a)?,
+            DiffOp::Insert(b) => added(out, b)?,
+            DiffOp::Replace(a, b) => {
+                removed(out, a)?;
+                added(out, b)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes `old` and `new` to `out` in two columns (split to fit the terminal)
+/// with the lines that differ highlighted.
+///
+/// Like `sdiff`, the gutter between the columns marks changed (`|`), removed
+/// (`<`), and added (`>`) lines. Lines too long for their column are wrapped.
+fn write_side_by_side_diff(
+    out: &mut dyn io::Write,
+    (old_name, old): (&str, &str),
+    (new_name, new): (&str, &str),
+) -> io::Result<()> {
+    let width = terminal_size()
+        .map(|(Width(w), _)| w as usize)
+        .unwrap_or(80);
+    let col = (width.saturating_sub(3) / 2).max(10);
+
+    fn wrap(line: &str, width: usize) -> Vec<String> {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.is_empty() {
+            vec![String::new()]
+        } else {
+            chars.chunks(width).map(|c| c.iter().collect()).collect()
+        }
+    }
+
+    let row = |out: &mut dyn io::Write,
+               left: Option<&str>,
+               right: Option<&str>,
+               gutter: char|
+     -> io::Result<()> {
+        let left = left.map(|l| wrap(l, col)).unwrap_or_default();
+        let right = right.map(|r| wrap(r, col)).unwrap_or_default();
+
+        for i in 0..left.len().max(right.len()) {
+            let l = format!("{:col$}", left.get(i).map(|l| l.as_str()).unwrap_or(""));
+            let r = right.get(i).map(|r| r.as_str()).unwrap_or("");
+            match gutter {
+                ' ' => writeln!(out, "{} {} {}", l, gutter.dimmed(), r)?,
+                '<' => writeln!(out, "{} {} {}", l.red(), gutter.dimmed(), r)?,
+                '>' => writeln!(out, "{} {} {}", l, gutter.dimmed(), r.green())?,
+                _ => writeln!(out, "{} {} {}", l.red(), gutter.dimmed(), r.green())?,
+            }
+        }
+        Ok(())
+    };
+
+    writeln!(
+        out,
+        "{}   {}",
+        format!("{:col$.col$}", old_name).bold(),
+        format!("{:.col$}", new_name).bold(),
+    )?;
+    writeln!(out, "{}", "-".repeat(col * 2 + 3).dimmed())?;
+
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+    for op in prettydiff::basic::diff(&old, &new) {
+        match op {
+            DiffOp::Equal(a) => a.iter().try_for_each(|l| row(out, Some(l), Some(l), ' '))?,
+            DiffOp::Remove(a) => a.iter().try_for_each(|l| row(out, Some(l), None, '<'))?,
+            DiffOp::Insert(b) => b.iter().try_for_each(|r| row(out, None, Some(r), '>'))?,
+            DiffOp::Replace(a, b) => {
+                for i in 0..a.len().max(b.len()) {
+                    row(out, a.get(i).copied(), b.get(i).copied(), '|')?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a diff of the actions in `actions` (one per log) to `out`, for
+/// `diff <output path> [--side-by-side]`; with more than 2 logs, each is
+/// diffed against the first.
+fn write_diff(
+    out: &mut dyn io::Write,
+    artifact: &str,
+    actions: &[(&String, &Arc<BuildAction<'_>>)],
+    side_by_side: bool,
+) -> io::Result<()> {
+    if actions.iter().all(|(_, a)| a.0 == actions[0].1 .0) {
+        return writeln!(out, "all executions of `{}` were equivalent", artifact);
+    }
+
+    let (reference, others) = actions.split_first().unwrap();
+    let old = format!("{:#?}", reference.1 .0);
+    for (f, a) in others {
+        if others.len() > 1 {
+            writeln!(out, "\n`{}` → `{}`:", reference.0.green(), f.green())?;
+            if a.0 == reference.1 .0 {
+                writeln!(out, "{}", "(equivalent)".dimmed())?;
+                continue;
+            }
+        }
+
+        let new = format!("{:#?}", a.0);
+        if side_by_side {
+            write_side_by_side_diff(out, (reference.0, &old), (f, &new))?;
+        } else {
+            write_unified_diff(out, &old, &new)?;
+        }
+    }
+    Ok(())
 }
 
 fn get<'l>(
     maps: &'l [(&'l String, Map<'l>)],
     path: &str,
 ) -> Option<Vec<(&'l String, &'l Arc<BuildAction<'l>>)>> {
-    match maps
-        .iter()
-        .map(|(f, m)| m.get(path).map(|v| (*f, v)))
+    match get_each(maps, path)
+        .into_iter()
+        .map(|(f, a)| a.map(|a| (f, a)))
         .collect::<Option<Vec<_>>>()
     {
         Some(v) => Some(v),
@@ -421,245 +1517,4395 @@ fn get<'l>(
     }
 }
 
-fn transitive_cmp<'l>(
-    root: ArtifactName<'l>,
-    maps: &'l [(&'l String, Map<'l>)],
-) -> (
-    impl Iterator<Item = (ArtifactName<'l>, &'l str)>, // env vars
-    impl Iterator<Item = (ArtifactName<'l>, &'l Path)>, // inputs
-    impl Iterator<Item = (ArtifactName<'l>, &'l Path)>, // outputs
-) {
-    let (envs, inps, outs) = (
-        Mutex::new(HashMap::new()),
-        Mutex::new(HashMap::new()),
-        Mutex::new(HashMap::new()),
-    );
-    let visited = RwLock::new(HashSet::new());
-
-    #[allow(clippy::type_complexity)]
-    fn traverse<'l>(
-        artifact: ArtifactName<'l>,
-        (envs, inps, outs): (
-            &Mutex<HashMap<&'l str, (ArtifactName<'l>, &'l str)>>,
-            &Mutex<HashMap<&'l Path, (ArtifactName<'l>, &'l Path)>>,
-            &Mutex<HashMap<&'l Path, (ArtifactName<'l>, &'l Path)>>,
-        ),
-        maps: &'l [(&'l String, Map<'l>)],
-        visited: &RwLock<HashSet<ArtifactName<'l>>>,
-    ) {
-        if visited.read().unwrap().contains(&artifact) {
-            return;
-        }
+/// The label of the execution log labelled `label`, if there is one.
+fn find_log<'l>(maps: &[(&'l String, Map<'_>)], label: &str) -> Option<&'l str> {
+    maps.iter()
+        .find(|(f, _)| *f == label)
+        .map(|(f, _)| f.as_str())
+}
 
-        if let Some(actions) = get(maps, artifact) {
-            let (env, inp, out) = find_mismatched(artifact, actions.into_iter());
-            visited.write().unwrap().insert(artifact);
+/// The action for `path` in each execution log, if it's there; unlike `get`
+/// this doesn't bail when some of the logs are missing it.
+fn get_each<'l>(
+    maps: &'l [(&'l String, Map<'l>)],
+    path: &str,
+) -> Vec<(&'l String, Option<&'l Arc<BuildAction<'l>>>)> {
+    maps.iter().map(|(f, m)| (*f, m.get(path))).collect()
+}
 
-            envs.lock().unwrap().extend(env.map(|p| (p.1, p)));
-            outs.lock().unwrap().extend(out.map(|p| (p.1, p)));
+/// Lists the divergent actions (found by `transitive_cmp`) that have no
+/// inputs; there's nothing further to look at for these.
+fn print_leaves(leaves: &[ArtifactName<'_>]) {
+    if leaves.is_empty() {
+        return;
+    }
 
-            let mismatched_inputs: Vec<_> = inp.collect();
-            inps.lock()
-                .unwrap()
-                .extend(mismatched_inputs.iter().map(|p| (p.1, *p)));
+    println!("\n{}:", "Leaves".bold());
+    for l in leaves {
+        println!(
+            "  `{}` {}",
+            l.blue(),
+            "(source/leaf, no further inputs)".dimmed()
+        );
+    }
+}
 
-            rayon::scope(|s| {
-                for (_, path) in mismatched_inputs {
-                    s.spawn(move |_| {
-                        traverse(path.to_str().unwrap(), (envs, inps, outs), maps, visited)
-                    });
-                }
-            })
-        }
+/// Finds the shortest chain of dependencies from the output `from` to `to` (an
+/// input somewhere below it) within one execution log, following the same
+/// edges (an action's inputs) that `tcmp` does.
+fn dependency_path<'l>(map: &Map<'l>, from: &str, to: &str) -> Option<Vec<&'l str>> {
+    let (from, _) = map.get_key_value(from)?;
+    if *from == to {
+        return Some(vec![from]);
     }
 
-    traverse(root, (&envs, &inps, &outs), &maps, &visited);
+    // Which artifact each artifact we've reached was first reached from:
+    let mut parents: HashMap<&'l str, &'l str> = HashMap::new();
+    let mut queue = VecDeque::from([*from]);
+    while let Some(artifact) = queue.pop_front() {
+        let action = match map.get(artifact) {
+            Some(a) => a,
+            None => continue,
+        };
 
-    (
-        envs.into_inner().unwrap().into_iter().map(|(_, v)| v),
-        inps.into_inner().unwrap().into_iter().map(|(_, v)| v),
-        outs.into_inner().unwrap().into_iter().map(|(_, v)| v),
-    )
-}
+        for input in action.0.inputs.iter().map(|i| i.path.to_str().unwrap()) {
+            if input == *from || parents.contains_key(input) {
+                continue;
+            }
+            parents.insert(input, artifact);
 
-fn main() -> eyre::Result<()> {
-    color_eyre::install()?;
+            if input == to {
+                let mut path = vec![input];
+                while let Some(p) = parents.get(path.last().unwrap()) {
+                    path.push(p);
+                }
+                path.reverse();
 
-    let args = || args_os().skip(1);
-    let num_files = args().count();
-    if num_files == 0 {
-        return Err(eyre!("specify 1 or more files to compare!"));
+                return Some(path);
+            }
+            queue.push_back(input);
+        }
     }
 
-    let truncate_file_names = args().any(|f| f.to_str().unwrap().len() > 20)
-        && args()
-            .map(|f| PathBuf::from(&f).file_name().unwrap().to_owned())
-            .collect::<HashSet<_>>()
-            .len()
-            == num_files;
+    None
+}
 
-    let files: Vec<(String, String)> = args()
-        .progress()
-        .map(PathBuf::from)
-        .map(|f| {
-            read_to_string(&f).map(|c| {
-                let n = if truncate_file_names {
-                    f.file_name().unwrap().to_str().unwrap()
-                } else {
-                    f.to_str().unwrap()
-                };
+/// Hashes the file at `path` (without reading all of it into memory at once);
+/// returns its digest.
+///
+/// This is always a SHA-256 digest; `verify` can't check digests from the
+/// other hash functions (Bazel's default is SHA-256).
+fn digest_file(path: &Path) -> io::Result<Digest<'static>> {
+    use sha2::Digest as _;
 
-                (c, n.to_owned())
-            })
-        })
-        .collect::<Result<_, _>>()?;
+    let mut hasher = sha2::Sha256::new();
+    let size = io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
 
-    let p = MultiProgress::new();
-    let sty = ProgressStyle::default_bar()
-        .template("{msg:20!.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
-        .progress_chars("#>-");
-    let maps: Vec<_> = files
-        .iter()
-        .map(|(f, n)| {
-            let pb = p.add(ProgressBar::new(f.len() as _).with_message(String::from(n)));
-            pb.set_style(sty.clone());
-            (f, n, pb)
+    Ok(Digest {
+        hash: Hash::from_bytes(&hasher.finalize()),
+        size_bytes: size as usize,
+        hash_function_name: "SHA-256",
+    })
+}
+
+/// Prints one row per execution log summarizing the action for `output`
+/// (mnemonic, number of inputs and outputs, remotable/cacheable, exit code and
+/// runner), for `info <output path>`; a quick check that the logs even ran the same
+/// action before digging in with `cmp`.
+fn print_info(maps: &[(&String, Map<'_>)], output: &str, label_width: usize) {
+    let rows: Vec<_> = get_each(maps, output)
+        .into_iter()
+        .map(|(f, a)| {
+            let cells = a.map(|a| {
+                let a = &a.0;
+                let size: usize = a.actual_outputs.iter().map(|o| o.digest.size_bytes).sum();
+                [
+                    if a.mnemonic.is_empty() {
+                        "<no mnemonic>".to_owned()
+                    } else {
+                        a.mnemonic.to_owned()
+                    },
+                    format!("{} inputs", a.inputs.len()),
+                    format!("{} outputs ({})", a.actual_outputs.len(), human_size(size)),
+                    if a.remotable {
+                        "remotable"
+                    } else {
+                        "not remotable"
+                    }
+                    .to_owned(),
+                    if a.cacheable {
+                        "cacheable"
+                    } else {
+                        "not cacheable"
+                    }
+                    .to_owned(),
+                    if a.status.is_empty() {
+                        format!("exit code {}", a.exit_code)
+                    } else {
+                        format!("exit code {} ({})", a.exit_code, a.status)
+                    },
+                    render_runner(a.runner, a.cache_hit),
+                ]
+            });
+            (f, cells)
         })
         .collect();
-    thread::spawn(move || p.join_and_clear().unwrap());
+    if rows.iter().all(|(_, c)| c.is_none()) {
+        println!("`{}` not found in any of the execution logs", output);
+        return;
+    }
 
-    let maps: Vec<(_, Map)> = maps
-        .par_iter()
-        .map(|(f, n, p)| exec_log_to_hashmap(f.as_bytes(), p).map(|h| (*n, h)))
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .map(|(n, (map, dups))| {
-            if !dups.is_empty() {
-                eprintln!(
-                    "[{}] Some outputs in `{}` appear to be produced by multiple actions:",
-                    "WARNING".yellow(),
-                    n.blue()
+    let mut widths = [0; 7];
+    for cells in rows.iter().filter_map(|(_, c)| c.as_ref()) {
+        for (w, c) in widths.iter_mut().zip(cells) {
+            *w = (*w).max(c.len());
+        }
+    }
+
+    for (f, cells) in rows {
+        match cells {
+            Some(cells) => {
+                let row: Vec<_> = cells
+                    .iter()
+                    .zip(widths)
+                    .map(|(c, w)| format!("{:<w$}", c, w = w))
+                    .collect();
+                println!(
+                    "  {:>w$.w$}: {}",
+                    f.green(),
+                    row.join("  ").trim_end(),
+                    w = label_width
                 );
-                for o in dups {
-                    eprintln!("  - {}", o.underline());
-                }
-                eprintln!();
             }
+            None => println!(
+                "  {:>w$.w$}: {}",
+                f.green(),
+                "<not present>".red(),
+                w = label_width
+            ),
+        }
+    }
+}
 
-            (n, map)
+/// Formats a duration for people, as in `750µs`, `512ms`, `1.50s` or `2m05s`.
+fn human_duration(d: Duration) -> String {
+    if d < Duration::from_millis(1) {
+        format!("{}µs", d.as_micros())
+    } else if d < Duration::from_secs(1) {
+        format!("{}ms", d.as_millis())
+    } else if d < Duration::from_secs(60) {
+        format!("{:.2}s", d.as_secs_f64())
+    } else {
+        format!("{}m{:02}s", d.as_secs() / 60, d.as_secs() % 60)
+    }
+}
+
+/// Prints how long the action for `output` took in each execution log (in
+/// total, running, and waiting to run), for `timing <output path>`.
+fn print_timing(maps: &[(&String, Map<'_>)], output: &str, label_width: usize) {
+    let rows: Vec<_> = get_each(maps, output)
+        .into_iter()
+        .map(|(f, a)| {
+            let cells = a.map(|a| {
+                let m = &a.0.metrics;
+                [
+                    ("total", m.total_time),
+                    ("executing", m.execution_wall_time),
+                    ("queued", m.queue_time),
+                ]
+                .map(|(what, d)| {
+                    let d = d.map(human_duration).unwrap_or_else(|| "?".to_owned());
+                    format!("{} {}", d, what)
+                })
+            });
+            (f, cells)
         })
         .collect();
+    if rows.iter().all(|(_, c)| c.is_none()) {
+        println!("`{}` not found in any of the execution logs", output);
+        return;
+    }
+
+    let mut widths = [0; 3];
+    for cells in rows.iter().filter_map(|(_, c)| c.as_ref()) {
+        for (w, c) in widths.iter_mut().zip(cells) {
+            *w = (*w).max(c.chars().count());
+        }
+    }
+
+    for (f, cells) in rows {
+        let row = match cells {
+            Some(cells) => cells
+                .iter()
+                .zip(widths)
+                .map(|(c, w)| format!("{:>w$}", c, w = w))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .yellow()
+                .to_string(),
+            None => "<not present>".red().to_string(),
+        };
+        println!("  {:>w$.w$}: {}", f.green(), row, w = label_width);
+    }
+}
+
+/// How many times as long as another execution of it an action has to take
+/// for `cmp` to point it out.
+const SLOW_FACTOR: u32 = 4;
+
+/// The shortest and longest times that `actions` took, if the longest is long
+/// enough (compared to the shortest) to point out.
+fn timing_difference(actions: &[(&String, &Arc<BuildAction<'_>>)]) -> Option<(Duration, Duration)> {
+    let times = actions.iter().filter_map(|(_, a)| a.0.metrics.total_time);
+    let (fastest, slowest) = (times.clone().min()?, times.max()?);
+    // Tiny actions vary a lot relative to how long they take:
+    if slowest < fastest * SLOW_FACTOR || slowest - fastest < Duration::from_millis(100) {
+        return None;
+    }
 
-    let mut rl = Editor::with_config(
-        Config::builder()
-            .auto_add_history(true)
-            .completion_type(CompletionType::List)
-            .completion_prompt_limit(50)
-            .build(),
+    Some((fastest, slowest))
+}
+
+/// Notes (for `cmp`) when an action took much longer in some execution logs
+/// than in others; worth knowing about even if nothing else differs.
+fn print_timing_difference(actions: &[(&String, &Arc<BuildAction<'_>>)], label_width: usize) {
+    let (fastest, slowest) = match timing_difference(actions) {
+        Some(times) => times,
+        None => return,
+    };
+
+    println!(
+        "\n{} {}:",
+        "Timing Difference".bold(),
+        format!(
+            "(up to {:.1}x as long)",
+            slowest.as_secs_f64() / fastest.max(Duration::from_millis(1)).as_secs_f64()
+        )
+        .dimmed()
     );
-    rl.set_helper(Some(ExecLogHelper::new(&maps)));
-    let prompt = format!("{}", "> ".blue());
+    for (f, a) in actions {
+        let time = match a.0.metrics.total_time {
+            Some(t) if t == slowest => human_duration(t).red().to_string(),
+            Some(t) => human_duration(t).yellow().to_string(),
+            None => "<unknown>".dimmed().to_string(),
+        };
+        println!("    {:>w$.w$}: {}", f.dimmed(), time, w = label_width);
+    }
+}
 
-    loop {
-        let inp = rl.readline(prompt.as_str());
-        match inp.as_deref() {
-            Ok("quit") | Ok("q") => break,
-            Err(_) | Ok("help") => {
-                println!(
-                    "usage:
-  - `quit` or `q` to quit
-  - `cmp <output path>` to compare items of interest within the action for an output path
-  - `transitive-cmp <output path>` or `tcmp` to compare all transitive dependencies of an output path
-  - `edges <output path>` *attempts* to determine the inputs that caused the executions of the output path to diverge; may not be accurate
-  - `diff <output path>` to print a textual diff of the fields from `view <output path>`
-  - `view <output path>` to print selected fields of interest from the action for an output path"
-                );
+/// The digest that `map`'s action for `output` recorded for it, if any.
+fn output_digest<'l>(map: &'l Map<'l>, output: &str) -> Option<&'l Digest<'l>> {
+    let o = map
+        .get(output)?
+        .0
+        .actual_outputs
+        .iter()
+        .find(|o| o.path == Path::new(output))?;
+    Some(&o.digest)
+}
 
-                #[cfg(feature = "json-dump-command")]
-                println!("  - `json <output path>` to print the raw JSON blobs for an output path");
-                println!();
-            }
-            #[cfg(feature = "json-dump-command")]
-            Ok(path) if path.starts_with("json ") => {
-                if let Some(v) = get(&maps, path.strip_prefix("json ").unwrap()) {
-                    for (f, a) in v {
-                        println!(
-                            "`{}`:\n{}\n",
-                            f.green(),
-                            serde_json::to_string_pretty(&a.0)?
-                        );
-                    }
-                }
-            }
-            Ok(path) if path.starts_with("cmp ") => {
-                let artifact = path.strip_prefix("cmp ").unwrap();
-                if let Some(v) = get(&maps, artifact) {
-                    print_mismatched(find_mismatched(artifact, v.into_iter()), &maps);
-                }
-            }
-            Ok(path) if path.starts_with("transitive-cmp ") | path.starts_with("tcmp ") => {
-                let artifact = path.split_once(" ").map(|(_, a)| a).unwrap_or("");
-                if get(&maps, artifact).is_none() {
-                    continue;
-                }
+/// Exit code for when `--run` or `--script` find mismatches, or when a
+/// `--require-identical` output differs.
+const EXIT_MISMATCH: i32 = 2;
+/// Exit code for when a `--require-identical` output is missing from some of
+/// the execution logs.
+const EXIT_MISSING: i32 = 3;
+/// Exit code for when `--run`, `--script` or `--require-identical` takes
+/// longer than `--timeout`; the same as `timeout(1)`'s.
+const EXIT_TIMEOUT: i32 = 124;
 
-                print_mismatched(transitive_cmp(artifact, &maps), &maps);
-            }
-            Ok(path) if path.starts_with("edges ") => {
-                let artifact = path.strip_prefix("edges ").unwrap();
-                if get(&maps, artifact).is_none() {
-                    continue;
-                }
+/// Set once `--timeout` has passed.
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
 
-                let (e, i, o) = transitive_cmp(artifact, &maps);
-                let i = i.collect::<Vec<_>>();
-                let o = o.collect::<Vec<_>>();
-                let inps = i.iter().map(|(_, i)| *i).collect::<HashSet<_>>();
-                let outs = o.iter().map(|(_, o)| *o).collect::<HashSet<_>>();
+/// Cancels the running command (see [`cancel`]) once `timeout` has passed,
+/// and stops any commands after it; commands that stop when cancelled print
+/// what they have so far.
+///
+/// Not every command can be cancelled so if the command is still going a few
+/// seconds later, we exit anyways.
+fn start_timeout(timeout: Duration) {
+    const GRACE_PERIOD: Duration = Duration::from_secs(5);
 
-                print_mismatched(
-                    (
-                        e,
-                        i.into_iter().filter(|(_, i)| !outs.contains(i)),
-                        o.into_iter().filter(|(_, o)| !inps.contains(o)),
-                    ),
-                    &maps,
-                );
+    thread::spawn(move || {
+        thread::sleep(timeout);
+        TIMED_OUT.store(true, Ordering::Relaxed);
+        cancel();
+        eprintln!("[{}] Timed out after {:?}.", "ERROR".red(), timeout);
+
+        thread::sleep(GRACE_PERIOD);
+        let _ = io::stdout().flush();
+        std::process::exit(EXIT_TIMEOUT);
+    });
+}
+
+/// Exits with [`EXIT_TIMEOUT`] if `--timeout` has passed.
+fn exit_if_timed_out() {
+    if TIMED_OUT.load(Ordering::Relaxed) {
+        let _ = io::stdout().flush();
+        std::process::exit(EXIT_TIMEOUT);
+    }
+}
+
+/// Checks that each of the `required` outputs has the same digest in every
+/// execution log (`--require-identical`) and prints the mismatches between the
+/// actions for those that don't; returns the exit code.
+///
+/// Outputs that match one of the `allowed` patterns
+/// (`--allow-nondeterministic`) are still reported if they differ but don't
+/// fail the check; missing outputs always do.
+fn require_identical(session: &Session<'_>, required: &[String], allowed: &[Pattern]) -> i32 {
+    let (maps, print_opts) = (session.maps, &session.print_opts);
+    // Keep stdout to just the mismatches when it's meant for other tools:
+    let status = |tag: String, output: &str, note: String| {
+        let line = format!("{} `{}`{}", tag, output.blue(), note.dimmed());
+        if print_opts.format == OutputFormat::Human {
+            println!("{}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+    };
+
+    let (mut identical, mut different, mut allowed_to_differ, mut missing) = (0, 0, 0, 0);
+    for output in required {
+        let digests: Vec<_> = maps
+            .iter()
+            .map(|(f, m)| (f.as_str(), output_digest(m, output)))
+            .collect();
+
+        let absent: Vec<_> = digests
+            .iter()
+            .filter(|(_, d)| d.is_none())
+            .map(|(f, _)| *f)
+            .collect();
+        if !absent.is_empty() {
+            missing += 1;
+            status(
+                format!("{:11}", "[MISSING]").red().to_string(),
+                output,
+                format!(" (not in {})", absent.join(", ")),
+            );
+            continue;
+        }
+
+        if digests.iter().all(|(_, d)| *d == digests[0].1) {
+            identical += 1;
+            status(
+                format!("{:11}", "[IDENTICAL]").green().to_string(),
+                output,
+                String::new(),
+            );
+            continue;
+        }
+
+        if allowed.iter().any(|p| p.matches(output)) {
+            allowed_to_differ += 1;
+            status(
+                format!("{:11}", "[ALLOWED]").yellow().to_string(),
+                output,
+                " (differs, but is allowed to be nondeterministic)".to_owned(),
+            );
+        } else {
+            different += 1;
+            status(
+                format!("{:11}", "[DIFFERENT]").red().to_string(),
+                output,
+                String::new(),
+            );
+        }
+        let actions = maps.iter().map(|(f, m)| (*f, &m[output.as_str()]));
+        print_mismatched(
+            find_mismatched(output, actions, session.ignored_env),
+            maps,
+            print_opts,
+            session.baseline.get(),
+        );
+        session.print_ignored_env();
+        if print_opts.format == OutputFormat::Human {
+            println!();
+        }
+    }
+
+    let summary = format!(
+        "{} of {} required outputs are identical in every log ({} differ, {} are allowed to differ, {} are missing)",
+        identical,
+        required.len(),
+        different,
+        allowed_to_differ,
+        missing
+    );
+    if print_opts.format == OutputFormat::Human {
+        println!("{}", summary.bold());
+    } else {
+        eprintln!("{}", summary);
+    }
+
+    if missing > 0 {
+        EXIT_MISSING
+    } else if different > 0 {
+        EXIT_MISMATCH
+    } else {
+        0
+    }
+}
+
+/// Compares the digest that each execution log has for `output` with the file
+/// at `file` (i.e. the output in the real output tree), for
+/// `verify <output path> <file>`; returns whether any of them differ.
+fn verify_output(
+    maps: &[(&String, Map<'_>)],
+    output: &str,
+    file: &Path,
+    label_width: usize,
+) -> bool {
+    let recorded: Vec<_> = maps
+        .iter()
+        .map(|(f, m)| (*f, output_digest(m, output)))
+        .collect();
+    if recorded.iter().all(|(_, d)| d.is_none()) {
+        println!("`{}` isn't an output in any of the execution logs", output);
+        return false;
+    }
+
+    let actual = match digest_file(file) {
+        Ok(d) => d,
+        Err(e) => {
+            println!("couldn't read `{}`: {}", file.display(), e);
+            return false;
+        }
+    };
+
+    // Room for the file's row, too:
+    let label_width = label_width.max("on disk".len());
+    println!(
+        "  {:>w$.w$}: {}",
+        "on disk".dimmed(),
+        render_digest(&actual, None),
+        w = label_width
+    );
+    let mut mismatched = false;
+    for (f, digest) in recorded {
+        let verdict = match digest {
+            None => "<not present>".red().to_string(),
+            Some(d) if d.hash_function_name != actual.hash_function_name => format!(
+                "{} {}",
+                render_digest(d, None),
+                format!("(can't check {} digests)", d.hash_function_name).yellow()
+            ),
+            Some(d) if d.hash == actual.hash && d.size_bytes == actual.size_bytes => {
+                format!("{} {}", render_digest(d, None), "(matches)".green())
             }
-            Ok(path) if path.starts_with("view ") => {
-                if let Some(v) = get(&maps, path.strip_prefix("view ").unwrap()) {
-                    for (f, a) in v {
-                        println!("`{}`:\n{:#?}", f.green(), a.0);
-                    }
-                }
+            Some(d) => {
+                mismatched = true;
+                format!("{} {}", render_digest(d, None), "(differs)".red())
             }
-            Ok(path) if path.starts_with("diff ") => {
-                if let Some(v) = get(&maps, path.strip_prefix("diff ").unwrap()) {
-                    if v.iter().all(|(_, a)| a.0 == v[0].1 .0) {
-                        println!(
-                            "all executions of `{}` were equivalent",
-                            path.strip_prefix("diff ").unwrap()
-                        );
-                    } else if v.len() == 2 {
-                        println!(
-                            "{}",
-                            prettydiff::text::diff_lines(
-                                &format!("{:#?}", v[0].1 .0),
-                                &format!("{:#?}", v[1].1 .0),
-                            )
-                        );
-                    } else {
-                        println!("can't diff more than 2 things yet, sorry!");
-                    }
-                }
+        };
+        println!("  {:>w$.w$}: {}", f.dimmed(), verdict, w = label_width);
+    }
+
+    mismatched
+}
+
+/// Prints the shortest chain of dependencies from `from` to `to` in each
+/// execution log (just once if it's the same in all of them), for
+/// `path <output path> <input path>`.
+fn print_dependency_path(maps: &[(&String, Map<'_>)], from: &str, to: &str) {
+    if maps.iter().all(|(_, m)| !m.contains_key(from)) {
+        return eprintln!("`{}` not found in any execution log", from);
+    }
+
+    let paths: Vec<_> = maps
+        .iter()
+        .map(|(_, m)| dependency_path(m, from, to))
+        .collect();
+    let print = |path: &Option<Vec<&str>>| match path {
+        Some(path) => {
+            println!("{}", path[0].blue());
+            for p in &path[1..] {
+                println!("  {} {}", "→".dimmed(), p.blue());
             }
-            _ => println!("unrecognized command!"),
+        }
+        None => println!("{}", format!("no path from `{}` to `{}`", from, to).red()),
+    };
+
+    if paths.iter().all(|p| *p == paths[0]) {
+        print(&paths[0]);
+    } else {
+        for ((f, _), path) in maps.iter().zip(&paths) {
+            println!("\n`{}`:", f.green());
+            print(path);
         }
     }
+}
 
-    // Since we're exiting anyways, don't bother cleaning up memory and running
-    // destructors; let the OS take care of it:
-    forget(maps);
+/// What differs between the actions for an output (see `summarize`).
+struct OutputSummary<'l> {
+    output: ArtifactName<'l>,
+    env_vars: Vec<&'l str>,
+    inputs: Vec<&'l Path>,
+    outputs: usize,
+    args: bool,
+    platform: usize,
+    exit: bool,
+    /// With a baseline: the logs that differ from it.
+    differs: BTreeSet<&'l str>,
+}
 
-    Ok(())
+impl OutputSummary<'_> {
+    fn is_identical(&self) -> bool {
+        self.env_vars.is_empty()
+            && self.inputs.is_empty()
+            && self.outputs == 0
+            && !self.args
+            && self.platform == 0
+            && !self.exit
+    }
+}
+
+/// The outputs that are in every execution log.
+fn shared_outputs<'l>(maps: &[(&String, Map<'l>)]) -> Vec<ArtifactName<'l>> {
+    maps[0]
+        .1
+        .keys()
+        .filter(|o| maps[1..].iter().all(|(_, m)| m.contains_key(*o)))
+        .copied()
+        .collect()
+}
+
+/// Splits the outputs that are in every execution log into those whose actions
+/// produced exactly the same outputs in every log and those whose actions
+/// didn't; both are sorted.
+fn partition_reproducible<'l>(
+    maps: &[(&String, Map<'l>)],
+) -> (Vec<ArtifactName<'l>>, Vec<ArtifactName<'l>>) {
+    let shared = shared_outputs(maps);
+    let (mut same, mut different): (Vec<&str>, Vec<&str>) = shared.par_iter().partition(|&&o| {
+        let outputs: Vec<BTreeMap<_, _>> = maps
+            .iter()
+            .map(|(_, m)| {
+                m[o].0
+                    .actual_outputs
+                    .iter()
+                    .map(|i| (i.path, &i.digest))
+                    .collect()
+            })
+            .collect();
+        outputs.iter().all(|d| *d == outputs[0])
+    });
+    same.sort_unstable();
+    different.sort_unstable();
+
+    (same, different)
+}
+
+/// Lists the outputs (that are in every execution log) whose actions produced
+/// exactly the same outputs in every log, or, with `reproducible` unset, the
+/// ones whose actions didn't (`reproducible` and `nonreproducible`); returns
+/// whether any outputs aren't reproducible.
+fn print_reproducible(maps: &[(&String, Map<'_>)], reproducible: bool) -> bool {
+    let (same, different) = partition_reproducible(maps);
+    let shared = same.len() + different.len();
+
+    let listed = if reproducible { &same } else { &different };
+    for o in listed {
+        println!("{}", o);
+    }
+    if !listed.is_empty() {
+        println!();
+    }
+    if !reproducible {
+        println!("{} outputs not reproducible", different.len().red());
+    }
+    println!(
+        "{}/{} outputs reproducible ({:.1}%)",
+        same.len().green(),
+        shared,
+        if shared == 0 {
+            100.0
+        } else {
+            same.len() as f64 * 100.0 / shared as f64
+        }
+    );
+
+    !different.is_empty()
+}
+
+/// The paths of the outputs in `map` that have each hash, along with the
+/// output's digest (for `dupes`).
+///
+/// Empty outputs are left out: they all have the same digest and would make
+/// one big (uninteresting) group.
+fn outputs_by_hash<'l>(
+    map: &'l Map<'l>,
+) -> HashMap<&'l Hash, (&'l Digest<'l>, BTreeSet<&'l Path>)> {
+    let actions: HashMap<_, _> = map.values().map(|a| (Arc::as_ptr(a), a)).collect();
+
+    let mut index: HashMap<_, (_, BTreeSet<_>)> = HashMap::new();
+    for a in actions.values() {
+        for o in
+            a.0.actual_outputs
+                .iter()
+                .filter(|o| o.digest.size_bytes != 0)
+        {
+            index
+                .entry(&o.digest.hash)
+                .or_insert_with(|| (&o.digest, BTreeSet::new()))
+                .1
+                .insert(o.path);
+        }
+    }
+
+    index
+}
+
+/// Lists the groups of distinct output paths that have the same contents (by
+/// hash) within each execution log (`dupes`); these are often redundant
+/// actions or unexpected aliasing.
+///
+/// With `cross`, lists the contents that are at different paths in different
+/// logs instead (`dupes --cross`).
+fn print_dupes(maps: &[(&String, Map<'_>)], cross: bool) {
+    let indices: Vec<_> = maps.par_iter().map(|(_, m)| outputs_by_hash(m)).collect();
+
+    if !cross {
+        for ((f, _), index) in maps.iter().zip(indices) {
+            let mut groups: Vec<_> = index.into_values().filter(|(_, p)| p.len() > 1).collect();
+            groups.sort_unstable_by_key(|(_, p)| p.iter().next().copied());
+
+            println!("`{}`:", f.green());
+            if groups.is_empty() {
+                println!("  {}", "no outputs have the same contents".dimmed());
+            }
+            for (digest, paths) in groups {
+                println!("  {}", render_digest(digest, None));
+                for p in paths {
+                    println!("    {}", p.display().blue());
+                }
+            }
+        }
+
+        return;
+    }
+
+    // The paths that each log has each hash at:
+    let mut by_hash: HashMap<_, (_, Vec<(&str, BTreeSet<_>)>)> = HashMap::new();
+    for ((f, _), index) in maps.iter().zip(indices) {
+        for (hash, (digest, paths)) in index {
+            by_hash
+                .entry(hash)
+                .or_insert_with(|| (digest, Vec::new()))
+                .1
+                .push((f.as_str(), paths));
+        }
+    }
+
+    let mut groups: Vec<_> = by_hash
+        .into_values()
+        .filter(|(_, logs)| logs.len() > 1 && logs.iter().any(|(_, p)| *p != logs[0].1))
+        .map(|(digest, logs)| {
+            let mut paths: BTreeMap<_, Vec<_>> = BTreeMap::new();
+            for (f, ps) in logs {
+                for p in ps {
+                    paths.entry(p).or_default().push(f);
+                }
+            }
+            (digest, paths)
+        })
+        .collect();
+    groups.sort_unstable_by_key(|(_, p)| p.keys().next().copied());
+
+    if groups.is_empty() {
+        println!("no contents are at different paths in different logs");
+    }
+    for (digest, paths) in groups {
+        println!("  {}", render_digest(digest, None));
+        for (p, logs) in paths {
+            println!(
+                "    {} {}",
+                p.display().blue(),
+                format!("(in {})", logs.join(", ")).dimmed()
+            );
+        }
+    }
+}
+
+/// Compares the actions for every output that's in all of the execution logs
+/// and prints how many differ, along with the env vars and inputs that differ
+/// most often; returns whether any differ.
+///
+/// This is `summary [--full | --compact] [--mnemonic <mnemonic>]`: with
+/// `--full`, what differs for each output is listed as well and with
+/// `--mnemonic`, only the outputs of actions with that mnemonic (in any of the
+/// logs) are compared. With a `baseline`, how many outputs each of the other
+/// logs differs from it in is also printed.
+///
+/// `--compact` prints just one line per output that differs instead: its path
+/// and how many env vars, inputs and outputs differ (i.e. `E:2 I:5 O:1`).
+fn summarize(
+    maps: &[(&String, Map<'_>)],
+    args: &str,
+    ignored_env: &IgnoredEnv,
+    baseline: Option<&str>,
+) -> bool {
+    const TOP: usize = 10;
+    const USAGE: &str = "usage: `summary [--full | --compact] [--mnemonic <mnemonic>]`";
+
+    let (mut full, mut compact, mut mnemonic) = (false, false, None);
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "--full" if !compact => full = true,
+            "--compact" if !full => compact = true,
+            "--mnemonic" => match words.next() {
+                Some(m) => mnemonic = Some(m),
+                None => {
+                    println!("{}", USAGE);
+                    return false;
+                }
+            },
+            _ => {
+                println!("{}", USAGE);
+                return false;
+            }
+        }
+    }
+
+    let has_mnemonic = |output: &str| match mnemonic {
+        Some(m) => maps
+            .iter()
+            .any(|(_, map)| map.get(output).is_some_and(|a| a.0.mnemonic == m)),
+        None => true,
+    };
+    if let Some(m) = mnemonic {
+        if !maps
+            .iter()
+            .any(|(_, map)| map.values().any(|a| a.0.mnemonic == m))
+        {
+            println!("no actions with the mnemonic `{}`", m);
+            return false;
+        }
+    }
+
+    let mut shared = shared_outputs(maps);
+    shared.retain(|o| has_mnemonic(o));
+    let mut summaries: Vec<_> = shared
+        .par_iter()
+        .map(|&output| {
+            let actions = maps.iter().map(|(f, m)| (*f, &m[output]));
+            let mismatches = find_mismatched(output, actions, ignored_env);
+            OutputSummary {
+                output,
+                env_vars: mismatches.env_vars.iter().map(|m| m.key).collect(),
+                inputs: mismatches.inputs.iter().map(|m| m.key).collect(),
+                outputs: mismatches.outputs.len(),
+                args: !mismatches.args.is_empty(),
+                platform: mismatches.platform.len(),
+                exit: !mismatches.exit_status.is_empty(),
+                differs: baseline
+                    .map(|b| mismatches.deviating_logs(b))
+                    .unwrap_or_default(),
+            }
+        })
+        .filter(|s| !s.is_identical())
+        .collect();
+    summaries.sort_unstable_by_key(|s| s.output);
+
+    if compact {
+        for s in &summaries {
+            println!(
+                "{} {}",
+                s.output,
+                format!(
+                    "E:{} I:{} O:{}",
+                    s.env_vars.len(),
+                    s.inputs.len(),
+                    s.outputs
+                )
+                .dimmed()
+            );
+        }
+        return !summaries.is_empty();
+    }
+
+    let all = maps
+        .iter()
+        .flat_map(|(_, m)| m.keys())
+        .filter(|o| has_mnemonic(o))
+        .collect::<HashSet<_>>();
+    let unshared = all.len() - shared.len();
+    if let Some(m) = mnemonic {
+        println!("{} {}:", "Mnemonic".bold(), format!("`{}`", m).bold());
+    }
+    println!(
+        "{:>8} outputs are in every log ({} aren't)",
+        shared.len().yellow(),
+        unshared
+    );
+    println!(
+        "{:>8} are identical",
+        (shared.len() - summaries.len()).green()
+    );
+    println!("{:>8} differ", summaries.len().red());
+    if let Some(b) = baseline {
+        println!("\n{} {}:", "Compared to".bold(), format!("`{}`", b).bold());
+        for (f, _) in maps.iter().filter(|(f, _)| *f != b) {
+            let differ = summaries
+                .iter()
+                .filter(|s| s.differs.contains(f.as_str()))
+                .count();
+            println!("{:>8} differ in {}", differ.red(), f.blue());
+        }
+    }
+
+    fn print_top<'l>(name: &str, it: impl Iterator<Item = &'l str>) {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for t in it {
+            *counts.entry(t).or_default() += 1;
+        }
+        if counts.is_empty() {
+            return;
+        }
+
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_unstable_by_key(|(t, c)| (std::cmp::Reverse(*c), *t));
+        println!("\n{} {}:", name.bold(), "(number of outputs)".dimmed());
+        for (t, c) in counts.iter().take(TOP) {
+            println!("{:>8} {}", c.yellow(), t.blue());
+        }
+        if counts.len() > TOP {
+            println!("{}", format!("…and {} more", counts.len() - TOP).dimmed());
+        }
+    }
+    print_top(
+        "Most Mismatched Environment Variables",
+        summaries.iter().flat_map(|s| s.env_vars.iter().copied()),
+    );
+    print_top(
+        "Most Mismatched Inputs",
+        summaries
+            .iter()
+            .flat_map(|s| s.inputs.iter().map(|i| i.to_str().unwrap())),
+    );
+
+    if full && !summaries.is_empty() {
+        println!("\n{}:", "Mismatched Outputs".bold());
+        for s in &summaries {
+            let mut differences = Vec::new();
+            if !s.env_vars.is_empty() {
+                differences.push(format!("env vars: {}", s.env_vars.len()));
+            }
+            if s.platform != 0 {
+                differences.push(format!("platform properties: {}", s.platform));
+            }
+            if s.args {
+                differences.push("arguments".to_owned());
+            }
+            if s.exit {
+                differences.push("exit status".to_owned());
+            }
+            if !s.inputs.is_empty() {
+                differences.push(format!("inputs: {}", s.inputs.len()));
+            }
+            if s.outputs != 0 {
+                differences.push(format!("outputs: {}", s.outputs));
+            }
+            if baseline.is_some() {
+                let logs: Vec<_> = s.differs.iter().copied().collect();
+                differences.push(format!("in {}", logs.join(", ")));
+            }
+            println!(
+                "  `{}` {}",
+                s.output.blue(),
+                format!("({})", differences.join(", ")).dimmed()
+            );
+        }
+    }
+
+    !summaries.is_empty()
+}
+
+/// Compares the actions for every output that's in all of the execution logs
+/// (like `summary`) and writes what differs to `path` as a Markdown document
+/// for `export-md <file>`; returns whether any outputs differ.
+///
+/// The document has the counts of outputs that are identical and that differ,
+/// a table of the outputs that differ (with how many of each kind of mismatch
+/// they have) and a collapsible section for each with its mismatches.
+fn export_markdown(
+    maps: &[(&String, Map<'_>)],
+    path: &str,
+    ignored_env: &IgnoredEnv,
+    baseline: Option<&str>,
+) -> bool {
+    const KINDS: [&str; 7] = [
+        "Env vars",
+        "Platform",
+        "Arguments",
+        "Exit status",
+        "Inputs",
+        "Outputs",
+        "Runner",
+    ];
+
+    let shared = shared_outputs(maps);
+    let mut reports: Vec<_> = shared
+        .par_iter()
+        .filter_map(|&output| {
+            let actions = maps.iter().map(|(f, m)| (*f, &m[output]));
+            let mut mismatches = find_mismatched(output, actions, ignored_env);
+            if mismatches.is_empty() {
+                return None;
+            }
+            mismatches.sort();
+            Some((output, MismatchReport::new(mismatches, maps, baseline)))
+        })
+        .collect();
+    reports.sort_unstable_by_key(|(o, _)| *o);
+
+    let write = |mut out: io::BufWriter<File>| -> io::Result<()> {
+        writeln!(out, "# Execution Log Comparison\n")?;
+        let logs: Vec<_> = maps
+            .iter()
+            .map(|(f, _)| match baseline {
+                Some(b) if b == f.as_str() => format!("{} (baseline)", md_code(f)),
+                _ => md_code(f),
+            })
+            .collect();
+        writeln!(out, "Compared: {}.\n", logs.join(", "))?;
+        writeln!(
+            out,
+            "{} outputs are in every log: {} are identical and {} differ.",
+            shared.len(),
+            shared.len() - reports.len(),
+            reports.len()
+        )?;
+        if reports.is_empty() {
+            return out.flush();
+        }
+
+        writeln!(out, "\n## Outputs That Differ\n")?;
+        writeln!(out, "| Output | {} |", KINDS.join(" | "))?;
+        writeln!(out, "|---|{}", "---:|".repeat(KINDS.len()))?;
+        for (output, report) in &reports {
+            write!(out, "| {} |", md_code(output))?;
+            for (_, c) in report.counts() {
+                write!(out, " {} |", c)?;
+            }
+            writeln!(out)?;
+        }
+
+        writeln!(out, "\n## Mismatches\n")?;
+        for (output, report) in &reports {
+            let counts: Vec<_> = report
+                .counts()
+                .iter()
+                .zip(KINDS)
+                .filter(|((_, c), _)| *c != 0)
+                .map(|((_, c), k)| format!("{}: {}", k.to_lowercase(), c))
+                .collect();
+            writeln!(out, "<details>")?;
+            writeln!(
+                out,
+                "<summary><code>{}</code> ({})</summary>\n",
+                output
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;"),
+                counts.join(", ")
+            )?;
+            report.write_markdown(&mut out)?;
+            writeln!(out, "\n</details>\n")?;
+        }
+        out.flush()
+    };
+    match File::create(path).and_then(|f| write(io::BufWriter::new(f))) {
+        Ok(()) => eprintln!("wrote the comparison to `{}`", path),
+        Err(e) => println!("couldn't write `{}`: {}", path, e),
+    }
+
+    !reports.is_empty()
+}
+
+/// The formats of execution log that we know how to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum LogFormat {
+    /// `--execution_log_json_file`: concatenated JSON objects.
+    Json,
+    /// `--execution_log_compact_file`: length delimited `ExecLogEntry` protos.
+    Compact,
+    /// `--execution_log_binary_file`: length delimited `SpawnExec` protos.
+    Binary,
+}
+
+impl LogFormat {
+    /// Guesses the format of a log from its first few bytes.
+    ///
+    /// JSON logs start with an object. The protobuf formats start with a length
+    /// prefix and then the first field of the first message: an entry ID or
+    /// the invocation entry (fields 1 and 2) for compact logs and the command
+    /// line (field 1) for binary logs.
+    fn detect(start: &[u8]) -> Self {
+        if let Some(b'{') | None = start.iter().find(|b| !b.is_ascii_whitespace()) {
+            return LogFormat::Json;
+        }
+
+        let tag = start
+            .iter()
+            .position(|b| b & 0x80 == 0)
+            .and_then(|i| start.get(i + 1));
+        match tag {
+            Some(0x08) | Some(0x12) => LogFormat::Compact,
+            Some(0x0a) => LogFormat::Binary,
+            _ => LogFormat::Json,
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Json => write!(fmt, "JSON"),
+            LogFormat::Compact => write!(fmt, "compact"),
+            LogFormat::Binary => write!(fmt, "binary"),
+        }
+    }
+}
+
+/// An execution log that's been opened but not parsed yet.
+enum LogInput {
+    /// A JSON log that's been read (or mapped) into memory; the parsed actions
+    /// borrow from this.
+    ///
+    /// This isn't checked to be UTF-8 up front; each action is as it's parsed.
+    Buffered(&'static [u8]),
+    /// A log that's parsed as it's read.
+    Streamed(Box<dyn Read + Send>),
+    /// An entry from the `--cache` for a log; the actions borrow from this.
+    Cached(&'static [u8]),
+}
+
+/// Where an execution log came from.
+#[derive(Debug, Clone)]
+struct LogSource {
+    path: PathBuf,
+    bytes: usize,
+    format: LogFormat,
+    /// The size and modification time of the file when it was read; `None`
+    /// if it isn't a file.
+    stamp: Option<(u64, SystemTime)>,
+    /// Every action that produced the outputs in the log that more than one
+    /// action produced.
+    producers: Producers<'static>,
+}
+
+/// The number of distinct actions in `map`.
+///
+/// Actions with multiple outputs are shared between those outputs' entries in
+/// the map so we count unique `Arc`s rather than entries.
+fn distinct_actions(map: &Map<'_>) -> usize {
+    map.values().map(Arc::as_ptr).collect::<HashSet<_>>().len()
+}
+
+/// The hash functions used by the digests in `map`.
+fn hash_functions<'l>(map: &Map<'l>) -> BTreeSet<&'l str> {
+    map.values()
+        .flat_map(|a| a.0.inputs.iter().chain(a.0.actual_outputs.iter()))
+        .map(|i| i.digest.hash_function_name)
+        .collect()
+}
+
+/// Whether the logs that use the hash functions in `functions` (one set per
+/// log) don't all use the same ones; logs without any digests don't count.
+fn hash_functions_differ(functions: &[BTreeSet<&str>]) -> bool {
+    let used = || functions.iter().filter(|f| !f.is_empty());
+    used().zip(used().skip(1)).any(|(a, b)| a != b)
+}
+
+/// Lists the output paths in the first execution log (or, with `--all`, in any
+/// of them) for `list [--all] [--limit <n>] [pattern]`.
+///
+/// Patterns with glob characters in them are matched as globs; anything else
+/// is matched as a substring. Only the first `n` paths (100 by default; 0 for
+/// no limit) are printed.
+fn list_outputs(maps: &[(&String, Map<'_>)], args: &str) {
+    const USAGE: &str = "usage: `list [--all] [--limit <n>] [pattern]`";
+
+    let (mut all, mut limit, mut pattern) = (false, 100, None);
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "--all" => all = true,
+            "--limit" => match words.next().and_then(|n| n.parse().ok()) {
+                Some(n) => limit = n,
+                None => return println!("{}", USAGE),
+            },
+            p if pattern.is_none() => pattern = Some(p),
+            _ => return println!("{}", USAGE),
+        }
+    }
+
+    let matches: Box<dyn Fn(&str) -> bool> = match pattern {
+        Some(p) if p.contains(&['*', '?', '['][..]) => match Pattern::new(p) {
+            Ok(p) => Box::new(move |o| p.matches(o)),
+            Err(e) => return println!("invalid pattern `{}`: {}", p, e),
+        },
+        Some(p) => Box::new(move |o| o.contains(p)),
+        None => Box::new(|_| true),
+    };
+
+    // Which logs each output is in:
+    let mut outputs: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (f, m) in if all { maps } else { &maps[..1] } {
+        for o in m.keys().filter(|o| matches(o)) {
+            outputs.entry(o).or_default().push(f);
+        }
+    }
+
+    let limit = if limit == 0 { outputs.len() } else { limit };
+    for (o, logs) in outputs.iter().take(limit) {
+        if !all {
+            println!("{}", o);
+        } else if logs.len() == maps.len() {
+            println!("{} {}", o, "(all logs)".dimmed());
+        } else {
+            println!("{} {}", o, format!("({})", logs.join(", ")).dimmed());
+        }
+    }
+    if outputs.len() > limit {
+        println!(
+            "{}",
+            format!("…and {} more", outputs.len() - limit).dimmed()
+        );
+    }
+}
+
+/// Lists the outputs whose actions have a command argument (or, with `--env`,
+/// an env var value) that matches a regex, for `grep [-i] [--env] <regex>`.
+///
+/// The matching arguments are printed after each output with the matches
+/// highlighted; outputs whose actions only match in some of the logs are
+/// marked with the logs they match in.
+fn grep_actions(maps: &[(&String, Map<'_>)], args: &[String]) {
+    const USAGE: &str = "usage: `grep [-i] [--env] <regex>`";
+
+    // Anything that isn't one of our flags (i.e. `-ffast-math`) starts the
+    // regex; `--` can be used to search for one of the flags:
+    let (mut ignore_case, mut env, mut rest) = (false, false, args);
+    while let Some((flag, r)) = rest.split_first() {
+        match flag.as_str() {
+            "-i" | "--ignore-case" => ignore_case = true,
+            "--env" => env = true,
+            "--" => {
+                rest = r;
+                break;
+            }
+            _ => break,
+        }
+        rest = r;
+    }
+    if rest.is_empty() {
+        return println!("{}", USAGE);
+    }
+
+    let pattern = rest.join(" ");
+    let regex = match RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+    {
+        Ok(r) => r,
+        Err(e) => return println!("invalid regex `{}`: {}", pattern, e),
+    };
+    let highlight = |s: &str| {
+        let (mut out, mut last, mut matched) = (String::new(), 0, false);
+        for m in regex.find_iter(s) {
+            out.push_str(&s[last..m.start()]);
+            out.push_str(&m.as_str().red().to_string());
+            last = m.end();
+            matched = true;
+        }
+        out.push_str(&s[last..]);
+
+        matched.then_some(out)
+    };
+
+    // The (highlighted) matching values for each output and the logs that it
+    // matched in:
+    let mut matches: BTreeMap<&str, (Vec<String>, Vec<&str>)> = BTreeMap::new();
+    for (f, m) in maps {
+        for (o, a) in m {
+            let found: Vec<_> = if env {
+                a.0.environment_variables
+                    .iter()
+                    .filter_map(|e| highlight(e.value).map(|v| format!("{}={}", e.name, v)))
+                    .collect()
+            } else {
+                a.0.command_args
+                    .iter()
+                    .filter_map(|a| highlight(a))
+                    .collect()
+            };
+            if found.is_empty() {
+                continue;
+            }
+
+            let (values, logs) = matches.entry(o).or_default();
+            for v in found {
+                if !values.contains(&v) {
+                    values.push(v);
+                }
+            }
+            logs.push(f);
+        }
+    }
+
+    if matches.is_empty() {
+        let what = if env { "env var" } else { "argument" };
+        println!("no actions have an {} that matches `{}`", what, pattern);
+    }
+    for (o, (values, logs)) in matches {
+        if logs.len() == maps.len() {
+            println!("{}: {}", o, values.join(" "));
+        } else {
+            let logs = format!("({})", logs.join(", "));
+            println!("{}: {} {}", o, values.join(" "), logs.dimmed());
+        }
+    }
+}
+
+/// Prints what we ingested for each execution log.
+///
+/// Handy when results are surprising and a parsing or labelling issue is
+/// suspected.
+fn print_logs(maps: &[(&String, Map<'_>)], sources: &[LogSource]) {
+    for (idx, ((name, map), source)) in maps.iter().zip(sources).enumerate() {
+        let actions = distinct_actions(map);
+        let mut hash_functions: Vec<_> = map
+            .values()
+            .flat_map(|a| a.0.inputs.iter().chain(a.0.actual_outputs.iter()))
+            .map(|i| i.digest.hash_function_name)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        hash_functions.sort_unstable();
+
+        println!("[{}] {}", idx, name.green());
+        println!("    {:>15}: {}", "path".dimmed(), source.path.display());
+        println!("    {:>15}: {}", "format".dimmed(), source.format);
+        println!(
+            "    {:>15}: {}",
+            "size".dimmed(),
+            human_size(source.bytes).yellow()
+        );
+        println!("    {:>15}: {}", "actions".dimmed(), actions.yellow());
+        println!("    {:>15}: {}", "outputs".dimmed(), map.len().yellow());
+        println!(
+            "    {:>15}: {}",
+            "hash functions".dimmed(),
+            hash_functions.join(", ")
+        );
+    }
+}
+
+/// Lists the outputs that more than one action in each execution log (or just
+/// the one labelled `label`) produced, along with how many actions did, for
+/// `dup-outputs [<label>]`.
+fn write_dup_outputs(
+    out: &mut dyn io::Write,
+    maps: &[(&String, Map<'_>)],
+    sources: &[LogSource],
+    label: Option<&str>,
+) -> io::Result<()> {
+    for ((f, _), source) in maps.iter().zip(sources) {
+        if label.is_some_and(|l| l != f.as_str()) {
+            continue;
+        }
+
+        let mut outputs: Vec<_> = source
+            .producers
+            .iter()
+            .map(|(o, actions)| (*o, actions.len()))
+            .collect();
+        outputs.sort_unstable();
+
+        writeln!(out, "`{}`:", f.green())?;
+        if outputs.is_empty() {
+            writeln!(
+                out,
+                "  {}",
+                "no outputs are produced by more than one action".dimmed()
+            )?;
+        }
+        for (output, actions) in outputs {
+            writeln!(
+                out,
+                "  {} {}",
+                output.underline(),
+                format!("({} actions)", actions).dimmed()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints a line for each execution log (its index, label and where it was
+/// loaded from); a shorter version of [`print_logs`] for startup.
+fn print_log_banner(maps: &[(&String, Map<'_>)], sources: &[LogSource]) {
+    for (idx, ((name, map), source)) in maps.iter().zip(sources).enumerate() {
+        let path = source.path.display().to_string();
+        println!(
+            "[{}] {}{} ({} actions, {} outputs)",
+            idx,
+            name.green(),
+            if **name == path {
+                String::new()
+            } else {
+                format!(": {}", path)
+            },
+            distinct_actions(map).yellow(),
+            map.len().yellow(),
+        );
+    }
+    println!();
+}
+
+/// Opens the execution log at `path`, returning a reader for it, its size (if
+/// known) and its format.
+///
+/// With the `http` feature, `path` can also be an `http(s)://` URL to fetch the
+/// log from and `-` reads the log from stdin. Compressed logs (gzip or zstd) are
+/// decompressed transparently; we don't know how big these (or logs from stdin)
+/// are until they've been read.
+fn open_log(path: &Path) -> eyre::Result<(Box<dyn Read + Send>, Option<u64>, LogFormat)> {
+    let (reader, len): (Box<dyn Read + Send>, _) = match path.to_str() {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+            #[cfg(feature = "http")]
+            {
+                let resp = ureq::get(url).call()?;
+                let len = resp.header("Content-Length").and_then(|l| l.parse().ok());
+                (Box::new(resp.into_reader()), len)
+            }
+
+            #[cfg(not(feature = "http"))]
+            return Err(eyre::eyre!(
+                "can't fetch `{}`; loading execution logs from URLs requires the `http` feature",
+                url
+            ));
+        }
+        _ if is_stdin(path) => (Box::new(io::stdin()), None),
+        _ => {
+            let file = File::open(path)?;
+            let len = file.metadata()?.len();
+            (Box::new(file), Some(len))
+        }
+    };
+
+    let mut reader = BufReader::new(reader);
+    let (reader, len): (Box<dyn Read + Send>, _) =
+        match Compression::detect(path, reader.fill_buf()?) {
+            Some(Compression::Gzip) => (Box::new(MultiGzDecoder::new(reader)), None),
+            Some(Compression::Zstd) => (Box::new(zstd::Decoder::with_buffer(reader)?), None),
+            None => (Box::new(reader), len),
+        };
+
+    let mut reader = BufReader::new(reader);
+    let format = LogFormat::detect(reader.fill_buf()?);
+
+    Ok((Box::new(reader), len, format))
+}
+
+/// How an execution log is compressed.
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Guesses from the first bytes of a log (`magic`) and its extension.
+    fn detect(path: &Path, magic: &[u8]) -> Option<Self> {
+        let extension = path.extension().and_then(|e| e.to_str());
+        if magic.starts_with(&[0x1f, 0x8b]) || extension == Some("gz") {
+            Some(Self::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) || extension == Some("zst") {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Maps an (uncompressed) execution log on disk into memory, instead of
+/// reading it into a buffer, so that the parsed actions can borrow from the
+/// file's pages directly; this roughly halves the memory that big logs need.
+///
+/// Returns `None` for logs that can't be mapped (stdin, URLs, compressed or
+/// empty logs). Like the maps, the mapping is never unmapped.
+///
+/// This is only done with `--mmap`: the parsed actions (and the completions at
+/// the prompt) point into the file for as long as the tool runs, so a log that
+/// is rewritten (i.e. by a rebuild) out from under them crashes the tool or
+/// changes strings that are still borrowed.
+fn map_log(path: &Path) -> eyre::Result<Option<&'static [u8]>> {
+    if is_stdin(path) || !path.is_file() {
+        return Ok(None);
+    }
+
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+
+    // Safety: the log mustn't be changed (or truncated) while we're running.
+    // Nothing stops Bazel from rewriting it (i.e. on a rebuild) so this is up
+    // to whoever passed `--mmap`; `reload` isn't allowed with it since the
+    // whole point of that is to pick up logs that were rewritten.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    if Compression::detect(path, &map).is_some() {
+        return Ok(None);
+    }
+
+    Ok(Some(&**Box::leak(Box::new(map))))
+}
+
+/// Whether `path` is `-`, i.e. stdin.
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// The extensions of the files in a directory (given instead of an execution
+/// log) that are loaded as execution logs.
+const LOG_EXTENSIONS: &[&str] = &[".json", ".json.gz", ".json.zst"];
+
+/// Expands the globs in `files` (that aren't paths of their own) into the
+/// paths they match, and then the directories into the execution logs in them
+/// (and, with `recursive`, in their subdirectories), in order of their path;
+/// each of the latter is labelled with its path relative to the directory.
+/// Other files are left as they are, without a label.
+///
+/// The other files in these directories are skipped, with a warning.
+fn expand_args(files: &[PathBuf], recursive: bool) -> eyre::Result<Vec<(PathBuf, Option<String>)>> {
+    fn walk(
+        dir: &Path,
+        recursive: bool,
+        logs: &mut Vec<PathBuf>,
+        skipped: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        for path in entries {
+            let name = path.file_name().unwrap().to_string_lossy();
+            if path.is_dir() {
+                if recursive {
+                    walk(&path, recursive, logs, skipped)?;
+                } else {
+                    skipped.push(path);
+                }
+            } else if LOG_EXTENSIONS.iter().any(|e| name.ends_with(e)) {
+                logs.push(path);
+            } else {
+                skipped.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Shells usually expand globs before we ever see them but not all of them
+    // do (i.e. on Windows):
+    let mut matched = Vec::new();
+    for f in files {
+        let pattern = match f.to_str() {
+            Some(p)
+                if p.contains(&['*', '?', '['][..])
+                    && !f.exists()
+                    && !p.starts_with("http://")
+                    && !p.starts_with("https://") =>
+            {
+                p
+            }
+            _ => {
+                matched.push(f.clone());
+                continue;
+            }
+        };
+
+        let paths = glob::glob(pattern)
+            .map_err(|e| eyre::eyre!("`{}` isn't a valid glob: {}", pattern, e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| eyre::eyre!("couldn't expand `{}`: {}", pattern, e))?;
+        if paths.is_empty() {
+            return Err(eyre::eyre!("`{}` doesn't match any files", pattern));
+        }
+        matched.extend(paths);
+    }
+
+    let mut expanded = Vec::new();
+    for f in &matched {
+        if is_stdin(f) || !f.is_dir() {
+            expanded.push((f.clone(), None));
+            continue;
+        }
+
+        let (mut logs, mut skipped) = (Vec::new(), Vec::new());
+        walk(f, recursive, &mut logs, &mut skipped)
+            .map_err(|e| eyre::eyre!("couldn't read the directory `{}`: {}", f.display(), e))?;
+
+        let relative = |p: &Path| p.strip_prefix(f).unwrap().to_string_lossy().into_owned();
+        if !skipped.is_empty() {
+            eprintln!(
+                "[{}] Skipped these in `{}` since they don't look like execution logs ({}){}:",
+                "WARNING".yellow(),
+                f.display(),
+                LOG_EXTENSIONS
+                    .iter()
+                    .map(|e| format!("`*{}`", e))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if recursive || !skipped.iter().any(|p| p.is_dir()) {
+                    ""
+                } else {
+                    "; pass `--recursive` to load the logs in subdirectories"
+                },
+            );
+            for p in &skipped {
+                eprintln!("  - {}", relative(p));
+            }
+            eprintln!();
+        }
+        if logs.is_empty() {
+            return Err(eyre::eyre!(
+                "the directory `{}` doesn't have any execution logs in it",
+                f.display()
+            ));
+        }
+
+        expanded.extend(logs.into_iter().map(|p| {
+            let label = relative(&p);
+            (p, Some(label))
+        }));
+    }
+
+    Ok(expanded)
+}
+
+/// Reads in an execution log (of size `len`, if known), showing the bytes read
+/// so far on `pb`.
+///
+/// Reading a big log can take a while (before we even start parsing it) so we
+/// show progress. `pb` is left as it was (but for its style) for whatever
+/// comes next.
+fn read_log(reader: impl Read, len: Option<u64>, pb: &ProgressBar) -> eyre::Result<Vec<u8>> {
+    pb.set_length(len.unwrap_or(0));
+    pb.set_style(ProgressStyle::default_spinner().template(if len.is_some() {
+        "{spinner:.green} {msg:20!.green} [{elapsed_precise}] {bytes}/{total_bytes} ({bytes_per_sec})"
+    } else {
+        "{spinner:.green} {msg:20!.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})"
+    }));
+    pb.enable_steady_tick(100);
+
+    let mut c = Vec::with_capacity(len.unwrap_or(0) as _);
+    let read = pb.wrap_read(reader).read_to_end(&mut c);
+    pb.disable_steady_tick();
+    pb.reset();
+    read?;
+
+    Ok(c)
+}
+
+/// The loaded execution logs and settings that commands run against.
+struct Session<'l> {
+    maps: &'l [(&'l String, Map<'l>)],
+    sources: &'l [LogSource],
+    print_opts: PrintOpts,
+    /// Whether commands are typed at the prompt (rather than coming from
+    /// `--run` or `--script`); progress is only shown if so.
+    interactive: bool,
+    /// Whether long output from commands at the prompt is shown in a pager.
+    page: bool,
+    /// Whether any command has reported mismatches (for `--run`'s exit code).
+    found_mismatches: Cell<bool>,
+    ignored_env: &'l IgnoredEnv,
+    /// The log (by label) that the others are compared against, if any
+    /// (`--baseline` or `baseline <label>`).
+    baseline: Cell<Option<&'l str>>,
+    multi: MultiPolicy,
+    /// `--label-width`; for commands that label the actions they compare
+    /// with more than the log's label (`cmp` with `--multi all`).
+    max_label_width: usize,
+    /// How many artifacts a traversal compares before asking whether to
+    /// carry on (`--confirm-after`); `None` with `--yes`.
+    confirm_after: Option<usize>,
+    /// Built on first use (by `rdeps`); see [`Session::reverse_deps`].
+    reverse_deps: OnceCell<Vec<ReverseDeps<'l>>>,
+    /// Built on first use (by `find-hash`); see [`Session::hash_index`].
+    hash_index: OnceCell<Vec<HashIndex<'l>>>,
+}
+
+impl<'l> Session<'l> {
+    fn new(
+        maps: &'l [(&'l String, Map<'l>)],
+        sources: &'l [LogSource],
+        opts: &Opts,
+        ignored_env: &'l IgnoredEnv,
+        baseline: Option<&str>,
+    ) -> Self {
+        let print_opts = PrintOpts {
+            no_wrap: opts.no_wrap,
+            group_values: opts.group_values,
+            format: opts.format,
+            mark_generated: false,
+            label_width: label_width(maps.iter().map(|(n, _)| *n), opts.label_width),
+        };
+
+        Session {
+            maps,
+            sources,
+            print_opts,
+            interactive: opts.run.is_none() && opts.script.is_none(),
+            page: !opts.no_pager && io::stdout().is_terminal(),
+            found_mismatches: Cell::new(false),
+            ignored_env,
+            baseline: Cell::new(baseline.and_then(|b| find_log(maps, b))),
+            multi: opts.multi,
+            max_label_width: opts.label_width,
+            confirm_after: (!opts.yes).then_some(opts.confirm_after),
+            reverse_deps: OnceCell::new(),
+            hash_index: OnceCell::new(),
+        }
+    }
+
+    /// Every action that produced `output` in each execution log that has it,
+    /// labelled with the log and, for logs with more than one, which of them
+    /// it is (i.e. `a.json (2/3)`).
+    fn producers(&self, output: &str) -> Vec<(String, Arc<BuildAction<'l>>)> {
+        let mut out = Vec::new();
+        for ((f, m), source) in self.maps.iter().zip(self.sources) {
+            match source.producers.get(output) {
+                Some(actions) => out.extend(
+                    actions
+                        .iter()
+                        .enumerate()
+                        .map(|(i, a)| (format!("{} ({}/{})", f, i + 1, actions.len()), a.clone())),
+                ),
+                None => out.extend(m.get(output).map(|a| ((*f).clone(), a.clone()))),
+            }
+        }
+
+        out
+    }
+
+    fn record(&self, mismatched: bool) {
+        self.found_mismatches
+            .set(self.found_mismatches.get() || mismatched);
+    }
+
+    /// Runs `traverse` (a [`transitive_cmp`]) with `--confirm-after`'s
+    /// confirmation, returning whether it was told to stop; outside of the
+    /// prompt there's no one to ask, so going past it is an error instead
+    /// (unless `--yes` was passed).
+    fn confirmed_traversal<T>(
+        &self,
+        traverse: impl FnOnce(Option<(usize, &(dyn Fn(usize) -> bool + Sync))>) -> T,
+    ) -> eyre::Result<(T, bool)> {
+        let interactive = self.interactive;
+        let declined = Mutex::new(None);
+        let confirm = |n: usize| {
+            let carry_on = interactive && ask_to_carry_on(n);
+            if !carry_on {
+                *declined.lock().unwrap() = Some(n);
+            }
+            carry_on
+        };
+
+        let found = traverse(self.confirm_after.map(|n| (n, &confirm as _)));
+        match declined.into_inner().unwrap() {
+            Some(n) if !interactive => Err(eyre::eyre!(
+                "stopped after comparing {} artifacts; pass `--yes` to compare more than `--confirm-after` ({}) of them",
+                n,
+                self.confirm_after.unwrap()
+            )),
+            declined => Ok((found, declined.is_some())),
+        }
+    }
+
+    /// Notes which env vars had mismatches that were ignored (by the last
+    /// comparison), if any.
+    fn print_ignored_env(&self) {
+        let suppressed = self.ignored_env.take_suppressed();
+        if suppressed.is_empty() || self.print_opts.format != OutputFormat::Human {
+            return;
+        }
+
+        let names: Vec<_> = suppressed.iter().map(|n| format!("${}", n)).collect();
+        println!(
+            "\n{}",
+            format!(
+                "(ignored mismatches in {} env var{}: {})",
+                names.len(),
+                if names.len() == 1 { "" } else { "s" },
+                names.join(", ")
+            )
+            .dimmed()
+        );
+    }
+
+    /// The reverse dependency index of each execution log.
+    fn reverse_deps(&self) -> &[ReverseDeps<'l>] {
+        self.reverse_deps
+            .get_or_init(|| self.maps.par_iter().map(|(_, m)| reverse_deps(m)).collect())
+    }
+
+    /// The [`HashIndex`] for each execution log; built the first time it's
+    /// needed since most sessions never look anything up by hash.
+    fn hash_index(&self) -> &[HashIndex<'l>] {
+        self.hash_index
+            .get_or_init(|| self.maps.par_iter().map(|(_, m)| hash_index(m)).collect())
+    }
+}
+
+/// The outputs of the actions that consume each input path; the reverse of the
+/// edges that `tcmp` follows.
+type ReverseDeps<'l> = HashMap<&'l str, Vec<ArtifactName<'l>>>;
+
+/// The inputs and outputs (by path, along with which of the two they are) that
+/// have each hash, for `find-hash`; ordered so that hashes can be looked up by
+/// prefix.
+type HashIndex<'l> = BTreeMap<&'l Hash, (&'l Digest<'l>, BTreeSet<(&'l Path, &'static str)>)>;
+
+fn hash_index<'l>(map: &'l Map<'l>) -> HashIndex<'l> {
+    let actions: HashMap<_, _> = map.values().map(|a| (Arc::as_ptr(a), a)).collect();
+
+    let mut index = HashIndex::new();
+    for a in actions.values() {
+        let items = a.0.inputs.iter().map(|i| (i, "input"));
+        let items = items.chain(a.0.actual_outputs.iter().map(|o| (o, "output")));
+        for (item, kind) in items {
+            index
+                .entry(&item.digest.hash)
+                .or_insert_with(|| (&item.digest, BTreeSet::new()))
+                .1
+                .insert((item.path, kind));
+        }
+    }
+
+    index
+}
+
+/// Lists the inputs and outputs in every execution log whose hash starts with
+/// `prefix` (`find-hash <hex>`); handy for going from a hash in a remote cache
+/// back to the artifact it's for.
+fn find_hash(session: &Session<'_>, prefix: &str) {
+    /// Shorter prefixes match too much to be useful.
+    const MIN_PREFIX: usize = 8;
+
+    let prefix = prefix.to_ascii_lowercase();
+    if prefix.len() < MIN_PREFIX || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        println!(
+            "`{}` isn't a hash (or a prefix of one that's at least {} hex digits long)",
+            prefix, MIN_PREFIX
+        );
+        return;
+    }
+
+    // Every hash that starts with the prefix sorts at or after the prefix
+    // itself (padded out to a whole number of bytes):
+    let start = Hash::from_hex(&format!("{:0<1$}", prefix, prefix.len().div_ceil(2) * 2)).unwrap();
+
+    let mut found = false;
+    for ((f, _), index) in session.maps.iter().zip(session.hash_index()) {
+        let matches: Vec<_> = index
+            .range::<&Hash, _>(&start..)
+            .take_while(|(h, _)| format!("{:x}", h).starts_with(&prefix))
+            .collect();
+        if matches.is_empty() {
+            continue;
+        }
+
+        found = true;
+        println!("`{}`:", f.green());
+        for (_, (digest, items)) in matches {
+            println!("  {}", render_digest(digest, None));
+            for (path, kind) in items {
+                println!(
+                    "    {} {}",
+                    path.display().blue(),
+                    format!("({})", kind).dimmed()
+                );
+            }
+        }
+    }
+
+    if !found {
+        println!(
+            "no inputs or outputs have a hash that starts with `{}`",
+            prefix
+        );
+    }
+}
+
+fn reverse_deps<'l>(map: &Map<'l>) -> ReverseDeps<'l> {
+    let actions: HashMap<_, _> = map.values().map(|a| (Arc::as_ptr(a), a)).collect();
+
+    let mut index = ReverseDeps::new();
+    for a in actions.values() {
+        let inputs: HashSet<_> = a.0.inputs.iter().map(|i| i.path).collect();
+        for i in inputs {
+            index
+                .entry(i.to_str().unwrap())
+                .or_default()
+                .extend(a.0.listed_outputs.iter().copied());
+        }
+    }
+
+    index
+}
+
+/// What to do after running a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Quit,
+    /// Re-read the execution logs and start a new session (`reload`).
+    Reload,
+    /// Add another execution log (a path and a label) and start a new session
+    /// (`load`).
+    Load(PathBuf, String),
+    /// Change the label of an execution log (by index) and start a new
+    /// session (`label`).
+    Relabel(usize, String),
+}
+
+fn print_help() {
+    println!(
+        "usage:
+  - `quit` or `q` to quit
+  - `cmp <output path>` to compare items of interest within the action for an output path
+  - `transitive-cmp <output path> [--filter <glob>] [--depth <n>] [--dot <file>]` or `tcmp` to compare all transitive dependencies of an output path, optionally only following inputs that match a pattern (or up to some depth) and writing the graph of what was compared to a DOT file
+  - `edges <output path>` to list the mismatches where the executions of the output path started to diverge: the actions whose inputs match but whose outputs don't, and mismatched source files
+  - `diff <output path> [--side-by-side]` to print a textual diff of the fields from `view <output path>` (against the first execution log, when there are more than 2)
+  - `view <output path> [--json] [--all]` to print selected fields of interest from the action for an output path (as JSON, keyed by execution log, with `--json`; for every action that produced it in each log, with `--all`)
+  - `timing <output path>` to print how long the action for an output path took in each execution log (in total, running, and waiting to run)
+  - `info <output path>` to print a one line summary (mnemonic, inputs, outputs, exit code, runner, etc.) of the action for an output path in each execution log, even if only some of them have it
+  - `manifest-diff` to list the outputs whose digests changed between 2 execution logs as `<old hash> <new hash> <output path>`
+  - `logs` to print what was loaded from each execution log (path, size, number of actions, etc.)
+  - `reload` to read the execution logs that have changed (i.e. from a rebuild) again, without restarting
+  - `load <path> [--label <name>]` to add another execution log (labelled with its path, by default) to the ones being compared
+  - `label <index> <new name>` to change the label that an execution log (by its index, as shown by `logs`) is shown with
+  - `list [--all] [--limit <n>] [pattern]` to list the output paths in the first execution log (or in any of them, with `--all`), optionally only those that match a substring or glob
+  - `stats` to print the number of outputs and distinct actions in each execution log
+  - `summary [--full | --compact] [--mnemonic <mnemonic>]` to compare every output that's in all the execution logs and report how many differ (and, with `--full`, what differs for each; with `--compact`, just a line per output that differs); `--mnemonic` only compares the outputs of actions with that mnemonic (i.e. `CppCompile`)
+  - `reproducible` to list the outputs (that are in every execution log) whose actions produced identical outputs in every log, and `nonreproducible` to list the ones whose actions didn't
+  - `dupes [--cross]` to list the distinct output paths that have the same contents within each execution log (or, with `--cross`, the contents that are at different paths in different logs)
+  - `dup-outputs [<label>]` to list the outputs that more than one action produced in each execution log (or in the one with a label), with how many actions did
+  - `intra-cmp <output path> <output path>` (experimental) to compare the actions for two different output paths within each execution log
+  - `replay <file>` to run the commands in a file (i.e. a saved history file), one per line
+  - `mnemonic <mnemonic>` to list the output paths produced by actions with a mnemonic (i.e. `CppLink`)
+  - `grep [-i] [--env] <regex>` to list the output paths whose actions have a command argument (or, with `--env`, an env var value) that matches a regex
+  - `find-hash <hex>` to list the inputs and outputs in each execution log whose digest has a hash (or a prefix of one, at least 8 hex digits long)
+  - `export-md <file>` to compare every output that's in all the execution logs (like `summary`) and write what differs to a Markdown file
+  - `rdeps <input path>` to list the output paths of the actions that consume an input
+  - `path <output path> <input path>` to print the shortest chain of dependencies from an output to one of its (transitive) inputs
+  - `verify <output path> <file>` to check the digests that the execution logs have for an output against a file (i.e. the output in `bazel-bin`); only SHA-256 digests can be checked
+  - `baseline [<label> | --clear]` to compare the other execution logs against one of them (by its label, as shown by `logs`) so that mismatches show how each differs from it, or to print the current baseline
+  - `ignore env [<name>]` to stop comparing an env var (or the env vars that match a glob, i.e. `BAZEL_*`), or to list the ones that are ignored"
+    );
+
+    #[cfg(feature = "json-dump-command")]
+    println!("  - `json <output path>` to print the raw JSON blobs for an output path");
+    println!(
+        "
+paths can also be given as a fuzzy query (i.e. `cmp ~foo.o`) that's used if it matches exactly one path"
+    );
+    println!();
+}
+
+/// Runs a single command (as typed at the prompt) against `session`.
+fn run_command(
+    line: &str,
+    session: &Session<'_>,
+    helper: &ExecLogHelper<'_>,
+) -> eyre::Result<Flow> {
+    let line = match helper.resolve_fuzzy_query(line.to_owned()) {
+        Some(line) => line,
+        None => return Ok(Flow::Continue),
+    };
+
+    // Arguments can be quoted (i.e. for paths with spaces); past this point
+    // they're separated by single spaces and only `intra-cmp`, which takes two
+    // paths, needs to tell them apart:
+    let words = match split_args(&line) {
+        (_, true) => {
+            println!("unterminated quote or escape in `{}`", line);
+            return Ok(Flow::Continue);
+        }
+        (words, false) => words.into_iter().map(|(_, w)| w).collect::<Vec<_>>(),
+    };
+    let line = words.join(" ");
+    let (maps, sources, print_opts) = (session.maps, session.sources, &session.print_opts);
+
+    match line.as_str() {
+        "quit" | "q" => return Ok(Flow::Quit),
+        "help" => print_help(),
+        "reload" if session.interactive => return Ok(Flow::Reload),
+        "reload" => println!("`reload` only works at the prompt"),
+        #[cfg(feature = "json-dump-command")]
+        path if path.starts_with("json ") => {
+            if let Some(v) = get(maps, path.strip_prefix("json ").unwrap()) {
+                for (f, a) in v {
+                    println!(
+                        "`{}`:\n{}\n",
+                        f.green(),
+                        serde_json::to_string_pretty(&a.0)?
+                    );
+                }
+            }
+        }
+        path if path.starts_with("cmp ") => {
+            let artifact = path.strip_prefix("cmp ").unwrap();
+            if let Some(v) = get(maps, artifact) {
+                // With `--multi all`, each of the actions that produced the
+                // output in a log is compared as if it was from a log of its
+                // own (like `intra-cmp`):
+                let producers = match session.multi {
+                    MultiPolicy::All => session.producers(artifact),
+                    _ => Vec::new(),
+                };
+                let pairing: Vec<(&String, Map)> = producers
+                    .iter()
+                    .map(|(l, a)| (l, std::iter::once((artifact, a.clone())).collect()))
+                    .collect();
+                let (v, maps, print_opts) = if pairing.len() > v.len() {
+                    let v = pairing.iter().map(|(l, m)| (*l, &m[artifact])).collect();
+                    let print_opts = PrintOpts {
+                        label_width: label_width(
+                            pairing.iter().map(|(l, _)| *l),
+                            session.max_label_width,
+                        ),
+                        ..*print_opts
+                    };
+                    (v, &pairing[..], print_opts)
+                } else {
+                    (v, maps, *print_opts)
+                };
+
+                if print_opts.format == OutputFormat::Human {
+                    print_input_count_mismatch(&v, print_opts.label_width);
+                    print_timing_difference(&v, print_opts.label_width);
+                }
+                session.record(print_mismatched(
+                    find_mismatched(artifact, v.into_iter(), session.ignored_env),
+                    maps,
+                    &print_opts,
+                    session.baseline.get().and_then(|b| find_log(maps, b)),
+                ));
+                session.print_ignored_env();
+            }
+        }
+        path if path.starts_with("transitive-cmp ") | path.starts_with("tcmp ") => {
+            const USAGE: &str =
+                "usage: `tcmp <output path> [--filter <glob>] [--depth <n>] [--dot <file>]`";
+
+            let (mut artifact, mut filter, mut depth, mut dot) = (Vec::new(), None, None, None);
+            let mut args = words[1..].iter();
+            while let Some(arg) = args.next() {
+                let value = match arg.as_str() {
+                    "--filter" => &mut filter,
+                    "--depth" => &mut depth,
+                    "--dot" => &mut dot,
+                    _ => {
+                        artifact.push(arg.as_str());
+                        continue;
+                    }
+                };
+                match args.next() {
+                    Some(v) => *value = Some(v),
+                    None => {
+                        println!("{}", USAGE);
+                        return Ok(Flow::Continue);
+                    }
+                }
+            }
+            let artifact = artifact.join(" ");
+            let depth = match depth.map(|d| d.parse::<usize>()).transpose() {
+                Ok(d) => d,
+                Err(_) => {
+                    println!("{}", USAGE);
+                    return Ok(Flow::Continue);
+                }
+            };
+            let filter = match filter
+                .map(|f| Pattern::new(f).map_err(|e| (f, e)))
+                .transpose()
+            {
+                Ok(f) => f,
+                Err((f, e)) => {
+                    println!("invalid filter `{}`: {}", f, e);
+                    return Ok(Flow::Continue);
+                }
+            };
+            if get(maps, &artifact).is_none() {
+                return Ok(Flow::Continue);
+            }
+
+            let (
+                TransitiveCmp {
+                    mismatches,
+                    leaves,
+                    graph,
+                    ..
+                },
+                declined,
+            ) = session.confirmed_traversal(|confirm| {
+                transitive_cmp(
+                    &artifact,
+                    maps,
+                    filter.as_ref(),
+                    depth,
+                    session.ignored_env,
+                    session.interactive,
+                    confirm,
+                )
+            })?;
+            // Whatever was compared before a cancellation is still printed (and
+            // that can be cancelled in turn):
+            let partial = reset_cancelled() || declined;
+            session.record(print_mismatched(
+                mismatches,
+                maps,
+                &PrintOpts {
+                    mark_generated: true,
+                    ..*print_opts
+                },
+                session.baseline.get(),
+            ));
+            session.print_ignored_env();
+            if print_opts.format == OutputFormat::Human {
+                print_leaves(&leaves);
+                if let (Some(depth), false) = (depth, graph.truncated.is_empty()) {
+                    println!("\n{}", format!("(truncated at depth {})", depth).dimmed());
+                }
+                if partial {
+                    println!(
+                        "\n{}",
+                        "(cancelled; not every artifact was compared)".yellow()
+                    );
+                }
+            }
+            if let Some(dot) = dot {
+                match File::create(dot).and_then(|f| graph.write_dot(io::BufWriter::new(f))) {
+                    Ok(()) => eprintln!("wrote the graph to `{}`", dot),
+                    Err(e) => println!("couldn't write `{}`: {}", dot, e),
+                }
+            }
+        }
+        path if path.starts_with("edges ") => {
+            let artifact = path.strip_prefix("edges ").unwrap();
+            if get(maps, artifact).is_none() {
+                return Ok(Flow::Continue);
+            }
+
+            let (
+                TransitiveCmp {
+                    mismatches: all,
+                    leaves,
+                    origins: mut mismatches,
+                    graph,
+                },
+                declined,
+            ) = session.confirmed_traversal(|confirm| {
+                transitive_cmp(
+                    artifact,
+                    maps,
+                    None,
+                    None,
+                    session.ignored_env,
+                    session.interactive,
+                    confirm,
+                )
+            })?;
+            let partial = reset_cancelled() || declined;
+
+            // Divergence starts at the actions whose inputs match but whose
+            // outputs don't, and at the mismatched inputs that no two logs
+            // have actions for (i.e. source files) since there's nothing
+            // further to compare for those:
+            let compared: HashSet<_> = graph.nodes.iter().map(|(n, _)| Path::new(n)).collect();
+            mismatches.inputs = all
+                .inputs
+                .into_iter()
+                .filter(|m| !compared.contains(m.key))
+                .collect();
+            // Several origins can share a mismatched env var (etc.); it's only
+            // listed once, like in `tcmp`:
+            mismatches.sort();
+            mismatches.env_vars.dedup_by_key(|m| m.key);
+            mismatches.platform.dedup_by_key(|m| m.key);
+            mismatches.outputs.dedup_by_key(|m| m.key);
+
+            session.record(print_mismatched(
+                mismatches,
+                maps,
+                &PrintOpts {
+                    mark_generated: true,
+                    ..*print_opts
+                },
+                session.baseline.get(),
+            ));
+            session.print_ignored_env();
+            if print_opts.format == OutputFormat::Human {
+                print_leaves(&leaves);
+                if partial {
+                    println!(
+                        "\n{}",
+                        "(cancelled; not every artifact was compared)".yellow()
+                    );
+                }
+            }
+        }
+        path if path.starts_with("view ") => {
+            let (flags, artifact): (Vec<_>, Vec<_>) = words[1..]
+                .iter()
+                .partition(|w| *w == "--json" || *w == "--all");
+            let artifact = artifact
+                .into_iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let (json, all) = (
+                flags.iter().any(|f| *f == "--json"),
+                flags.iter().any(|f| *f == "--all"),
+            );
+
+            if let Some(v) = get(maps, &artifact) {
+                // With `--all`, every action that produced the output in each
+                // log is shown, not just the one that's compared:
+                let v: Vec<(String, Arc<BuildAction>)> = if all {
+                    session.producers(&artifact)
+                } else {
+                    v.into_iter().map(|(f, a)| (f.clone(), a.clone())).collect()
+                };
+                if json {
+                    let actions: BTreeMap<_, _> = v.iter().map(|(f, a)| (f, &a.0)).collect();
+                    println!("{}", serde_json::to_string_pretty(&actions).unwrap());
+                } else {
+                    for (f, a) in v {
+                        println!("`{}`:\n{:#?}", f.green(), a.0);
+                    }
+                }
+            }
+        }
+        path if path.starts_with("diff ") => {
+            let artifact = path.strip_prefix("diff ").unwrap();
+            let (artifact, side_by_side) = match artifact.strip_suffix(" --side-by-side") {
+                Some(a) => (a, true),
+                None => (artifact, false),
+            };
+
+            if let Some(v) = get(maps, artifact) {
+                let _ = write_diff(&mut io::stdout().lock(), artifact, &v, side_by_side);
+            }
+        }
+        "logs" => print_logs(maps, sources),
+        "list" => list_outputs(maps, ""),
+        "reproducible" => session.record(print_reproducible(maps, true)),
+        "dupes" => print_dupes(maps, false),
+        "dupes --cross" => print_dupes(maps, true),
+        "dup-outputs" => {
+            let _ = write_dup_outputs(&mut io::stdout().lock(), maps, sources, None);
+        }
+        path if path.starts_with("dup-outputs ") => {
+            let label = path.strip_prefix("dup-outputs ").unwrap();
+            match find_log(maps, label) {
+                Some(l) => {
+                    let _ = write_dup_outputs(&mut io::stdout().lock(), maps, sources, Some(l));
+                }
+                None => println!(
+                    "no execution log is labelled `{}` (see `logs` for the labels)",
+                    label
+                ),
+            }
+        }
+        path if path.starts_with("export-md ") => {
+            session.record(export_markdown(
+                maps,
+                path.strip_prefix("export-md ").unwrap(),
+                session.ignored_env,
+                session.baseline.get(),
+            ));
+            session.print_ignored_env();
+        }
+        path if path.starts_with("find-hash ") => {
+            find_hash(session, path.strip_prefix("find-hash ").unwrap())
+        }
+        "nonreproducible" => session.record(print_reproducible(maps, false)),
+        args if args == "summary" || args.starts_with("summary ") => {
+            session.record(summarize(
+                maps,
+                &args["summary".len()..],
+                session.ignored_env,
+                session.baseline.get(),
+            ));
+            session.print_ignored_env();
+        }
+        args if args.starts_with("list ") => list_outputs(maps, &args["list ".len()..]),
+        args if args == "grep" || args.starts_with("grep ") => grep_actions(maps, &words[1..]),
+        "stats" => {
+            for (f, m) in maps.iter() {
+                println!(
+                    "{:>w$.w$}: {} outputs produced by {} distinct actions",
+                    f.dimmed(),
+                    m.len().yellow(),
+                    distinct_actions(m).yellow(),
+                    w = print_opts.label_width,
+                );
+            }
+        }
+        path if path.starts_with("intra-cmp ") => {
+            let (a, b) = match &words[..] {
+                [_, a, b] => (a.as_str(), b.as_str()),
+                _ => {
+                    println!("usage: `intra-cmp <output path> <output path>`");
+                    return Ok(Flow::Continue);
+                }
+            };
+
+            // Within each log, we pretend that the actions for `a` and
+            // `b` are the same artifact in two different logs so that we
+            // can reuse the usual comparison machinery. The paths can be
+            // too long to label the values with (and could be cut down to
+            // the same label) so they're just called `A` and `B`:
+            let labels = ["A".to_owned(), "B".to_owned()];
+            if print_opts.format == OutputFormat::Human {
+                println!("{}: `{}`", labels[0].blue(), a);
+                println!("{}: `{}`", labels[1].blue(), b);
+            }
+            for (f, m) in maps.iter() {
+                println!("\n`{}`:", f.green());
+                let (action_a, action_b) = match (m.get(a), m.get(b)) {
+                    (Some(x), Some(y)) => (x, y),
+                    _ => {
+                        println!("{}", "<not present>".red());
+                        continue;
+                    }
+                };
+
+                let pairing: Vec<(&String, Map)> = vec![
+                    (&labels[0], std::iter::once((a, action_a.clone())).collect()),
+                    (&labels[1], std::iter::once((a, action_b.clone())).collect()),
+                ];
+                session.record(print_mismatched(
+                    find_mismatched(
+                        a,
+                        pairing.iter().map(|(l, m)| (*l, &m[a])),
+                        session.ignored_env,
+                    ),
+                    &pairing,
+                    &PrintOpts {
+                        label_width: label_width(labels.iter(), 20),
+                        ..*print_opts
+                    },
+                    None,
+                ));
+                session.print_ignored_env();
+            }
+        }
+        "manifest-diff" => {
+            if maps.len() != 2 {
+                println!("can only produce a manifest diff for exactly 2 things, sorry!");
+                return Ok(Flow::Continue);
+            }
+
+            // Only the final digests of the outputs that both logs know
+            // about are considered; nothing else about the actions is.
+            let digest = |a: &'_ BuildAction<'_>, output: &str| {
+                a.0.actual_outputs
+                    .iter()
+                    .find(|i| i.path == Path::new(output))
+                    .map(|i| i.digest.hash.clone())
+            };
+            let mut changed: Vec<_> = maps[0]
+                .1
+                .iter()
+                .filter_map(|(output, old)| {
+                    let new = maps[1].1.get(output)?;
+                    match (digest(old, output), digest(new, output)) {
+                        (Some(o), Some(n)) if o != n => Some((*output, o, n)),
+                        _ => None,
+                    }
+                })
+                .collect();
+            changed.sort();
+
+            for (output, old, new) in changed {
+                println!("{:x} {:x} {}", old, new, output);
+            }
+        }
+        path if path.starts_with("timing ") => print_timing(
+            maps,
+            path.strip_prefix("timing ").unwrap(),
+            print_opts.label_width,
+        ),
+        path if path.starts_with("info ") => print_info(
+            maps,
+            path.strip_prefix("info ").unwrap(),
+            print_opts.label_width,
+        ),
+        args if args.starts_with("verify ") => match &words[..] {
+            [_, output, file] => session.record(verify_output(
+                maps,
+                output,
+                Path::new(file),
+                print_opts.label_width,
+            )),
+            _ => println!("usage: `verify <output path> <file>`"),
+        },
+        args if args.starts_with("path ") => match &words[..] {
+            [_, from, to] => print_dependency_path(maps, from, to),
+            _ => println!("usage: `path <output path> <input path>`"),
+        },
+        path if path.starts_with("rdeps ") => {
+            let input = path.strip_prefix("rdeps ").unwrap();
+
+            // Which logs each consumer is in:
+            let mut consumers: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+            for ((f, _), index) in maps.iter().zip(session.reverse_deps()) {
+                for o in index.get(input).into_iter().flatten() {
+                    let logs = consumers.entry(o).or_default();
+                    if logs.last() != Some(&f.as_str()) {
+                        logs.push(f);
+                    }
+                }
+            }
+
+            if consumers.is_empty() {
+                println!("no actions consume `{}`", input);
+            }
+            for (o, logs) in consumers {
+                if logs.len() == maps.len() {
+                    println!("{}", o);
+                } else {
+                    println!("{} {}", o, format!("({})", logs.join(", ")).dimmed());
+                }
+            }
+        }
+        "ignore env" => {
+            let patterns = session.ignored_env.patterns();
+            if patterns.is_empty() {
+                println!("no env vars are ignored");
+            }
+            for p in patterns {
+                println!("{}", p.as_str());
+            }
+        }
+        path if path.starts_with("ignore env ") => {
+            let pattern = path.strip_prefix("ignore env ").unwrap();
+            match Pattern::new(pattern) {
+                Ok(p) => session.ignored_env.add(p),
+                Err(e) => println!("invalid pattern `{}`: {}", pattern, e),
+            }
+        }
+        "baseline" => match session.baseline.get() {
+            Some(b) => println!("{}", b),
+            None => println!("there's no baseline; every log is compared with every other"),
+        },
+        "baseline --clear" => session.baseline.set(None),
+        path if path.starts_with("baseline ") => {
+            let label = path.strip_prefix("baseline ").unwrap();
+            match find_log(maps, label) {
+                Some(b) => session.baseline.set(Some(b)),
+                None => println!(
+                    "no execution log is labelled `{}` (see `logs` for the labels)",
+                    label
+                ),
+            }
+        }
+        path if path.starts_with("load ") => {
+            const USAGE: &str = "usage: `load <path> [--label <name>]`";
+            if !session.interactive {
+                println!("`load` only works at the prompt");
+                return Ok(Flow::Continue);
+            }
+
+            let (mut file, mut label) = (Vec::new(), None);
+            let mut args = words[1..].iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--label" => match args.next() {
+                        Some(l) => label = Some(l.clone()),
+                        None => {
+                            println!("{}", USAGE);
+                            return Ok(Flow::Continue);
+                        }
+                    },
+                    _ => file.push(arg.as_str()),
+                }
+            }
+            let file = file.join(" ");
+            if file.is_empty() {
+                println!("{}", USAGE);
+                return Ok(Flow::Continue);
+            }
+            if is_stdin(Path::new(&file)) {
+                println!("execution logs can't be loaded from stdin at the prompt");
+                return Ok(Flow::Continue);
+            }
+
+            let label = label.unwrap_or_else(|| file.clone());
+            if find_log(maps, &label).is_some() {
+                println!(
+                    "there's already an execution log labelled `{}`; pick another with `--label <name>`",
+                    label
+                );
+                return Ok(Flow::Continue);
+            }
+
+            return Ok(Flow::Load(file.into(), label));
+        }
+        path if path.starts_with("label ") => {
+            if !session.interactive {
+                println!("`label` only works at the prompt");
+                return Ok(Flow::Continue);
+            }
+
+            let (idx, label) = match words.get(1).map(|i| i.parse::<usize>()) {
+                Some(Ok(idx)) if words.len() > 2 => (idx, words[2..].join(" ")),
+                _ => {
+                    println!("usage: `label <index> <new name>`");
+                    return Ok(Flow::Continue);
+                }
+            };
+            if idx >= maps.len() {
+                println!(
+                    "there's no execution log with the index {} (see `logs` for the indices)",
+                    idx
+                );
+                return Ok(Flow::Continue);
+            }
+            match find_log(maps, &label) {
+                Some(_) if *maps[idx].0 == label => {}
+                Some(_) => println!("there's already an execution log labelled `{}`", label),
+                None => return Ok(Flow::Relabel(idx, label)),
+            }
+        }
+        path if path.starts_with("mnemonic ") => {
+            let mnemonic = path.strip_prefix("mnemonic ").unwrap();
+            let mut outputs: Vec<_> = maps
+                .iter()
+                .flat_map(|(_, m)| m.iter())
+                .filter(|(_, a)| a.0.mnemonic == mnemonic)
+                .map(|(o, _)| *o)
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            outputs.sort_unstable();
+
+            if outputs.is_empty() {
+                println!("no actions with the mnemonic `{}`", mnemonic);
+            }
+            for o in outputs {
+                println!("{}", o);
+            }
+        }
+        path if path.starts_with("replay ") => {
+            let file = path.strip_prefix("replay ").unwrap();
+            let commands = match read_to_string(file) {
+                Ok(c) => c,
+                Err(e) => {
+                    println!("couldn't read `{}`: {}", file, e);
+                    return Ok(Flow::Continue);
+                }
+            };
+
+            run_script(&commands, session, helper, true)?;
+        }
+        _ => println!("unrecognized command!"),
+    }
+
+    Ok(Flow::Continue)
+}
+
+/// Runs each line of `commands` (i.e. a `--script` or a file passed to
+/// `replay`) and returns how many of them failed.
+///
+/// Works with saved history files as well as hand written lists of commands;
+/// blank lines and `#` comments are skipped and `quit` just stops the script.
+/// Unless `fail_fast` is set, a failed command is reported and the rest of the
+/// commands still run.
+fn run_script(
+    commands: &str,
+    session: &Session<'_>,
+    helper: &ExecLogHelper<'_>,
+    fail_fast: bool,
+) -> eyre::Result<usize> {
+    let mut failed = 0;
+    for cmd in commands
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        if cancelled() || TIMED_OUT.load(Ordering::Relaxed) {
+            break;
+        }
+
+        println!("{}{}", "> ".blue(), cmd);
+        if cmd.starts_with("replay ") {
+            println!("not replaying a nested `replay`");
+            continue;
+        }
+        if cmd == "reload" || cmd.starts_with("load ") || cmd.starts_with("label ") {
+            println!("not changing the execution logs (or their labels) from a script");
+            continue;
+        }
+
+        match run_command(cmd, session, helper) {
+            Ok(Flow::Quit) => break,
+            Ok(Flow::Continue | Flow::Reload | Flow::Load(..) | Flow::Relabel(..)) => {}
+            Err(e) if fail_fast => return Err(e),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Where the REPL's history is kept between sessions:
+/// `$XDG_CACHE_HOME/bazel-execlog-cmp/history` (or `~/.cache/...`).
+fn history_path() -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".cache")))?;
+
+    Some(cache.join("bazel-execlog-cmp").join("history"))
+}
+
+/// CLI tool that helps compare Bazel execution logs.
+#[derive(Debug, StructOpt)]
+struct Opts {
+    /// Warn if the number of outputs in one execution log is more than this
+    /// many times the number in another.
+    #[structopt(long, default_value = "2")]
+    size_mismatch_factor: f64,
+
+    /// Don't warn when the execution logs have very different numbers of
+    /// outputs.
+    #[structopt(long)]
+    no_size_mismatch_warning: bool,
+
+    /// Compare execution logs that don't have any actions in them (i.e. empty
+    /// files) instead of stopping with an error.
+    #[structopt(long)]
+    allow_empty: bool,
+
+    /// Also load the execution logs in the subdirectories of directories that
+    /// are given instead of logs.
+    #[structopt(long)]
+    recursive: bool,
+
+    /// Which action to use for an output that more than one action in a log
+    /// produced: the `first` or `last` one in the log, or `all` of them (`cmp`
+    /// compares each one as if it was from a log of its own).
+    #[structopt(long, default_value = "last", possible_values = MultiPolicy::NAMES)]
+    multi: MultiPolicy,
+
+    /// Don't shorten long paths and values to fit the width of the terminal.
+    #[structopt(long)]
+    no_wrap: bool,
+
+    /// Don't show the output of commands that's longer than the terminal in
+    /// `$PAGER` (`less -R` by default).
+    #[structopt(long)]
+    no_pager: bool,
+
+    /// The most columns that the execution logs' labels (in front of each
+    /// log's value) take up; longer labels are cut off.
+    #[structopt(long, default_value = "40", value_name = "columns")]
+    label_width: usize,
+
+    /// Only show the first this many hex digits of hashes (followed by `…`);
+    /// hashes are still compared in full.
+    #[structopt(long, value_name = "digits")]
+    hash_len: Option<usize>,
+
+    /// Don't compare this env var; can be given more than once and can be a
+    /// glob (i.e. `BAZEL_*`).
+    #[structopt(long, value_name = "name", number_of_values = 1)]
+    ignore_env: Vec<Pattern>,
+
+    /// When to color output: `auto` (only when printing to a terminal and
+    /// `NO_COLOR` isn't set), `always` or `never`.
+    #[structopt(long, default_value = "auto", possible_values = ColorChoice::NAMES)]
+    color: ColorChoice,
+
+    /// Don't color output; same as `--color never`.
+    #[structopt(long)]
+    no_color: bool,
+
+    /// Keep the parsed execution logs in this directory so that later
+    /// sessions with the same (unchanged) logs can skip parsing them.
+    #[structopt(long, parse(from_os_str), value_name = "dir")]
+    cache: Option<PathBuf>,
+
+    /// When printing mismatches, list each distinct value once along with the
+    /// execution logs that have it (instead of one line per log).
+    #[structopt(long)]
+    group_values: bool,
+
+    /// Treat this execution log (by its label, as shown by `logs`) as the
+    /// reference: mismatches are reported as how each of the other logs
+    /// differs from it.
+    #[structopt(long, value_name = "label")]
+    baseline: Option<String>,
+
+    /// Complete paths by prefix instead of fuzzy matching them; useful on
+    /// slow connections.
+    #[structopt(long)]
+    no_fuzzy: bool,
+
+    /// Replace the configuration directory in `bazel-out/<config>/...` paths
+    /// (in inputs, outputs and command arguments) with `<config>` so that
+    /// artifacts line up across configurations (i.e. `k8-opt` and
+    /// `k8-fastbuild`).
+    #[structopt(long)]
+    normalize_paths: bool,
+
+    /// Parse the execution logs as they're read instead of reading them into
+    /// memory first; uses much less memory on big logs.
+    #[structopt(long)]
+    low_memory: bool,
+
+    /// Map uncompressed execution logs into memory instead of reading them in;
+    /// uses about half the memory on big logs.
+    ///
+    /// The logs mustn't change while the tool is running: rebuilding (which
+    /// rewrites them) makes later commands crash or see garbage, and `reload`
+    /// isn't available.
+    #[structopt(long, conflicts_with = "low-memory")]
+    mmap: bool,
+
+    /// Print the fields in the execution logs that aren't modeled (and so
+    /// aren't compared); useful for noticing when Bazel adds new fields.
+    #[structopt(long)]
+    report_unknown_fields: bool,
+
+    /// How to print the results of commands like `cmp`: `human`, `json` (one
+    /// object per command, for other tools) or `csv`.
+    #[structopt(long, default_value = "human", possible_values = OutputFormat::NAMES)]
+    format: OutputFormat,
+
+    /// Run this command (i.e. `tcmp <output path>`) instead of starting the
+    /// REPL, then exit.
+    ///
+    /// The exit code is 2 if the command found mismatches.
+    #[structopt(long, value_name = "command", conflicts_with = "script")]
+    run: Option<String>,
+
+    /// Run the commands in this file (one per line) instead of starting the
+    /// REPL, then exit.
+    ///
+    /// Like `--run`, the exit code is 2 if any of the commands found
+    /// mismatches.
+    #[structopt(long, parse(from_os_str), value_name = "file")]
+    script: Option<PathBuf>,
+
+    /// Check that this output has the same digest in every execution log
+    /// (printing the mismatches between its actions if it doesn't) instead of
+    /// starting the REPL, then exit; can be given more than once.
+    ///
+    /// The exit code is 2 if any of the outputs differ and 3 if any are
+    /// missing from some of the logs.
+    #[structopt(
+        long,
+        value_name = "output path",
+        number_of_values = 1,
+        conflicts_with_all = &["run", "script"]
+    )]
+    require_identical: Vec<String>,
+
+    /// An output (or glob of outputs) that's known to be nondeterministic;
+    /// with `--require-identical`, it's reported if it differs but doesn't
+    /// fail the check. Can be given more than once.
+    #[structopt(
+        long,
+        value_name = "path",
+        number_of_values = 1,
+        requires = "require-identical"
+    )]
+    allow_nondeterministic: Vec<Pattern>,
+
+    /// Stop running a `--script` at the first command that fails.
+    #[structopt(long, requires = "script")]
+    fail_fast: bool,
+
+    /// Give up on `--run`, `--script` or `--require-identical` if it takes
+    /// longer than this many seconds (not counting loading the logs) and
+    /// exit with code 124.
+    #[structopt(long, value_name = "seconds")]
+    timeout: Option<f64>,
+
+    /// How many artifacts `tcmp` (or `edges`) compares before asking whether
+    /// to carry on; with `--run` or `--script`, comparing more than this is an
+    /// error unless `--yes` is passed.
+    #[structopt(long, default_value = "10000", value_name = "artifacts")]
+    confirm_after: usize,
+
+    /// Don't ask before comparing more than `--confirm-after` artifacts (and
+    /// don't fail for it with `--run` or `--script`).
+    #[structopt(long)]
+    yes: bool,
+
+    /// Label the execution logs with this instead of their paths; the first
+    /// `--label` is for the first log (after directories and globs are
+    /// expanded), and so on. Can be given more than once.
+    #[structopt(long, value_name = "name", number_of_values = 1)]
+    label: Vec<String>,
+
+    /// The (JSON) execution logs to compare.
+    ///
+    /// One of these can be `-` to read a log from stdin. A directory stands
+    /// for all the logs in it (see `--recursive`) and globs that the shell
+    /// didn't expand are expanded. With the `http` feature these can also be
+    /// `http(s)://` URLs.
+    #[structopt(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+}
+
+/// The labels for the execution logs in `files` (as [`expand_args`] returns
+/// them): the `--label`s given (in order), then the labels that came with the
+/// files, then their paths (just their file names if the paths are long and
+/// the file names are enough to tell them apart). A log read from stdin is
+/// `<stdin>`.
+fn log_labels(files: &[(PathBuf, Option<String>)], labels: &[String]) -> eyre::Result<Vec<String>> {
+    if labels.len() > files.len() {
+        return Err(eyre::eyre!(
+            "there are {} `--label`s but only {} execution logs",
+            labels.len(),
+            files.len()
+        ));
+    }
+
+    let args = || files.iter().map(|(f, _)| f);
+    let truncate_file_names = args().any(|f| f.to_str().unwrap().len() > 20)
+        && args()
+            .map(|f| f.file_name().unwrap().to_owned())
+            .collect::<HashSet<_>>()
+            .len()
+            == files.len();
+
+    Ok(files
+        .iter()
+        .enumerate()
+        .map(|(idx, (f, label))| {
+            let name = if let Some(label) = labels.get(idx).or(label.as_ref()) {
+                label
+            } else if is_stdin(f) {
+                "<stdin>"
+            } else if truncate_file_names {
+                f.file_name().unwrap().to_str().unwrap()
+            } else {
+                f.to_str().unwrap()
+            };
+            name.to_owned()
+        })
+        .collect())
+}
+
+/// Reads and parses each of the execution `logs` (a path and a label each),
+/// warning about outputs that are produced by more than one action.
+fn load_logs(
+    opts: &Opts,
+    logs: &[(&PathBuf, &String)],
+) -> eyre::Result<Vec<(Map<'static>, LogSource)>> {
+    // For `reload`, this is taken before the log is read so that a change
+    // made while it's being read isn't missed:
+    let stamps: Vec<_> = logs.iter().map(|(f, _)| file_stamp(f)).collect();
+
+    // Each log gets a bar that shows it being read and then parsed:
+    let p = MultiProgress::new();
+    let bars: Vec<_> = logs
+        .iter()
+        .map(|(_, n)| p.add(ProgressBar::new(0).with_message((*n).clone())))
+        .collect();
+    let progress = thread::spawn(move || p.join_and_clear().unwrap());
+
+    // JSON logs are read (or, with `--mmap` and if they're uncompressed
+    // files, mapped) into memory up front so that the parsed actions can
+    // borrow from them. With `--low-memory` (and for the other formats which
+    // don't have anything to borrow) logs are parsed as they're read instead.
+    //
+    // The logs are read in parallel since reading them one at a time is slow
+    // on network filesystems (and doesn't make the most of fast disks).
+    //
+    // Logs that are in the `--cache` aren't opened at all; the others are
+    // added to it once they've been parsed:
+    let inputs = logs
+        .par_iter()
+        .zip(&bars)
+        .map(|((f, _), pb)| -> eyre::Result<_> {
+            let key = opts.cache.as_ref().and_then(|_| cache::Key::for_log(f));
+            if let (Some(dir), Some(key)) = (&opts.cache, &key) {
+                if let Some((format, entry)) = cache::load(dir, key) {
+                    return Ok((LogInput::Cached(entry), Some(key.len()), format, None));
+                }
+            }
+
+            let (reader, len, format) = open_log(f)?;
+
+            Ok(if format == LogFormat::Json && !opts.low_memory {
+                // Like the maps, this is around until we exit:
+                let mapped = if opts.mmap { map_log(f)? } else { None };
+                let content = match mapped {
+                    Some(mapped) => mapped,
+                    None => Box::leak(read_log(reader, len, pb)?.into()),
+                };
+                let len = Some(content.len() as _);
+
+                (LogInput::Buffered(content), len, format, key)
+            } else {
+                (LogInput::Streamed(reader), len, format, key)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let formats: Vec<_> = inputs.iter().map(|(_, _, format, _)| *format).collect();
+
+    let sty = ProgressStyle::default_bar()
+        .template("{msg:20!.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+        .progress_chars("#>-");
+    let inputs: Vec<_> = inputs
+        .into_iter()
+        .zip(bars)
+        .zip(logs.iter())
+        .map(|(((input, len, format, key), pb), (f, _))| {
+            pb.set_length(len.unwrap_or(0));
+            pb.set_style(if len.is_some() {
+                sty.clone()
+            } else {
+                ProgressStyle::default_spinner().template(
+                    "{spinner:.green} {msg:20!.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})",
+                )
+            });
+            (input, len, format, key, pb, f)
+        })
+        .collect();
+
+    let store_in_cache = |key: Option<cache::Key>, format, map: &Map, dups: &Producers| {
+        if let (Some(dir), Some(key)) = (&opts.cache, key) {
+            if let Err(e) = cache::store(dir, &key, format, map, dups) {
+                eprintln!(
+                    "[{}] Couldn't cache `{}`: {}",
+                    "WARNING".yellow(),
+                    key.path().display(),
+                    e
+                );
+            }
+        }
+    };
+
+    let unknown_fields = Mutex::new(BTreeSet::new());
+    let report_unknown_fields = opts.report_unknown_fields.then_some(&unknown_fields);
+
+    // Along with the offsets of the actions that have invalid UTF-8 in them:
+    type Parsed<'m> = (Map<'m>, Producers<'m>, usize, Vec<usize>);
+    let parsed: Vec<Parsed> = inputs
+        .into_par_iter()
+        .map(|(input, len, format, key, pb, f)| {
+            let parse = || -> eyre::Result<Parsed> {
+                let mut invalid_utf8 = Vec::new();
+                let (map, dups) = match (input, format) {
+                    (LogInput::Cached(entry), _) => {
+                        let (map, dups) = cache::parse(entry)?;
+                        pb.finish();
+                        return Ok((map, dups, len.unwrap() as usize, invalid_utf8));
+                    }
+                    (LogInput::Buffered(c), _) => {
+                        let (map, dups) =
+                            exec_log_to_hashmap(c, &pb, report_unknown_fields, &mut invalid_utf8)?;
+                        store_in_cache(key, format, &map, &dups);
+                        return Ok((map, dups, c.len(), invalid_utf8));
+                    }
+                    (LogInput::Streamed(r), LogFormat::Json) => exec_log_to_hashmap_streaming(
+                        BufReader::new(r),
+                        &pb,
+                        report_unknown_fields,
+                        &mut invalid_utf8,
+                    )?,
+                    (LogInput::Streamed(r), LogFormat::Compact) => {
+                        compact_log_to_hashmap(BufReader::new(r), &pb)?
+                    }
+                    (LogInput::Streamed(r), LogFormat::Binary) => {
+                        binary_log_to_hashmap(BufReader::new(r), &pb)?
+                    }
+                };
+                store_in_cache(key, format, &map, &dups);
+
+                Ok((map, dups, pb.position() as usize, invalid_utf8))
+            };
+
+            parse().wrap_err_with(|| format!("couldn't parse `{}`", f.display()))
+        })
+        .collect::<eyre::Result<_>>()?;
+    // So that the bars are cleared before anything else is printed (i.e. the
+    // prompt, after a `reload`):
+    let _ = progress.join();
+
+    let mut strings = Interner::default();
+    let loaded = parsed
+        .into_iter()
+        .zip(logs)
+        .zip(formats.into_iter().zip(stamps))
+        .map(|(((map, dups, bytes, invalid_utf8), (path, n)), (format, stamp))| {
+            if let Some(first) = invalid_utf8.first() {
+                eprintln!(
+                    "[{}] `{}` has invalid UTF-8 in {} action(s), starting at byte {}; the invalid bytes are replaced with `U+FFFD`.\n",
+                    "WARNING".yellow(),
+                    path.display(),
+                    invalid_utf8.len(),
+                    first,
+                );
+            }
+
+            // An empty log (i.e. from a build that failed before it ran
+            // anything) would otherwise just be missing every output:
+            if map.is_empty() {
+                if !opts.allow_empty {
+                    return Err(eyre::eyre!(
+                        "`{}` doesn't have any actions in it; pass `--allow-empty` to compare it anyway",
+                        path.display()
+                    ));
+                }
+                eprintln!(
+                    "[{}] `{}` doesn't have any actions in it.\n",
+                    "WARNING".yellow(),
+                    n.blue()
+                );
+            }
+
+            let (mut map, dups) = if opts.normalize_paths {
+                normalize_paths(map, dups, &mut strings)
+            } else {
+                (map, dups)
+            };
+            // The map has the last of the actions for each of these:
+            if opts.multi == MultiPolicy::First {
+                for (output, actions) in &dups {
+                    map.insert(output, actions[0].clone());
+                }
+            }
+
+            if !dups.is_empty() {
+                eprintln!(
+                    "[{}] {} output(s) in `{}` appear to be produced by multiple actions; `dup-outputs` lists them.\n",
+                    "WARNING".yellow(),
+                    dups.len(),
+                    n.blue()
+                );
+            }
+
+            let source = LogSource {
+                path: (*path).clone(),
+                bytes,
+                format,
+                stamp,
+                producers: dups,
+            };
+            Ok((map, source))
+        })
+        .collect::<eyre::Result<_>>()?;
+
+    if opts.report_unknown_fields {
+        let unknown_fields = unknown_fields.into_inner().unwrap();
+        if unknown_fields.is_empty() {
+            eprintln!("[{}] No unmodeled fields were found.\n", "NOTE".cyan());
+        } else {
+            eprintln!(
+                "[{}] These fields in the execution logs aren't modeled (and so aren't compared):",
+                "NOTE".cyan(),
+            );
+            for f in unknown_fields {
+                eprintln!("  - {}", f);
+            }
+            eprintln!();
+        }
+    }
+
+    Ok(loaded)
+}
+
+/// Warns about execution logs that probably shouldn't be compared with each
+/// other.
+fn warn_about_logs(opts: &Opts, maps: &[(&String, Map<'_>)]) {
+    // Logs with wildly different numbers of outputs probably aren't from
+    // comparable builds (i.e. a different target or a partial build); catch
+    // this early instead of letting the user chase down lots of missing
+    // outputs:
+    if !opts.no_size_mismatch_warning {
+        let sizes = maps.iter().map(|(_, m)| m.len());
+        let (min, max) = (sizes.clone().min().unwrap(), sizes.max().unwrap());
+        if max as f64 > min as f64 * opts.size_mismatch_factor {
+            eprintln!(
+                "[{}] These execution logs have very different numbers of outputs; are they from comparable builds?",
+                "WARNING".yellow(),
+            );
+            for (n, m) in maps.iter() {
+                eprintln!("  - {}: {} outputs", n.blue(), m.len());
+            }
+            eprintln!();
+        }
+    }
+
+    // Digests from different hash functions never match so comparing logs
+    // that use different ones just reports every input and output as
+    // different; point this out up front:
+    let functions: Vec<_> = maps.iter().map(|(_, m)| hash_functions(m)).collect();
+    if hash_functions_differ(&functions) {
+        eprintln!(
+            "[{}] These execution logs use different hash functions; comparing their digests is not meaningful:",
+            "WARNING".yellow(),
+        );
+        for ((n, _), f) in maps.iter().zip(functions) {
+            eprintln!(
+                "  - {}: {}",
+                n.blue(),
+                f.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+        eprintln!();
+    }
+}
+
+/// The size and modification time of the file at `path`, if it is one; used
+/// to tell whether a log has changed since it was loaded.
+fn file_stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Re-reads the execution logs that have changed since they were loaded (for
+/// `reload`), reporting which ones did.
+///
+/// Logs from stdin can't be re-read and are left as they are. The memory
+/// that the old versions of the changed logs borrowed from isn't freed.
+fn reload_logs(
+    opts: &Opts,
+    maps: &mut [(&'static String, Map<'static>)],
+    sources: &mut [LogSource],
+) -> eyre::Result<()> {
+    let label_width = label_width(maps.iter().map(|(n, _)| *n), opts.label_width);
+    let mut changed = Vec::new();
+    for (idx, ((n, _), source)) in maps.iter().zip(sources.iter()).enumerate() {
+        let status = if is_stdin(&source.path) {
+            "unchanged (it was read from stdin and can't be read again)"
+                .dimmed()
+                .to_string()
+        } else {
+            match (source.stamp, file_stamp(&source.path)) {
+                (Some(old), Some(new)) if old == new => "unchanged".dimmed().to_string(),
+                (Some((old, _)), Some((new, _))) if old != new => {
+                    changed.push(idx);
+                    format!("changed ({} → {} bytes)", old, new)
+                        .yellow()
+                        .to_string()
+                }
+                (Some(_), Some(_)) => {
+                    changed.push(idx);
+                    "changed (modified)".yellow().to_string()
+                }
+                // Not a file (i.e. a URL); there's no telling, so it's read
+                // again:
+                (None, _) => {
+                    changed.push(idx);
+                    "read again".yellow().to_string()
+                }
+                (Some(_), None) => "missing; keeping what was loaded before".red().to_string(),
+            }
+        };
+        println!("  {:>w$.w$}: {}", n.green(), status, w = label_width);
+    }
+
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    let logs: Vec<_> = changed
+        .iter()
+        .map(|&idx| (&sources[idx].path, maps[idx].0))
+        .collect();
+    let reloaded = load_logs(opts, &logs)?;
+    for (idx, (map, source)) in changed.into_iter().zip(reloaded) {
+        maps[idx].1 = map;
+        sources[idx] = source;
+    }
+
+    Ok(())
+}
+
+/// Asks whether a traversal that has compared `n` artifacts should carry on;
+/// only a `y` (or `yes`) does.
+///
+/// This goes to stderr since stdout might be going to the pager.
+fn ask_to_carry_on(n: usize) -> bool {
+    eprint!("visited {} artifacts, continue? [y/N] ", n);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Reads commands at the prompt and runs them until the user quits (or asks
+/// for the logs to be reloaded); the commands are added to `history`.
+fn repl(session: &Session<'_>, config: Config, fuzzy: bool, history: &mut History) -> Flow {
+    let mut rl = Editor::with_config(config);
+    rl.set_helper(Some(ExecLogHelper::new(session.maps, fuzzy)));
+    std::mem::swap(rl.history_mut(), history);
+
+    let prompt = format!("{}", "> ".blue());
+    let flow = loop {
+        match rl.readline(prompt.as_str()) {
+            Ok(line) => {
+                // A line that was carried on (i.e. to close a quote) is still
+                // one command; the line breaks aren't part of it:
+                let line = line.replace("\\\n", "").replace('\n', "");
+
+                reset_cancelled();
+                let capture = session.page.then(pager::Capture::start).flatten();
+                let flow = run_command(&line, session, rl.helper().unwrap());
+                if let Some(capture) = capture {
+                    capture.finish();
+                }
+                match flow {
+                    Ok(Flow::Continue) => {}
+                    Ok(flow) => break flow,
+                    Err(e) => eprintln!("{:?}", e),
+                }
+                if reset_cancelled() {
+                    eprintln!("{}", "cancelled".yellow());
+                }
+            }
+            Err(_) => print_help(),
+        }
+    };
+
+    std::mem::swap(rl.history_mut(), history);
+    flow
+}
+
+fn main() -> eyre::Result<()> {
+    let opts = Opts::from_args();
+    color::set_enabled(!opts.no_color && opts.color.enabled());
+    set_hash_len(opts.hash_len);
+    if color::enabled() {
+        color_eyre::install()?;
+    } else {
+        color_eyre::config::HookBuilder::new()
+            .theme(color_eyre::config::Theme::new())
+            .install()?;
+    }
+
+    let batch = opts.run.is_some() || opts.script.is_some() || !opts.require_identical.is_empty();
+    let timeout = match opts.timeout {
+        Some(_) if !batch => {
+            return Err(eyre::eyre!(
+                "`--timeout` only applies to `--run`, `--script` and `--require-identical`"
+            ))
+        }
+        Some(t) => Some(
+            Duration::try_from_secs_f64(t)
+                .map_err(|_| eyre::eyre!("invalid `--timeout`: {}", t))?,
+        ),
+        None => None,
+    };
+
+    let files = expand_args(&opts.files, opts.recursive)?;
+    let args = || files.iter().map(|(f, _)| f);
+    if args().filter(|f| is_stdin(f)).count() > 1 {
+        return Err(eyre::eyre!(
+            "only one execution log can be read from stdin (`-`)"
+        ));
+    }
+
+    // Like the maps, these are around until we exit:
+    let names: Vec<&'static String> = log_labels(&files, &opts.label)?
+        .into_iter()
+        .map(|n| &*Box::leak(Box::new(n)))
+        .collect();
+
+    let logs: Vec<_> = args().zip(names.iter().copied()).collect();
+    let (mut maps, mut sources): (Vec<(&String, Map)>, Vec<_>) = load_logs(&opts, &logs)?
+        .into_iter()
+        .zip(names.iter().copied())
+        .map(|((map, source), n)| ((n, map), source))
+        .unzip();
+    warn_about_logs(&opts, &maps);
+
+    if let Some(label) = &opts.baseline {
+        if find_log(&maps, label).is_none() {
+            return Err(eyre::eyre!(
+                "`--baseline {}` isn't one of the execution logs ({})",
+                label,
+                names
+                    .iter()
+                    .map(|n| n.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+    let mut baseline = opts.baseline.clone();
+    let ignored_env = IgnoredEnv::new(opts.ignore_env.clone());
+
+    if batch {
+        if let Some(timeout) = timeout {
+            start_timeout(timeout);
+        }
+
+        let session = Session::new(&maps, &sources, &opts, &ignored_env, baseline.as_deref());
+        if !opts.require_identical.is_empty() {
+            let required: Vec<_> = opts
+                .require_identical
+                .iter()
+                .map(|p| {
+                    if opts.normalize_paths {
+                        normalize_path(p).into_owned()
+                    } else {
+                        p.clone()
+                    }
+                })
+                .collect();
+            let code = require_identical(&session, &required, &opts.allow_nondeterministic);
+            exit_if_timed_out();
+            std::process::exit(code);
+        }
+
+        let helper = ExecLogHelper::new(&maps, !opts.no_fuzzy);
+        if let Some(command) = &opts.run {
+            run_command(command, &session, &helper)?;
+        }
+        if let Some(script) = &opts.script {
+            let commands = read_to_string(script)
+                .map_err(|e| eyre::eyre!("couldn't read `{}`: {}", script.display(), e))?;
+            let failed = run_script(&commands, &session, &helper, opts.fail_fast)?;
+            exit_if_timed_out();
+            if failed > 0 {
+                return Err(eyre::eyre!("{} command(s) in the script failed", failed));
+            }
+        }
+
+        exit_if_timed_out();
+        // Like below, there's no need to clean up before exiting.
+        std::process::exit(if session.found_mismatches.get() {
+            EXIT_MISMATCH
+        } else {
+            0
+        });
+    }
+
+    /// How many commands are kept in the history.
+    const MAX_HISTORY: usize = 1000;
+
+    let config = Config::builder()
+        .auto_add_history(true)
+        .max_history_size(MAX_HISTORY)
+        .completion_type(CompletionType::List)
+        .completion_prompt_limit(50)
+        .build();
+
+    print_log_banner(&maps, &sources);
+
+    // There's no history the first time around; it's created on exit.
+    let mut history = History::with_config(config);
+    let history_file = history_path();
+    if let Some(h) = &history_file {
+        let _ = history.load(h);
+    }
+
+    // Ctrl-C at the prompt is handled by `rustyline`; while a command runs it
+    // cancels the command instead of exiting. Commands that don't check for
+    // this can't be cancelled, so a second Ctrl-C still exits (printing what
+    // the command had printed so far if it's being paged).
+    ctrlc::set_handler(|| {
+        if cancel() {
+            pager::dump_capture();
+            std::process::exit(130);
+        }
+    })?;
+
+    // The session (and everything that borrows the maps) is started over
+    // whenever the logs change:
+    loop {
+        let session = Session::new(&maps, &sources, &opts, &ignored_env, baseline.as_deref());
+        let flow = repl(&session, config, !opts.no_fuzzy, &mut history);
+        baseline = session.baseline.get().map(str::to_owned);
+        drop(session);
+
+        match flow {
+            Flow::Reload if opts.mmap => {
+                eprintln!(
+                    "[{}] Logs that are mapped with `--mmap` can't be reloaded; restart the tool instead",
+                    "WARNING".yellow()
+                );
+            }
+            Flow::Reload => {
+                if let Err(e) = reload_logs(&opts, &mut maps, &mut sources) {
+                    eprintln!("{:?}", e);
+                }
+                warn_about_logs(&opts, &maps);
+            }
+            Flow::Load(path, label) => {
+                // Like the other labels, this is around until we exit:
+                let label: &'static String = Box::leak(Box::new(label));
+                match load_logs(&opts, &[(&path, label)]) {
+                    Ok(loaded) => {
+                        for (map, source) in loaded {
+                            maps.push((label, map));
+                            sources.push(source);
+                        }
+                        warn_about_logs(&opts, &maps);
+                    }
+                    Err(e) => eprintln!(
+                        "{:?}",
+                        e.wrap_err(format!("couldn't load `{}`", path.display()))
+                    ),
+                }
+            }
+            Flow::Relabel(idx, label) => {
+                let old = &mut maps[idx].0;
+                if baseline.as_deref() == Some(old.as_str()) {
+                    baseline = Some(label.clone());
+                }
+                // Like the other labels, this is around until we exit:
+                *old = Box::leak(Box::new(label));
+            }
+            Flow::Quit | Flow::Continue => break,
+        }
+    }
+
+    if let Some(h) = &history_file {
+        let saved = match h.parent() {
+            Some(dir) => std::fs::create_dir_all(dir).map_err(Into::into),
+            None => Ok(()),
+        }
+        .and_then(|()| history.save(h));
+        if let Err(e) = saved {
+            eprintln!("couldn't save the history to `{}`: {}", h.display(), e);
+        }
+    }
+
+    // Since we're exiting anyways, don't bother cleaning up memory and running
+    // destructors; let the OS take care of it:
+    forget(maps);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bazel_execlog_cmp::parse_exec_log;
+
+    use super::*;
+
+    /// A directory for a test's files that's removed once the test is done.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "bazel-execlog-cmp-{}-{}",
+                test,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Writes `contents` to the file `name` in the directory; returns its
+        /// path.
+        fn write(&self, name: &str, contents: &[u8]) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// An execution log with two actions.
+    const LOG: &str = r#"{
+        "commandArgs": ["cc", "-c", "a.c"],
+        "environmentVariables": [{ "name": "V", "value": "1" }],
+        "inputs": [{
+            "path": "a.c",
+            "digest": { "hash": "1111111111111111111111111111111111111111111111111111111111111111", "sizeBytes": "1", "hashFunctionName": "SHA-256" }
+        }],
+        "listedOutputs": ["a.o"],
+        "remotable": true,
+        "cacheable": true,
+        "actualOutputs": [{
+            "path": "a.o",
+            "digest": { "hash": "2222222222222222222222222222222222222222222222222222222222222222", "sizeBytes": "2", "hashFunctionName": "SHA-256" }
+        }]
+    }{
+        "commandArgs": ["ld", "a.o"],
+        "environmentVariables": [],
+        "inputs": [{
+            "path": "a.o",
+            "digest": { "hash": "2222222222222222222222222222222222222222222222222222222222222222", "sizeBytes": "2", "hashFunctionName": "SHA-256" }
+        }],
+        "listedOutputs": ["a.out"],
+        "remotable": true,
+        "cacheable": true,
+        "actualOutputs": [{
+            "path": "a.out",
+            "digest": { "hash": "3333333333333333333333333333333333333333333333333333333333333333", "sizeBytes": "3", "hashFunctionName": "SHA-256" }
+        }]
+    }"#;
+
+    /// An execution log with an action for each output in `actions`, made from
+    /// the inputs listed with it.
+    fn log_of(actions: &[(&str, &[&str])]) -> String {
+        let digest = serde_json::json!({
+            "hash": "1".repeat(64),
+            "sizeBytes": "1",
+            "hashFunctionName": "SHA-256",
+        });
+        actions
+            .iter()
+            .map(|(o, inputs)| {
+                serde_json::json!({
+                    "commandArgs": ["touch", o],
+                    "environmentVariables": [],
+                    "inputs": inputs
+                        .iter()
+                        .map(|i| serde_json::json!({ "path": i, "digest": digest }))
+                        .collect::<Vec<_>>(),
+                    "listedOutputs": [o],
+                    "remotable": true,
+                    "cacheable": true,
+                    "actualOutputs": [{ "path": o, "digest": digest }],
+                })
+                .to_string()
+            })
+            .collect()
+    }
+
+    /// Parses the command line `args` (without the program name).
+    fn opts(args: &[&str]) -> Opts {
+        Opts::from_iter_safe(std::iter::once("bazel-execlog-cmp").chain(args.iter().copied()))
+            .unwrap()
+    }
+
+    /// Parses each of `logs` (a label and a JSON execution log); like in
+    /// `main`, these are around until the tests exit.
+    fn maps(logs: &[(&str, String)]) -> Vec<(&'static String, Map<'static>)> {
+        logs.iter()
+            .map(|(l, log)| {
+                let label: &'static String = Box::leak(Box::new(l.to_string()));
+                let log: &'static str = Box::leak(log.clone().into_boxed_str());
+                (label, parse_exec_log(log.as_bytes()).unwrap().0)
+            })
+            .collect()
+    }
+
+    /// Compares the actions for `output` in each of the `maps`.
+    fn compare<'l>(output: &'l str, maps: &'l [(&'l String, Map<'l>)]) -> MismatchSet<'l> {
+        find_mismatched(
+            output,
+            maps.iter().map(|(l, m)| (*l, &m[output])),
+            &IgnoredEnv::new(Vec::new()),
+        )
+    }
+
+    /// Calls `f` with a session over `maps` (with the options `args`).
+    fn with_session<T>(
+        args: &[&str],
+        maps: &[(&String, Map<'_>)],
+        f: impl FnOnce(&Session<'_>) -> T,
+    ) -> T {
+        let labels = maps.iter().map(|(l, _)| l.as_str());
+        let opts = opts(&args.iter().copied().chain(labels).collect::<Vec<_>>());
+        let sources: Vec<_> = maps
+            .iter()
+            .map(|(l, _)| LogSource {
+                path: PathBuf::from(l.as_str()),
+                bytes: 0,
+                format: LogFormat::Json,
+                stamp: None,
+                producers: Producers::default(),
+            })
+            .collect();
+        let ignored_env = IgnoredEnv::new(Vec::new());
+        f(&Session::new(maps, &sources, &opts, &ignored_env, None))
+    }
+
+    /// Runs each of `lines` at the prompt of a session over `maps` (with the
+    /// options `args`); returns whether any of them found mismatches.
+    fn run(args: &[&str], maps: &[(&String, Map<'_>)], lines: &[&str]) -> bool {
+        with_session(args, maps, |session| {
+            let helper = ExecLogHelper::new(session.maps, false);
+            for line in lines {
+                run_command(line, session, &helper).unwrap();
+            }
+            session.found_mismatches.get()
+        })
+    }
+
+    /// Loads each of `files` (labelled with their paths) with `opts`.
+    fn load(opts: &Opts, files: &[&PathBuf]) -> Vec<(Map<'static>, LogSource)> {
+        let labels: Vec<_> = files
+            .iter()
+            .map(|f| f.to_string_lossy().into_owned())
+            .collect();
+        let logs: Vec<_> = files.iter().copied().zip(&labels).collect();
+        load_logs(opts, &logs).unwrap()
+    }
+
+    /// Asserts that `a` and `b` have the same actions, and that comparing them
+    /// finds nothing.
+    fn assert_same_actions(a: &Map<'_>, b: &Map<'_>) {
+        let (la, lb) = ("a".to_owned(), "b".to_owned());
+        assert_eq!(a.len(), b.len());
+        for (output, action) in a {
+            assert_eq!(action.0, b[output].0, "{}", output);
+            let actions = [(&la, action), (&lb, &b[output])];
+            let found = find_mismatched(
+                output,
+                actions.iter().map(|(l, a)| (*l, *a)),
+                &IgnoredEnv::new(Vec::new()),
+            );
+            assert!(found.is_empty(), "{}", output);
+        }
+    }
+
+    #[test]
+    fn gzipped_logs_load_like_plain_ones() {
+        let dir = TempDir::new("gzip");
+        let plain = dir.write("a.json", LOG.as_bytes());
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(LOG.as_bytes()).unwrap();
+        let gz = gz.finish().unwrap();
+        let with_ext = dir.write("a.json.gz", &gz);
+        // Detected by its magic bytes:
+        let without_ext = dir.write("a", &gz);
+
+        for low_memory in [false, true] {
+            let args: &[&str] = if low_memory {
+                &["--low-memory", "-"]
+            } else {
+                &["-"]
+            };
+            let loaded = load(&opts(args), &[&plain, &with_ext, &without_ext]);
+            assert_eq!(loaded[0].0.len(), 2);
+            for (map, source) in &loaded[1..] {
+                assert_eq!(source.format, LogFormat::Json);
+                assert_same_actions(&loaded[0].0, map);
+            }
+        }
+    }
+
+    #[test]
+    fn logs_are_only_mapped_with_mmap() {
+        let dir = TempDir::new("mmap");
+        let a = dir.write("a.json", LOG.as_bytes());
+        let b = dir.write("b.json", LOG.as_bytes());
+
+        let read = load(&opts(&["-"]), &[&a]);
+        let mapped = load(&opts(&["--mmap", "-"]), &[&b]);
+        assert_same_actions(&read[0].0, &mapped[0].0);
+
+        // Logs that were read in don't care if they're rewritten afterwards
+        // (i.e. by a rebuild):
+        std::fs::write(&a, vec![b' '; LOG.len()]).unwrap();
+        assert_same_actions(&mapped[0].0, &read[0].0);
+    }
+
+    #[test]
+    fn zstd_logs_load_like_plain_ones() {
+        let dir = TempDir::new("zstd");
+        let plain = dir.write("a.json", LOG.as_bytes());
+        let zst = zstd::encode_all(LOG.as_bytes(), 0).unwrap();
+        let with_ext = dir.write("a.json.zst", &zst);
+        // Detected by its magic bytes:
+        let without_ext = dir.write("a", &zst);
+
+        for low_memory in [false, true] {
+            let args: &[&str] = if low_memory {
+                &["--low-memory", "-"]
+            } else {
+                &["-"]
+            };
+            let loaded = load(&opts(args), &[&plain, &with_ext, &without_ext]);
+            for (map, _) in &loaded[1..] {
+                assert_same_actions(&loaded[0].0, map);
+            }
+        }
+    }
+
+    #[test]
+    fn json_report_shape() {
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            ("b", LOG.replacen(r#""value": "1""#, r#""value": "2""#, 1)),
+            (
+                "c",
+                LOG.replacen(r#"[{ "name": "V", "value": "1" }]"#, "[]", 1),
+            ),
+        ]);
+
+        let report = MismatchReport::new(compare("a.o", &maps), &maps, None);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "mismatches": [{
+                    "artifact": "a.o",
+                    "kind": "env",
+                    "field": "V",
+                    "values": { "a": "1", "b": "2", "c": null },
+                }],
+            })
+        );
+
+        let report = MismatchReport::new(compare("a.o", &maps), &maps, Some("a"));
+        let report = serde_json::to_value(&report).unwrap();
+        assert_eq!(report["baseline"], "a");
+        assert_eq!(
+            report["mismatches"][0]["differs"],
+            serde_json::json!(["b", "c"])
+        );
+    }
+
+    #[test]
+    fn csv_report_has_a_row_per_value() {
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            (
+                "b,2",
+                LOG.replacen(r#"[{ "name": "V", "value": "1" }]"#, "[]", 1)
+                    .replacen(&"2".repeat(64), &"f".repeat(64), 2),
+            ),
+        ]);
+        let mismatches = compare("a.o", &maps);
+        let count = mismatches.env_vars.len() + mismatches.outputs.len();
+        assert_eq!(count, 2);
+
+        let mut csv = Vec::new();
+        MismatchReport::new(mismatches, &maps, None)
+            .write_csv(&mut csv)
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<_> = csv.lines().collect();
+        assert_eq!(rows[0], "artifact,kind,field,log_name,value,present");
+        assert_eq!(rows.len(), 1 + count * maps.len());
+        assert!(rows.contains(&"a.o,env,V,a,1,true"));
+        assert!(rows.contains(&"a.o,env,V,\"b,2\",,false"));
+        assert!(
+            rows.contains(&format!("a.o,output,a.o,\"b,2\",{}/2,true", "f".repeat(64)).as_str())
+        );
+    }
+
+    #[test]
+    fn diff_against_the_first_of_three_logs() {
+        color::set_enabled(false);
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            ("b", LOG.to_owned()),
+            ("c", LOG.replacen(r#""value": "1""#, r#""value": "2""#, 1)),
+        ]);
+
+        let mut out = Vec::new();
+        write_diff(&mut out, "a.o", &get(&maps, "a.o").unwrap(), false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let (b, c) = out.split_once("`a` → `c`:").unwrap();
+        assert_eq!(b.trim(), "`a` → `b`:\n(equivalent)");
+        assert!(c.lines().any(|l| l.contains(r#"value: "1""#)));
+        assert!(c.lines().any(|l| l.contains(r#"value: "2""#)));
+
+        let mut out = Vec::new();
+        write_diff(&mut out, "a.out", &get(&maps, "a.out").unwrap(), false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "all executions of `a.out` were equivalent\n"
+        );
+    }
+
+    #[test]
+    fn best_completion_comes_first() {
+        color::set_enabled(false);
+        let mut outputs: Vec<_> = (0..100).map(|i| format!("l/{}/i/b/x.o", i)).collect();
+        outputs.push("lib.o".to_owned());
+        let outputs: Vec<_> = outputs.iter().map(|o| (o.as_str(), &[][..])).collect();
+        let maps = maps(&[("a", log_of(&outputs))]);
+
+        let helper = ExecLogHelper::new(&maps, true);
+        let history = rustyline::history::History::new();
+        let (start, candidates) = helper
+            .complete("cmp lib", 7, &rustyline::Context::new(&history))
+            .unwrap();
+        assert_eq!(start, 4);
+        assert_eq!(candidates.len(), 50);
+        assert_eq!(candidates[0].replacement(), "lib.o");
+    }
+
+    #[test]
+    fn tcmp_across_asymmetric_logs() {
+        // `a.o` differs and so is compared too, but only two of the logs have
+        // it:
+        let (_, link) = LOG.split_once("}{").unwrap();
+        let changed = |log: &str| {
+            log.replace(&"2".repeat(64), &"4".repeat(64)).replacen(
+                r#""value": "1""#,
+                r#""value": "2""#,
+                1,
+            )
+        };
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            ("b", changed(LOG)),
+            ("c", changed(&format!("{{{}", link))),
+        ]);
+        let ignored_env = IgnoredEnv::new(Vec::new());
+        let transitive = transitive_cmp("a.out", &maps, None, None, &ignored_env, false, None);
+        assert!(transitive.graph.nodes.contains(&("a.o", true)));
+        assert!(!transitive.mismatches.env_vars.is_empty());
+
+        for format in ["human", "json"] {
+            assert!(run(
+                &["--format", format],
+                &maps,
+                &["tcmp a.out", "edges a.out"]
+            ));
+        }
+    }
+
+    #[test]
+    fn split_args_quoting() {
+        fn words(line: &str) -> (Vec<String>, bool) {
+            let (words, incomplete) = split_args(line);
+            (words.into_iter().map(|(_, w)| w).collect(), incomplete)
+        }
+        assert_eq!(
+            words("cmp  a.o "),
+            (vec!["cmp".into(), "a.o".into()], false)
+        );
+        assert_eq!(
+            words(r#"cmp "My File.o" a\ b "say \"hi\"" c\\d"#),
+            (
+                vec![
+                    "cmp".into(),
+                    "My File.o".into(),
+                    "a b".into(),
+                    r#"say "hi""#.into(),
+                    r"c\d".into()
+                ],
+                false
+            )
+        );
+        assert_eq!(
+            words(r#"cmp "My Fi"#),
+            (vec!["cmp".into(), "My Fi".into()], true)
+        );
+        assert_eq!(words(r"cmp a\"), (vec!["cmp".into(), "a".into()], true));
+
+        let (spans, _) = split_args(r#"cmp "a b""#);
+        assert_eq!(spans[1].0, 4..9);
+
+        for arg in ["a.o", "My File.o", r#"a "b" \c"#] {
+            let line = format!("cmp {}", quote_arg(arg));
+            assert_eq!(words(&line), (vec!["cmp".into(), arg.into()], false));
+        }
+    }
+
+    #[test]
+    fn paths_with_spaces() {
+        let log = LOG.replace(r#""a.o""#, r#""My File.o""#);
+        let maps = maps(&[
+            ("a", log.clone()),
+            ("b", log.replacen(&"2".repeat(64), &"4".repeat(64), 2)),
+        ]);
+        assert!(run(&[], &maps, &[r#"cmp "My File.o""#]));
+
+        let helper = ExecLogHelper::new(&maps, false);
+        let history = rustyline::history::History::new();
+        let ctx = rustyline::Context::new(&history);
+        let (start, candidates) = helper.complete("cmp My", 6, &ctx).unwrap();
+        assert_eq!(start, 4);
+        let replacements: Vec<_> = candidates.iter().map(|c| c.replacement()).collect();
+        assert_eq!(replacements, [r#""My File.o""#]);
+        let (start, candidates) = helper.complete(r#"cmp "My F"#, 9, &ctx).unwrap();
+        assert_eq!(start, 4);
+        assert_eq!(candidates[0].replacement(), r#""My File.o""#);
+    }
+
+    #[test]
+    fn no_escape_codes_without_color() {
+        assert!(!"never".parse::<ColorChoice>().unwrap().enabled());
+        assert!("always".parse::<ColorChoice>().unwrap().enabled());
+
+        color::set_enabled(false);
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            ("b", LOG.replacen(r#""value": "1""#, r#""value": "2""#, 1)),
+        ]);
+        let actions = get(&maps, "a.o").unwrap();
+        for side_by_side in [false, true] {
+            let mut out = Vec::new();
+            write_diff(&mut out, "a.o", &actions, side_by_side).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.contains(r#"value: "2""#));
+            assert!(!out.contains('\x1b'), "{}", out);
+        }
+
+        let helper = ExecLogHelper::new(&maps, true);
+        let history = rustyline::history::History::new();
+        let (_, candidates) = helper
+            .complete("cmp ao", 6, &rustyline::Context::new(&history))
+            .unwrap();
+        assert!(!candidates.is_empty());
+        assert!(candidates.iter().all(|c| !c.display().contains('\x1b')));
+    }
+
+    #[test]
+    fn cached_logs_compare_like_fresh_ones() {
+        let dir = TempDir::new("cache");
+        let a = dir.write("a.json", LOG.as_bytes());
+        let b = dir.write(
+            "b.json",
+            LOG.replacen(r#""value": "1""#, r#""value": "2""#, 1)
+                .as_bytes(),
+        );
+        let cache = dir.0.join("cache");
+        let opts = opts(&["--cache", cache.to_str().unwrap(), "x"]);
+
+        // The JSON report for `cmp a.o` across the loaded logs:
+        let report = |loaded: Vec<(Map<'static>, LogSource)>| {
+            let maps: Vec<_> = ["a", "b"]
+                .iter()
+                .map(|l| &*Box::leak(Box::new(l.to_string())))
+                .zip(loaded.into_iter().map(|(m, _)| m))
+                .collect();
+            let report = MismatchReport::new(compare("a.o", &maps), &maps, None);
+            serde_json::to_string(&report).unwrap()
+        };
+
+        let fresh = load(&opts, &[&a, &b]);
+        assert!(std::fs::read_dir(&cache).unwrap().count() > 0);
+        let fresh_a = fresh[0].0.clone();
+        let fresh = report(fresh);
+
+        // Garble the logs without changing their size or modification time;
+        // they're only read from the cache now:
+        for f in [&a, &b] {
+            let meta = std::fs::metadata(f).unwrap();
+            std::fs::write(f, vec![b' '; meta.len() as usize]).unwrap();
+            let file = std::fs::File::options().write(true).open(f).unwrap();
+            file.set_modified(meta.modified().unwrap()).unwrap();
+        }
+        let cached = load(&opts, &[&a, &b]);
+        assert_same_actions(&fresh_a, &cached[0].0);
+        assert_eq!(report(cached), fresh);
+
+        // ...until they change:
+        std::fs::write(&b, LOG).unwrap();
+        let reloaded = load(&opts, &[&a, &b]);
+        assert_same_actions(&fresh_a, &reloaded[1].0);
+    }
+
+    #[test]
+    fn dependency_path_through_a_diamond() {
+        let maps = maps(&[(
+            "a",
+            log_of(&[
+                ("a.out", &["l.o", "r.o"]),
+                ("l.o", &["s.o"]),
+                ("r.o", &["s.o"]),
+                ("s.o", &["s.c"]),
+            ]),
+        )]);
+        let map = &maps[0].1;
+
+        let path = dependency_path(map, "a.out", "s.c").unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!((path[0], path[2], path[3]), ("a.out", "s.o", "s.c"));
+        assert!(["l.o", "r.o"].contains(&path[1]));
+        assert_eq!(dependency_path(map, "l.o", "s.o"), Some(vec!["l.o", "s.o"]));
+        assert_eq!(dependency_path(map, "a.out", "a.out"), Some(vec!["a.out"]));
+        assert_eq!(dependency_path(map, "l.o", "r.o"), None);
+        assert_eq!(dependency_path(map, "s.c", "a.out"), None);
+
+        assert!(!run(&[], &maps, &["path a.out s.c", "path l.o r.o"]));
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.00 KiB (1024 B)");
+        assert_eq!(human_size(1024 * 1024 - 1), "1.00 MiB (1048575 B)");
+        assert_eq!(human_size(1024 * 1024), "1.00 MiB (1048576 B)");
+        assert_eq!(human_size(1288490188), "1.20 GiB (1288490188 B)");
+    }
+
+    #[test]
+    fn mixed_hash_functions() {
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            ("b", LOG.replace("SHA-256", "BLAKE3")),
+        ]);
+        let functions: Vec<_> = maps.iter().map(|(_, m)| hash_functions(m)).collect();
+        assert_eq!(functions[0], BTreeSet::from(["SHA-256"]));
+        assert_eq!(functions[1], BTreeSet::from(["BLAKE3"]));
+        assert!(hash_functions_differ(&functions));
+        assert!(!hash_functions_differ(&[
+            functions[0].clone(),
+            BTreeSet::new(),
+            functions[0].clone()
+        ]));
+
+        // Every digest differs, if only in its hash function:
+        let found = compare("a.out", &maps);
+        assert_eq!((found.inputs.len(), found.outputs.len()), (1, 1));
+        let output = &found.outputs[0];
+        let functions: BTreeSet<_> = ["a", "b"]
+            .iter()
+            .map(|l| output.value(l).unwrap().hash_function_name)
+            .collect();
+        assert_eq!(functions, BTreeSet::from(["BLAKE3", "SHA-256"]));
+        assert!(run(&[], &maps, &["cmp a.out"]));
+    }
+
+    #[test]
+    fn baseline_is_never_reported_as_differing() {
+        let with_v = |v: &str| LOG.replacen(r#""value": "1""#, &format!(r#""value": "{}""#, v), 1);
+        let maps = maps(&[
+            ("a", with_v("1")),
+            ("b", with_v("2")),
+            ("c", with_v("2")),
+            (
+                "d",
+                LOG.replacen(r#"[{ "name": "V", "value": "1" }]"#, "[]", 1),
+            ),
+        ]);
+
+        let cases: [(&str, &[&str]); 3] = [
+            ("a", &["b", "c", "d"]),
+            ("b", &["a", "d"]),
+            ("d", &["a", "b", "c"]),
+        ];
+        for (baseline, expected) in cases {
+            let report = MismatchReport::new(compare("a.o", &maps), &maps, Some(baseline));
+            assert_eq!(report.baseline, Some(baseline));
+            let env = &report.mismatches[0];
+            assert_eq!(env.kind, "env");
+            let differs = env.differs.as_ref().unwrap();
+            assert!(!differs.contains(&baseline));
+            assert_eq!(differs, expected);
+        }
+
+        let report = MismatchReport::new(compare("a.o", &maps), &maps, None);
+        assert!(report.mismatches[0].differs.is_none());
+        assert!(run(
+            &["--baseline", "b"],
+            &maps,
+            &["cmp a.o", "baseline c", "cmp a.o"]
+        ));
+    }
+
+    #[test]
+    fn require_identical_exit_codes() {
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            ("b", LOG.replacen(&"3".repeat(64), &"4".repeat(64), 1)),
+        ]);
+        let required = |outputs: &[&str]| outputs.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        let allowed = [Pattern::new("*.out").unwrap()];
+
+        with_session(&[], &maps, |session| {
+            let check = |outputs: &[&str], allowed: &[Pattern]| {
+                require_identical(session, &required(outputs), allowed)
+            };
+            assert_eq!(check(&["a.o"], &[]), 0);
+            assert_eq!(check(&["a.o", "a.out"], &[]), EXIT_MISMATCH);
+            assert_eq!(check(&["a.o", "a.out"], &allowed), 0);
+            assert_eq!(check(&["a.o", "b.out"], &allowed), EXIT_MISSING);
+            assert_eq!(check(&["a.out", "b.o"], &[]), EXIT_MISSING);
+        });
+    }
+
+    #[test]
+    fn reproducible_partition() {
+        let maps = maps(&[
+            ("a", LOG.to_owned() + &log_of(&[("only-a", &[])])),
+            ("b", LOG.to_owned()),
+            ("c", LOG.replacen(&"3".repeat(64), &"4".repeat(64), 1)),
+        ]);
+        let (same, different) = partition_reproducible(&maps);
+        assert_eq!(same, ["a.o"]);
+        assert_eq!(different, ["a.out"]);
+
+        assert!(run(&[], &maps, &["reproducible", "nonreproducible"]));
+        assert!(!run(&[], &maps[..2], &["reproducible", "nonreproducible"]));
+    }
+
+    #[test]
+    fn outputs_with_the_same_contents_are_grouped() {
+        // Every output of a `log_of` log has the same digest:
+        let maps = maps(&[
+            ("a", log_of(&[("x", &[]), ("y", &[])])),
+            ("b", LOG.to_owned()),
+        ]);
+
+        let index = outputs_by_hash(&maps[0].1);
+        assert_eq!(index.len(), 1);
+        let (digest, paths) = index.values().next().unwrap();
+        assert_eq!(digest.hash, Hash::from_hex(&"1".repeat(64)).unwrap());
+        assert_eq!(paths, &BTreeSet::from([Path::new("x"), Path::new("y")]));
+
+        let index = outputs_by_hash(&maps[1].1);
+        assert_eq!(index.len(), 2);
+        assert!(index.values().all(|(_, paths)| paths.len() == 1));
+
+        run(&[], &maps, &["dupes", "dupes --cross"]);
+    }
+
+    #[test]
+    fn generated_inputs_are_told_apart_from_sources() {
+        // `gen.h` is only generated in the second log:
+        let maps = maps(&[
+            ("a", log_of(&[("a.o", &["gen.h", "src.h"])])),
+            ("b", log_of(&[("a.o", &["gen.h", "src.h"]), ("gen.h", &[])])),
+        ]);
+
+        assert!(is_generated(&maps, "gen.h"));
+        assert!(!is_generated(&maps[..1], "gen.h"));
+        assert!(!is_generated(&maps, "src.h"));
+        assert!(is_generated(&maps, "a.o"));
+    }
+
+    #[test]
+    fn long_labels_arent_cut_off() {
+        color::set_enabled(false);
+        let long = "bazel-out-k8-opt-nightly-2024-01-15".to_owned();
+        assert_eq!(long.len(), 35);
+        let short = "a".to_owned();
+        let maps = maps(&[(&short, LOG.to_owned()), (&long, LOG.to_owned())]);
+
+        let session_width = |args: &[&str]| with_session(args, &maps, |s| s.print_opts.label_width);
+        assert_eq!(session_width(&[]), 35);
+        assert_eq!(session_width(&["--label-width", "10"]), 10);
+        assert_eq!(label_width(std::iter::once(&short), 40), 1);
+
+        let w = session_width(&[]);
+        assert_eq!(format!("{:>w$.w$}", long.dimmed(), w = w), long);
+        assert_eq!(
+            format!("{:>w$.w$}", short.dimmed(), w = w),
+            format!("{:>35}", "a")
+        );
+    }
+
+    #[test]
+    fn much_slower_actions_are_pointed_out() {
+        let timed = |total: &str| {
+            LOG.replacen(
+                r#""remotable": true,"#,
+                &format!(
+                    r#""metrics": {{ "totalTime": "{}" }}, "remotable": true,"#,
+                    total
+                ),
+                1,
+            )
+        };
+        let maps = maps(&[
+            ("a", timed("0.500s")),
+            ("b", timed("5s")),
+            ("c", timed("0.600s")),
+            ("d", LOG.to_owned()),
+        ]);
+        let b = &maps[1].1["a.o"].0.metrics;
+        assert_eq!(b.total_time, Some(Duration::from_secs(5)));
+        assert_eq!(human_duration(b.total_time.unwrap()), "5.00s");
+        assert_eq!(human_duration(Duration::from_millis(500)), "500ms");
+
+        let actions = get(&maps, "a.o").unwrap();
+        assert_eq!(
+            timing_difference(&actions),
+            Some((Duration::from_millis(500), Duration::from_secs(5)))
+        );
+        // Only 20% slower:
+        assert_eq!(timing_difference(&[actions[0], actions[2]]), None);
+        assert_eq!(timing_difference(&actions[3..]), None);
+
+        run(&[], &maps, &["cmp a.o", "timing a.o"]);
+    }
+
+    #[test]
+    fn empty_logs_need_allow_empty() {
+        let dir = TempDir::new("empty");
+        let empty = dir.write("empty.json", b"");
+        let blank = dir.write("blank.json", b"  \n\n\t\n");
+        let spaced = dir.write(
+            "spaced.json",
+            format!("\n\n{}\n\n", LOG.replace("}{", "}\n\n{")).as_bytes(),
+        );
+
+        for extra in [&[][..], &["--low-memory"]] {
+            let args = |allow: bool| {
+                let mut args = extra.to_vec();
+                args.extend(allow.then_some("--allow-empty"));
+                args.push("x");
+                opts(&args)
+            };
+
+            for f in [&empty, &blank] {
+                let label = f.to_string_lossy().into_owned();
+                let err = load_logs(&args(false), &[(f, &label)]).unwrap_err();
+                let err = format!("{}", err);
+                assert!(
+                    err.contains(&label) && err.contains("--allow-empty"),
+                    "{}",
+                    err
+                );
+
+                let loaded = load(&args(true), &[f]);
+                assert!(loaded[0].0.is_empty());
+            }
+
+            let loaded = load(&args(false), &[&spaced]);
+            assert_eq!(
+                loaded[0].0.keys().copied().collect::<BTreeSet<_>>(),
+                BTreeSet::from(["a.o", "a.out"])
+            );
+        }
+    }
+
+    #[test]
+    fn outputs_with_multiple_actions() {
+        let dir = TempDir::new("multi");
+        let dup = dir.write(
+            "dup.json",
+            log_of(&[("dup", &[]), ("dup", &["x"])]).as_bytes(),
+        );
+        let inputs = |opts: &Opts| {
+            let (map, source) = load(opts, &[&dup]).pop().unwrap();
+            assert_eq!(source.producers["dup"].len(), 2);
+            map["dup"].0.inputs.len()
+        };
+        assert_eq!(inputs(&opts(&["x"])), 1);
+        assert_eq!(inputs(&opts(&["--multi", "last", "x"])), 1);
+        assert_eq!(inputs(&opts(&["--multi", "first", "x"])), 0);
+
+        // With `--multi all`, `cmp` compares every action for the output:
+        let opts = opts(&["--multi", "all", "x"]);
+        let (loaded, sources): (Vec<_>, Vec<_>) = load(&opts, &[&dup]).into_iter().unzip();
+        let label = "dup".to_owned();
+        let maps: Vec<_> = loaded.into_iter().map(|m| (&label, m)).collect();
+        let ignored_env = IgnoredEnv::new(Vec::new());
+        let session = Session::new(&maps, &sources, &opts, &ignored_env, None);
+        let producers: Vec<_> = session
+            .producers("dup")
+            .into_iter()
+            .map(|(l, a)| (l, a.0.inputs.len()))
+            .collect();
+        assert_eq!(
+            producers,
+            [("dup (1/2)".to_owned(), 0), ("dup (2/2)".to_owned(), 1)]
+        );
+
+        let helper = ExecLogHelper::new(&maps, false);
+        for line in ["cmp dup", "view dup --all"] {
+            run_command(line, &session, &helper).unwrap();
+        }
+        assert!(session.found_mismatches.get());
+    }
+
+    #[test]
+    fn dup_outputs_are_listed() {
+        color::set_enabled(false);
+        let dir = TempDir::new("dup-outputs");
+        let dup = dir.write(
+            "dup.json",
+            log_of(&[
+                ("dup", &[]),
+                ("once", &[]),
+                ("dup", &["x"]),
+                ("dup", &["y"]),
+            ])
+            .as_bytes(),
+        );
+        let plain = dir.write("plain.json", LOG.as_bytes());
+        let (loaded, sources): (Vec<_>, Vec<_>) =
+            load(&opts(&["x"]), &[&dup, &plain]).into_iter().unzip();
+        let labels = ["dup".to_owned(), "plain".to_owned()];
+        let maps: Vec<_> = labels.iter().zip(loaded).collect();
+
+        let listed = |label| {
+            let mut out = Vec::new();
+            write_dup_outputs(&mut out, &maps, &sources, label).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            listed(None),
+            "`dup`:\n  dup (3 actions)\n`plain`:\n  no outputs are produced by more than one action\n"
+        );
+        assert_eq!(listed(Some("dup")), "`dup`:\n  dup (3 actions)\n");
+    }
+
+    #[test]
+    fn completions_for_each_argument() {
+        let log = LOG
+            .replacen(
+                r#""remotable": true,"#,
+                r#""mnemonic": "CppCompile", "remotable": true,"#,
+                1,
+            )
+            .replacen(
+                r#""environmentVariables": []"#,
+                r#""environmentVariables": [{ "name": "PATH", "value": "/bin" }]"#,
+                1,
+            );
+        let maps = maps(&[("a", log)]);
+        let helper = ExecLogHelper::new(&maps, false);
+        let history = rustyline::history::History::new();
+        let ctx = rustyline::Context::new(&history);
+        // Asserts that `line` completes (from `start`) to `expected`:
+        let completes = |line: &str, start: usize, expected: &[&str]| {
+            let (at, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+            let replacements: Vec<_> = candidates.iter().map(|c| c.replacement()).collect();
+            assert_eq!((at, replacements.as_slice()), (start, expected), "{}", line);
+        };
+
+        completes("tc", 0, &["tcmp"]);
+        completes("cmp a.o", 4, &["a.o", "a.out"]);
+        completes("intra-cmp a.o a.ou", 14, &["a.out"]);
+        completes("rdeps a.", 6, &["a.c", "a.o"]);
+        completes("path a.out a.", 11, &["a.c", "a.o"]);
+        completes("mnemonic ", 9, &["CppCompile"]);
+        completes("ignore e", 7, &["env"]);
+        completes("ignore env ", 11, &["PATH", "V"]);
+        completes("ignore env P", 11, &["PATH"]);
+        completes("summary ", 0, &[]);
+    }
+
+    #[test]
+    fn unbalanced_quotes_continue_onto_the_next_line() {
+        let complete = |line| matches!(validate_line(line), ValidationResult::Valid(None));
+        let incomplete = |line| matches!(validate_line(line), ValidationResult::Incomplete);
+
+        assert!(complete(""));
+        assert!(complete("cmp a.o"));
+        assert!(complete(r#"cmp "My File.o""#));
+        assert!(complete(r#"cmp "say \"hi\"""#));
+        assert!(complete(r"cmp My\ File.o"));
+        assert!(incomplete(r#"cmp "bazel-out/foo"#));
+        assert!(incomplete(r#"cmp "say \"hi\""#));
+        assert!(incomplete(r"cmp My\"));
+    }
+
+    #[test]
+    fn directories_expand_into_the_logs_in_them() {
+        let dir = TempDir::new("expand-dir");
+        dir.write("b.json", LOG.as_bytes());
+        dir.write("a.json.gz", b"");
+        dir.write("notes.txt", b"");
+        dir.write("sub/c.json.zst", b"");
+
+        let labelled = |recursive| -> Vec<(PathBuf, Option<String>)> {
+            expand_args(std::slice::from_ref(&dir.0), recursive).unwrap()
+        };
+        assert_eq!(
+            labelled(false),
+            [
+                (dir.0.join("a.json.gz"), Some("a.json.gz".to_string())),
+                (dir.0.join("b.json"), Some("b.json".to_string())),
+            ]
+        );
+        assert_eq!(
+            labelled(true),
+            [
+                (dir.0.join("a.json.gz"), Some("a.json.gz".to_string())),
+                (dir.0.join("b.json"), Some("b.json".to_string())),
+                (
+                    dir.0.join("sub/c.json.zst"),
+                    Some(
+                        Path::new("sub")
+                            .join("c.json.zst")
+                            .to_string_lossy()
+                            .into_owned()
+                    )
+                ),
+            ]
+        );
+
+        let empty = TempDir::new("expand-empty-dir");
+        empty.write("notes.txt", b"");
+        let err = expand_args(std::slice::from_ref(&empty.0), true).unwrap_err();
+        assert!(err.to_string().contains("doesn't have any execution logs"));
+    }
+
+    #[test]
+    fn globs_expand_into_the_files_they_match() {
+        let dir = TempDir::new("expand-glob");
+        let a = dir.write("a.json", LOG.as_bytes());
+        let b = dir.write("b.json", LOG.as_bytes());
+        dir.write("c.txt", b"");
+
+        assert_eq!(
+            expand_args(&[dir.0.join("*.json")], false).unwrap(),
+            [(a, None), (b, None)]
+        );
+
+        let pattern = dir.0.join("*.json.gz");
+        let err = expand_args(std::slice::from_ref(&pattern), false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("`{}` doesn't match any files", pattern.display())
+        );
+    }
+
+    #[test]
+    fn big_traversals_need_yes_outside_the_prompt() {
+        let maps = maps(&[
+            ("a", LOG.to_owned()),
+            (
+                "b",
+                LOG.replace(&"1".repeat(64), &"4".repeat(64))
+                    .replace(&"2".repeat(64), &"5".repeat(64)),
+            ),
+        ]);
+        let tcmp = |args: &[&str]| {
+            with_session(args, &maps, |session| {
+                let helper = ExecLogHelper::new(session.maps, false);
+                run_command("tcmp a.out", session, &helper).map(|_| ())
+            })
+        };
+
+        let err = tcmp(&["--run", "", "--confirm-after", "1"]).unwrap_err();
+        assert!(err.to_string().contains("pass `--yes`"), "{}", err);
+        tcmp(&["--run", "", "--confirm-after", "1", "--yes"]).unwrap();
+        tcmp(&["--run", ""]).unwrap();
+    }
+
+    #[test]
+    fn logs_are_labelled_in_order() {
+        let files = [
+            (PathBuf::from("-"), None),
+            (PathBuf::from("b.json"), None),
+            (PathBuf::from("logs/c.json"), Some("c.json".to_owned())),
+        ];
+        let labels = |given: &[&str]| {
+            let given: Vec<_> = given.iter().map(|l| l.to_string()).collect();
+            log_labels(&files, &given)
+        };
+
+        assert_eq!(labels(&[]).unwrap(), ["<stdin>", "b.json", "c.json"]);
+        assert_eq!(labels(&["piped"]).unwrap(), ["piped", "b.json", "c.json"]);
+        assert_eq!(labels(&["x", "y", "z"]).unwrap(), ["x", "y", "z"]);
+        assert!(labels(&["x", "y", "z", "w"]).is_err());
+    }
 }
@@ -0,0 +1,203 @@
+//! Owned copies of the comparison results.
+//!
+//! [`MismatchSet`] and [`TransitiveCmp`] borrow from the parsed logs (and so
+//! from the bytes that those were parsed from); the types here copy out what
+//! they refer to so that results can be kept around after the logs are gone
+//! (i.e. to cache them or to compare the results of two runs).
+
+use std::path::PathBuf;
+
+use super::{Digest, DivergenceGraph, Hash, Mismatch, MismatchClass, MismatchSet, TransitiveCmp};
+
+/// An owned [`Digest`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OwnedDigest {
+    pub hash: Hash,
+    pub size_bytes: usize,
+    pub hash_function_name: String,
+}
+
+impl From<&Digest<'_>> for OwnedDigest {
+    fn from(d: &Digest<'_>) -> Self {
+        Self {
+            hash: d.hash.clone(),
+            size_bytes: d.size_bytes,
+            hash_function_name: d.hash_function_name.to_owned(),
+        }
+    }
+}
+
+/// An owned [`Mismatch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedMismatch<K, V> {
+    /// The output whose actions have the mismatch.
+    pub artifact: String,
+    /// What's mismatched: the name of an env var, the path of an input, etc.
+    pub key: K,
+    /// Whether the item is missing from some of the actions, different in
+    /// some, or both.
+    pub class: MismatchClass,
+    /// The value that each of the compared actions has, along with the label
+    /// of the log that it's from (`None` if the action doesn't have the item).
+    pub values: Vec<(String, Option<V>)>,
+}
+
+impl<K, V> OwnedMismatch<K, V> {
+    /// The value that the log labelled `log` has; `None` if it's not present
+    /// (or if that log's action wasn't compared).
+    pub fn value(&self, log: &str) -> Option<&V> {
+        self.values.iter().find(|(l, _)| l == log)?.1.as_ref()
+    }
+}
+
+impl<'l, K, V> Mismatch<'l, K, V> {
+    /// Copies this mismatch out of the logs, converting its key and values
+    /// with `key` and `value`.
+    fn to_owned_with<K2, V2>(
+        &self,
+        key: impl Fn(&K) -> K2,
+        value: impl Fn(&V) -> V2,
+    ) -> OwnedMismatch<K2, V2> {
+        OwnedMismatch {
+            artifact: self.artifact.to_owned(),
+            key: key(&self.key),
+            class: self.class,
+            values: self
+                .values
+                .iter()
+                .map(|(l, v)| ((*l).to_owned(), v.as_ref().map(&value)))
+                .collect(),
+        }
+    }
+}
+
+/// An owned [`MismatchSet`]; see [`MismatchSet::into_owned`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OwnedMismatchSet {
+    /// Env vars, by name.
+    pub env_vars: Vec<OwnedMismatch<String, String>>,
+    /// Inputs, by path.
+    pub inputs: Vec<OwnedMismatch<PathBuf, OwnedDigest>>,
+    /// Outputs, by path.
+    pub outputs: Vec<OwnedMismatch<PathBuf, OwnedDigest>>,
+    /// The first argument that differs, by index.
+    pub args: Vec<OwnedMismatch<usize, String>>,
+    /// Platform properties, by name.
+    pub platform: Vec<OwnedMismatch<String, String>>,
+    /// Exit codes and statuses.
+    pub exit_status: Vec<OwnedMismatch<(), (i32, String)>>,
+    /// Where the actions ran: their runners and whether they were cache hits.
+    pub runner: Vec<OwnedMismatch<(), (String, bool)>>,
+}
+
+impl OwnedMismatchSet {
+    /// Whether nothing differs, not counting where the actions ran (their
+    /// `runner`s); see [`MismatchSet::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.env_vars.is_empty()
+            && self.inputs.is_empty()
+            && self.outputs.is_empty()
+            && self.args.is_empty()
+            && self.platform.is_empty()
+            && self.exit_status.is_empty()
+    }
+}
+
+impl MismatchSet<'_> {
+    /// Copies these mismatches out of the logs that they were found in so that
+    /// they can outlive them.
+    pub fn into_owned(self) -> OwnedMismatchSet {
+        let string = |s: &&str| (*s).to_owned();
+        let path = |p: &&std::path::Path| p.to_path_buf();
+        let digest = |d: &&Digest<'_>| OwnedDigest::from(*d);
+
+        OwnedMismatchSet {
+            env_vars: self
+                .env_vars
+                .iter()
+                .map(|m| m.to_owned_with(string, string))
+                .collect(),
+            inputs: self
+                .inputs
+                .iter()
+                .map(|m| m.to_owned_with(path, digest))
+                .collect(),
+            outputs: self
+                .outputs
+                .iter()
+                .map(|m| m.to_owned_with(path, digest))
+                .collect(),
+            args: self
+                .args
+                .iter()
+                .map(|m| m.to_owned_with(|i| *i, string))
+                .collect(),
+            platform: self
+                .platform
+                .iter()
+                .map(|m| m.to_owned_with(string, string))
+                .collect(),
+            exit_status: self
+                .exit_status
+                .iter()
+                .map(|m| m.to_owned_with(|_| (), |(c, s)| (*c, (*s).to_owned())))
+                .collect(),
+            runner: self
+                .runner
+                .iter()
+                .map(|m| m.to_owned_with(|_| (), |(r, h)| ((*r).to_owned(), *h)))
+                .collect(),
+        }
+    }
+}
+
+/// An owned [`DivergenceGraph`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OwnedDivergenceGraph {
+    /// Each artifact whose actions were compared and whether they differed.
+    pub nodes: Vec<(String, bool)>,
+    /// From an artifact to one of its (mismatched) inputs.
+    pub edges: Vec<(String, String)>,
+    /// Compared artifacts whose mismatched inputs weren't followed because
+    /// they're at the depth limit.
+    pub truncated: Vec<String>,
+}
+
+/// An owned [`TransitiveCmp`]; see [`TransitiveCmp::into_owned`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OwnedTransitiveCmp {
+    /// See [`TransitiveCmp::mismatches`].
+    pub mismatches: OwnedMismatchSet,
+    /// See [`TransitiveCmp::leaves`].
+    pub leaves: Vec<String>,
+    /// See [`TransitiveCmp::origins`].
+    pub origins: OwnedMismatchSet,
+    /// See [`TransitiveCmp::graph`].
+    pub graph: OwnedDivergenceGraph,
+}
+
+impl TransitiveCmp<'_> {
+    /// Copies these results out of the logs that they were found in so that
+    /// they can outlive them.
+    pub fn into_owned(self) -> OwnedTransitiveCmp {
+        let DivergenceGraph {
+            nodes,
+            edges,
+            truncated,
+        } = self.graph;
+
+        OwnedTransitiveCmp {
+            mismatches: self.mismatches.into_owned(),
+            leaves: self.leaves.into_iter().map(str::to_owned).collect(),
+            origins: self.origins.into_owned(),
+            graph: OwnedDivergenceGraph {
+                nodes: nodes.into_iter().map(|(n, d)| (n.to_owned(), d)).collect(),
+                edges: edges
+                    .into_iter()
+                    .map(|(f, t)| (f.to_owned(), t.to_owned()))
+                    .collect(),
+                truncated: truncated.into_iter().map(str::to_owned).collect(),
+            },
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Paging long output through `$PAGER` (`less -R` by default), like `git`
+//! does (see `--no-pager`).
+//!
+//! Commands print to stdout as usual; while a [`Capture`] is alive, stdout
+//! goes to a temporary file instead. Once the command is done, what it
+//! printed is shown in the pager if it doesn't fit on the screen and is just
+//! printed otherwise. This way the pager is never left waiting on (or quit in
+//! the middle of) a command that's still printing.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::process::{Command, Stdio};
+
+use terminal_size::{Height, Width};
+
+/// The pager used when `$PAGER` isn't set; `-R` keeps the colors.
+const DEFAULT_PAGER: &str = "less -R";
+
+/// The size of the terminal that stdout is going to (or, while it's being
+/// captured, was going to).
+pub fn terminal_size() -> Option<(Width, Height)> {
+    imp::terminal_size()
+}
+
+/// Sends stdout somewhere else until [`Capture::finish`] is called (or the
+/// capture is dropped, in which case what was printed is lost).
+pub struct Capture(imp::Capture);
+
+impl Capture {
+    /// Starts capturing stdout; `None` if there's no pager to show it in or
+    /// stdout can't be captured (i.e. on platforms other than Unix).
+    pub fn start() -> Option<Self> {
+        let pager = pager()?;
+        imp::Capture::start(pager).map(Self)
+    }
+
+    /// Puts stdout back and shows what was printed while it was captured.
+    pub fn finish(self) {
+        let Self(mut capture) = self;
+        let (out, pager) = match capture.restore() {
+            Ok(captured) => captured,
+            Err(e) => {
+                eprintln!("couldn't read the output back: {}", e);
+                return;
+            }
+        };
+
+        let rows = terminal_size().map_or(usize::MAX, |(_, Height(h))| h as usize);
+        // Leave room for the prompt:
+        if out.iter().filter(|b| **b == b'\n').count() < rows.saturating_sub(1) {
+            let _ = io::stdout().write_all(&out);
+            return;
+        }
+
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&pager)
+            .stdin(Stdio::piped())
+            .spawn();
+        match child {
+            Ok(mut child) => {
+                // The pager can be quit before it's read everything:
+                let _ = child.stdin.take().unwrap().write_all(&out);
+                let _ = child.wait();
+            }
+            Err(e) => {
+                eprintln!("couldn't run the pager (`{}`): {}", pager, e);
+                let _ = io::stdout().write_all(&out);
+            }
+        }
+    }
+}
+
+/// Prints what's been captured so far straight to where stdout was going; for
+/// when the process is about to exit without finishing the capture (i.e. on a
+/// second Ctrl-C).
+pub fn dump_capture() {
+    imp::dump_capture()
+}
+
+/// The pager to use, if any; `$PAGER` can be set to an empty string (or
+/// `cat`) to not page.
+fn pager() -> Option<String> {
+    match std::env::var("PAGER") {
+        Ok(p) if p.trim().is_empty() || p.trim() == "cat" => None,
+        Ok(p) => Some(p),
+        Err(_) => Some(DEFAULT_PAGER.to_owned()),
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::fs::{self, File};
+    use std::mem::ManuallyDrop;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    use super::*;
+
+    /// The file descriptor that stdout was going to before it was captured;
+    /// `-1` while it isn't.
+    static TERMINAL: AtomicI32 = AtomicI32::new(-1);
+
+    /// The file descriptor of the file that stdout is captured in; `-1` while
+    /// it isn't.
+    static CAPTURED: AtomicI32 = AtomicI32::new(-1);
+
+    pub fn terminal_size() -> Option<(Width, Height)> {
+        match TERMINAL.load(Ordering::Relaxed) {
+            -1 => terminal_size::terminal_size(),
+            fd => terminal_size::terminal_size_using_fd(fd),
+        }
+    }
+
+    pub struct Capture {
+        /// Already unlinked; it's gone once this is closed.
+        file: File,
+        pager: String,
+        /// The original stdout, until it's restored.
+        stdout: Option<RawFd>,
+    }
+
+    impl Capture {
+        pub fn start(pager: String) -> Option<Self> {
+            io::stdout().flush().ok()?;
+
+            // The temporary directory is shared so the file mustn't already
+            // exist (or be a symlink someone left there); it's unlinked right
+            // away so that nothing else can open it and it doesn't outlive us:
+            let path =
+                std::env::temp_dir().join(format!("bazel-execlog-cmp-{}.out", std::process::id()));
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .mode(0o600)
+                .open(&path)
+                .ok()?;
+            fs::remove_file(&path).ok()?;
+
+            // Safety: these only deal with file descriptors that we own (or
+            // stdout's, which is never closed).
+            let stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
+            if stdout < 0 {
+                return None;
+            }
+            if unsafe { libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+                unsafe { libc::close(stdout) };
+                return None;
+            }
+            TERMINAL.store(stdout, Ordering::Relaxed);
+            CAPTURED.store(file.as_raw_fd(), Ordering::Relaxed);
+
+            Some(Self {
+                file,
+                pager,
+                stdout: Some(stdout),
+            })
+        }
+
+        /// Puts stdout back; returns what was printed and the pager to show
+        /// it in.
+        pub fn restore(&mut self) -> io::Result<(Vec<u8>, String)> {
+            self.put_back();
+
+            let mut out = Vec::new();
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.read_to_end(&mut out)?;
+
+            Ok((out, std::mem::take(&mut self.pager)))
+        }
+
+        fn put_back(&mut self) {
+            if let Some(stdout) = self.stdout.take() {
+                let _ = io::stdout().flush();
+                CAPTURED.store(-1, Ordering::Relaxed);
+                TERMINAL.store(-1, Ordering::Relaxed);
+                // Safety: see `start`.
+                unsafe {
+                    libc::dup2(stdout, libc::STDOUT_FILENO);
+                    libc::close(stdout);
+                }
+            }
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            self.put_back();
+        }
+    }
+
+    pub fn dump_capture() {
+        let (terminal, captured) = (
+            TERMINAL.load(Ordering::Relaxed),
+            CAPTURED.load(Ordering::Relaxed),
+        );
+        if terminal < 0 || captured < 0 {
+            return;
+        }
+
+        let _ = io::stdout().flush();
+        // Safety: these are still open (`put_back` forgets them before
+        // closing anything) and aren't closed here.
+        let (mut terminal, mut captured) = unsafe {
+            (
+                ManuallyDrop::new(File::from_raw_fd(terminal)),
+                ManuallyDrop::new(File::from_raw_fd(captured)),
+            )
+        };
+        let mut out = Vec::new();
+        if captured.seek(SeekFrom::Start(0)).is_ok() && captured.read_to_end(&mut out).is_ok() {
+            let _ = terminal.write_all(&out);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use super::*;
+
+    pub fn terminal_size() -> Option<(Width, Height)> {
+        terminal_size::terminal_size()
+    }
+
+    pub fn dump_capture() {}
+
+    pub enum Capture {}
+
+    impl Capture {
+        pub fn start(_pager: String) -> Option<Self> {
+            None
+        }
+
+        pub fn restore(&mut self) -> io::Result<(Vec<u8>, String)> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_never_follows_a_planted_symlink() {
+        let tmp = std::env::temp_dir();
+        let target = tmp.join(format!("bazel-execlog-cmp-{}.victim", std::process::id()));
+        let planted = tmp.join(format!("bazel-execlog-cmp-{}.out", std::process::id()));
+        std::fs::write(&target, "keep me").unwrap();
+        let _ = std::fs::remove_file(&planted);
+        std::os::unix::fs::symlink(&target, &planted).unwrap();
+
+        let capture = imp::Capture::start("cat".to_owned());
+        let kept = std::fs::read_to_string(&target).unwrap();
+        std::fs::remove_file(&planted).unwrap();
+        std::fs::remove_file(&target).unwrap();
+
+        assert!(capture.is_none());
+        assert_eq!(kept, "keep me");
+    }
+}
@@ -0,0 +1,778 @@
+//! Parsers for Bazel's protobuf based execution log formats.
+//!
+//! These produce the same `Map` that JSON execution logs do so that nothing
+//! downstream has to care about which format a log was in.
+//!
+//! The messages here are a subset of the ones in Bazel's
+//! `src/main/protobuf/spawn.proto`; fields that we don't compare are left out
+//! (and are skipped over when decoding).
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre;
+use indicatif::ProgressBar;
+use prost::{Message, Oneof};
+
+use super::{
+    insert_action, ActionContext, Digest, EnvVar, Hash, Interner, Item, Map, Metrics, Platform,
+    PlatformProperty, Producers,
+};
+
+/// The hash of an empty file with each hash function that Bazel supports; the
+/// compact format omits digests for these.
+const EMPTY_HASHES: &[(&str, &str)] = &[
+    ("SHA-1", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+    (
+        "SHA-256",
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    ),
+    (
+        "SHA-384",
+        "38b060a751ac96384cd9327eb1b1e36a21fdb71114be07434c0cc7bf63f6e1da274edebfe76f65fbd51ad2f14898b95b",
+    ),
+    (
+        "SHA-512",
+        "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+    ),
+    (
+        "BLAKE3",
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+    ),
+];
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDigest {
+    #[prost(string, tag = "1")]
+    hash: String,
+    #[prost(int64, tag = "2")]
+    size_bytes: i64,
+    #[prost(string, tag = "3")]
+    hash_function_name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EnvironmentVariable {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Property {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoPlatform {
+    #[prost(message, repeated, tag = "1")]
+    properties: Vec<Property>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDuration {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct SpawnMetrics {
+    #[prost(message, optional, tag = "1")]
+    total_time: Option<ProtoDuration>,
+    #[prost(message, optional, tag = "5")]
+    queue_time: Option<ProtoDuration>,
+    #[prost(message, optional, tag = "8")]
+    execution_wall_time: Option<ProtoDuration>,
+}
+
+/// An action in a binary execution log (`--execution_log_binary_file`).
+#[derive(Clone, PartialEq, Message)]
+struct SpawnExec {
+    #[prost(string, repeated, tag = "1")]
+    command_args: Vec<String>,
+    #[prost(message, repeated, tag = "2")]
+    environment_variables: Vec<EnvironmentVariable>,
+    #[prost(message, optional, tag = "3")]
+    platform: Option<ProtoPlatform>,
+    #[prost(message, repeated, tag = "4")]
+    inputs: Vec<File>,
+    #[prost(string, repeated, tag = "5")]
+    listed_outputs: Vec<String>,
+    #[prost(bool, tag = "6")]
+    remotable: bool,
+    #[prost(bool, tag = "7")]
+    cacheable: bool,
+    #[prost(string, tag = "10")]
+    mnemonic: String,
+    #[prost(message, repeated, tag = "11")]
+    actual_outputs: Vec<File>,
+    #[prost(string, tag = "12")]
+    runner: String,
+    #[prost(bool, tag = "13")]
+    remote_cache_hit: bool,
+    #[prost(string, tag = "14")]
+    status: String,
+    #[prost(int32, tag = "15")]
+    exit_code: i32,
+    #[prost(message, optional, tag = "20")]
+    metrics: Option<SpawnMetrics>,
+}
+
+/// An entry in a compact execution log (`--execution_log_compact_file`).
+///
+/// Entries with an `id` can be referred to by later entries; this is how
+/// inputs that are shared between lots of actions are only listed once.
+#[derive(Clone, PartialEq, Message)]
+struct ExecLogEntry {
+    #[prost(uint32, tag = "1")]
+    id: u32,
+    #[prost(oneof = "Entry", tags = "2, 3, 4, 6, 7")]
+    entry: Option<Entry>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+enum Entry {
+    #[prost(message, tag = "2")]
+    Invocation(Invocation),
+    #[prost(message, tag = "3")]
+    File(File),
+    #[prost(message, tag = "4")]
+    Directory(Directory),
+    #[prost(message, tag = "6")]
+    InputSet(InputSet),
+    #[prost(message, tag = "7")]
+    Spawn(Spawn),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Invocation {
+    #[prost(string, tag = "1")]
+    hash_function_name: String,
+}
+
+/// A file; used by both the binary (`SpawnExec`) and compact formats.
+///
+/// In compact logs the hash function name is left out of the digest (it's in
+/// the `Invocation` instead) and empty files have no digest.
+#[derive(Clone, PartialEq, Message)]
+struct File {
+    #[prost(string, tag = "1")]
+    path: String,
+    #[prost(message, optional, tag = "2")]
+    digest: Option<ProtoDigest>,
+}
+
+/// A source directory, fileset or tree artifact; `files` have paths relative to
+/// the directory.
+#[derive(Clone, PartialEq, Message)]
+struct Directory {
+    #[prost(string, tag = "1")]
+    path: String,
+    #[prost(message, repeated, tag = "2")]
+    files: Vec<File>,
+}
+
+/// A (nested) set of inputs.
+///
+/// Older versions of Bazel list files and directories separately (`file_ids`,
+/// `directory_ids`); newer ones use `input_ids` for both.
+#[derive(Clone, PartialEq, Message)]
+struct InputSet {
+    #[prost(uint32, repeated, tag = "1")]
+    file_ids: Vec<u32>,
+    #[prost(uint32, repeated, tag = "2")]
+    directory_ids: Vec<u32>,
+    #[prost(uint32, repeated, tag = "4")]
+    transitive_set_ids: Vec<u32>,
+    #[prost(uint32, repeated, tag = "5")]
+    input_ids: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Output {
+    #[prost(oneof = "OutputKind", tags = "1, 2, 4, 5")]
+    kind: Option<OutputKind>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+enum OutputKind {
+    #[prost(uint32, tag = "1")]
+    FileId(u32),
+    #[prost(uint32, tag = "2")]
+    DirectoryId(u32),
+    /// A declared output that's missing (or isn't the kind of thing that was
+    /// expected).
+    #[prost(string, tag = "4")]
+    InvalidOutputPath(String),
+    #[prost(uint32, tag = "5")]
+    OutputId(u32),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Spawn {
+    #[prost(string, repeated, tag = "1")]
+    args: Vec<String>,
+    #[prost(message, repeated, tag = "2")]
+    env_vars: Vec<EnvironmentVariable>,
+    #[prost(message, optional, tag = "3")]
+    platform: Option<ProtoPlatform>,
+    #[prost(uint32, tag = "4")]
+    input_set_id: u32,
+    #[prost(message, repeated, tag = "6")]
+    outputs: Vec<Output>,
+    #[prost(string, tag = "8")]
+    mnemonic: String,
+    #[prost(string, tag = "9")]
+    runner: String,
+    #[prost(bool, tag = "10")]
+    cache_hit: bool,
+    #[prost(string, tag = "11")]
+    status: String,
+    #[prost(int32, tag = "12")]
+    exit_code: i32,
+    #[prost(bool, tag = "13")]
+    remotable: bool,
+    #[prost(bool, tag = "14")]
+    cacheable: bool,
+    #[prost(message, optional, tag = "18")]
+    metrics: Option<SpawnMetrics>,
+}
+
+/// Reads the length prefix of the next message in a log, if there is one.
+fn read_len(log: &mut impl Read) -> eyre::Result<Option<(usize, usize)>> {
+    let mut len = 0;
+    for i in 0..10 {
+        let mut b = [0];
+        if log.read(&mut b)? == 0 {
+            return if i == 0 {
+                Ok(None)
+            } else {
+                Err(eyre::eyre!("execution log ends in the middle of a message"))
+            };
+        }
+
+        len |= ((b[0] & 0x7f) as usize) << (7 * i);
+        if b[0] & 0x80 == 0 {
+            return Ok(Some((len, i + 1)));
+        }
+    }
+
+    Err(eyre::eyre!("invalid message length in execution log"))
+}
+
+/// Decodes each of the length delimited messages in `log`, in order.
+fn read_messages<M: Message + Default>(
+    mut log: impl BufRead,
+    pb: &ProgressBar,
+    mut func: impl FnMut(M) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    let mut buf = Vec::new();
+    while let Some((len, prefix_len)) = read_len(&mut log)? {
+        buf.resize(len, 0);
+        log.read_exact(&mut buf)?;
+        pb.inc((prefix_len + len) as _);
+
+        func(M::decode(&buf[..])?)?;
+    }
+
+    pb.finish();
+
+    Ok(())
+}
+
+fn parse_hash(hex: &str) -> eyre::Result<Hash> {
+    Hash::from_hex(hex).ok_or_else(|| eyre::eyre!("`{}` isn't a hex encoded hash", hex))
+}
+
+/// The hash of an empty file with the hash function `name`.
+fn empty_hash(name: &str) -> eyre::Result<&'static str> {
+    EMPTY_HASHES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, h)| *h)
+        .ok_or_else(|| eyre::eyre!("unknown hash function `{}`", name))
+}
+
+/// Makes an `Item`; `hash_function_name` is used if the digest doesn't have
+/// one.
+fn item(
+    strings: &mut Interner,
+    path: &str,
+    digest: Option<&ProtoDigest>,
+    hash_function_name: &'static str,
+) -> eyre::Result<Item<'static>> {
+    let (hash, size_bytes, hash_function_name) = match digest {
+        Some(d) if !d.hash_function_name.is_empty() => (
+            d.hash.as_str(),
+            d.size_bytes as usize,
+            strings.intern(&d.hash_function_name),
+        ),
+        Some(d) => (d.hash.as_str(), d.size_bytes as usize, hash_function_name),
+        None => (empty_hash(hash_function_name)?, 0, hash_function_name),
+    };
+
+    Ok(Item {
+        path: Path::new(strings.intern(path)),
+        digest: Digest {
+            hash: parse_hash(hash)?,
+            size_bytes,
+            hash_function_name,
+        },
+    })
+}
+
+fn args(strings: &mut Interner, args: &[String]) -> Vec<Cow<'static, str>> {
+    args.iter().map(|a| strings.intern(a).into()).collect()
+}
+
+fn env_vars(strings: &mut Interner, vars: &[EnvironmentVariable]) -> Vec<EnvVar<'static>> {
+    vars.iter()
+        .map(|e| EnvVar {
+            name: strings.intern(&e.name),
+            value: strings.intern(&e.value),
+        })
+        .collect()
+}
+
+fn metrics(metrics: Option<&SpawnMetrics>) -> Metrics {
+    let duration = |d: &Option<ProtoDuration>| {
+        d.as_ref()
+            .map(|d| Duration::new(d.seconds.max(0) as u64, d.nanos.max(0) as u32))
+    };
+
+    metrics
+        .map(|m| Metrics {
+            total_time: duration(&m.total_time),
+            execution_wall_time: duration(&m.execution_wall_time),
+            queue_time: duration(&m.queue_time),
+        })
+        .unwrap_or_default()
+}
+
+fn platform(strings: &mut Interner, platform: Option<&ProtoPlatform>) -> Platform<'static> {
+    Platform {
+        properties: platform
+            .iter()
+            .flat_map(|p| p.properties.iter())
+            .map(|p| PlatformProperty {
+                name: strings.intern(&p.name),
+                value: strings.intern(&p.value),
+            })
+            .collect(),
+    }
+}
+
+/// The parsed actions of a log, keyed by output.
+#[derive(Default)]
+struct Actions {
+    map: Map<'static>,
+    producers: Producers<'static>,
+}
+
+impl Actions {
+    fn insert(&mut self, ctx: ActionContext<'static>) -> eyre::Result<()> {
+        #[cfg(feature = "json-dump-command")]
+        let val = serde_json::to_value(&ctx)?;
+
+        let action = Arc::new((
+            ctx,
+            #[cfg(feature = "json-dump-command")]
+            val,
+        ));
+        insert_action(&mut self.map, &mut self.producers, action);
+
+        Ok(())
+    }
+}
+
+/// The state needed to resolve references to earlier entries in a compact
+/// execution log.
+#[derive(Default)]
+struct CompactLog {
+    strings: Interner,
+    hash_function_name: &'static str,
+    /// The paths of files and directories.
+    paths: HashMap<u32, &'static str>,
+    /// Files and directories (expanded into the files they contain).
+    files: HashMap<u32, Vec<Item<'static>>>,
+    input_sets: HashMap<u32, InputSet>,
+}
+
+impl CompactLog {
+    /// Adds the files in the input set `id` (and the sets it contains) to
+    /// `out`.
+    fn flatten(&self, id: u32, out: &mut Vec<Item<'static>>, seen: &mut HashSet<u32>) {
+        if !seen.insert(id) {
+            return;
+        }
+
+        if let Some(set) = self.input_sets.get(&id) {
+            for t in set.transitive_set_ids.iter() {
+                self.flatten(*t, out, seen);
+            }
+
+            let direct = set
+                .file_ids
+                .iter()
+                .chain(&set.directory_ids)
+                .chain(&set.input_ids);
+            for i in direct {
+                if let Some(files) = self.files.get(i) {
+                    out.extend(files.iter().cloned());
+                }
+            }
+        }
+    }
+
+    fn action(&mut self, spawn: Spawn) -> ActionContext<'static> {
+        let mut inputs = Vec::new();
+        self.flatten(spawn.input_set_id, &mut inputs, &mut HashSet::new());
+        inputs.sort();
+        inputs.dedup();
+
+        let mut listed_outputs = Vec::new();
+        let mut actual_outputs = Vec::new();
+        for o in spawn.outputs {
+            match o.kind {
+                Some(OutputKind::FileId(id))
+                | Some(OutputKind::DirectoryId(id))
+                | Some(OutputKind::OutputId(id)) => {
+                    if let Some(files) = self.files.get(&id) {
+                        actual_outputs.extend(files.iter().cloned());
+                    }
+                    if let Some(path) = self.paths.get(&id) {
+                        listed_outputs.push(*path);
+                    }
+                }
+                Some(OutputKind::InvalidOutputPath(p)) => {
+                    listed_outputs.push(self.strings.intern(&p))
+                }
+                None => {}
+            }
+        }
+
+        ActionContext {
+            command_args: args(&mut self.strings, &spawn.args),
+            environment_variables: env_vars(&mut self.strings, &spawn.env_vars),
+            platform: platform(&mut self.strings, spawn.platform.as_ref()),
+            inputs,
+            listed_outputs,
+            remotable: spawn.remotable,
+            cacheable: spawn.cacheable,
+            mnemonic: self.strings.intern(&spawn.mnemonic),
+            actual_outputs,
+            status: self.strings.intern(&spawn.status),
+            exit_code: spawn.exit_code,
+            runner: self.strings.intern(&spawn.runner),
+            cache_hit: spawn.cache_hit,
+            metrics: metrics(spawn.metrics.as_ref()),
+        }
+    }
+}
+
+/// Parses a compact execution log (`--execution_log_compact_file`).
+///
+/// Compact logs are a stream of length delimited `ExecLogEntry` messages (that
+/// are usually zstd compressed; `open_log` takes care of that). Inputs are
+/// resolved through the nested input sets into the same flat list of files
+/// that JSON execution logs have.
+///
+/// Runfiles trees and unresolved symlinks aren't modeled; these are left out
+/// of actions' inputs and outputs.
+pub fn compact_log_to_hashmap(
+    log: impl BufRead,
+    pb: &ProgressBar,
+) -> eyre::Result<(Map<'static>, Producers<'static>)> {
+    let mut state = CompactLog {
+        hash_function_name: "SHA-256",
+        ..Default::default()
+    };
+    let mut actions = Actions::default();
+
+    read_messages(log, pb, |ExecLogEntry { id, entry }| {
+        let hash_function_name = state.hash_function_name;
+        match entry {
+            Some(Entry::Invocation(i)) => {
+                state.hash_function_name = state.strings.intern(&i.hash_function_name)
+            }
+            Some(Entry::File(f)) => {
+                let item = item(
+                    &mut state.strings,
+                    &f.path,
+                    f.digest.as_ref(),
+                    hash_function_name,
+                )?;
+                state.paths.insert(id, item.path.to_str().unwrap());
+                state.files.insert(id, vec![item]);
+            }
+            Some(Entry::Directory(d)) => {
+                let files = d
+                    .files
+                    .iter()
+                    .map(|f| {
+                        item(
+                            &mut state.strings,
+                            &format!("{}/{}", d.path, f.path),
+                            f.digest.as_ref(),
+                            hash_function_name,
+                        )
+                    })
+                    .collect::<eyre::Result<_>>()?;
+                state.paths.insert(id, state.strings.intern(&d.path));
+                state.files.insert(id, files);
+            }
+            Some(Entry::InputSet(s)) => {
+                state.input_sets.insert(id, s);
+            }
+            Some(Entry::Spawn(s)) => actions.insert(state.action(s))?,
+            None => {}
+        }
+
+        Ok(())
+    })?;
+
+    Ok((actions.map, actions.producers))
+}
+
+/// Parses a binary execution log (`--execution_log_binary_file`): a stream of
+/// length delimited `SpawnExec` messages.
+pub fn binary_log_to_hashmap(
+    log: impl BufRead,
+    pb: &ProgressBar,
+) -> eyre::Result<(Map<'static>, Producers<'static>)> {
+    let mut strings = Interner::default();
+    let mut actions = Actions::default();
+
+    read_messages(log, pb, |spawn: SpawnExec| {
+        let mut items = |files: &[File]| -> eyre::Result<Vec<_>> {
+            files
+                .iter()
+                .map(|f| item(&mut strings, &f.path, f.digest.as_ref(), "SHA-256"))
+                .collect()
+        };
+        let inputs = items(&spawn.inputs)?;
+        let actual_outputs = items(&spawn.actual_outputs)?;
+
+        actions.insert(ActionContext {
+            command_args: args(&mut strings, &spawn.command_args),
+            environment_variables: env_vars(&mut strings, &spawn.environment_variables),
+            platform: platform(&mut strings, spawn.platform.as_ref()),
+            inputs,
+            listed_outputs: spawn
+                .listed_outputs
+                .iter()
+                .map(|o| strings.intern(o))
+                .collect(),
+            remotable: spawn.remotable,
+            cacheable: spawn.cacheable,
+            mnemonic: strings.intern(&spawn.mnemonic),
+            actual_outputs,
+            status: strings.intern(&spawn.status),
+            exit_code: spawn.exit_code,
+            runner: strings.intern(&spawn.runner),
+            cache_hit: spawn.remote_cache_hit,
+            metrics: metrics(spawn.metrics.as_ref()),
+        })
+    })?;
+
+    Ok((actions.map, actions.producers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_exec_log;
+    use super::*;
+
+    const HASH_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const HASH_B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    /// `messages`, as a log of length delimited messages.
+    fn encode<M: Message>(messages: &[M]) -> Vec<u8> {
+        let mut log = Vec::new();
+        for m in messages {
+            m.encode_length_delimited(&mut log).unwrap();
+        }
+        log
+    }
+
+    /// A JSON execution log with the action that the protobuf logs in the
+    /// tests below have.
+    const JSON_LOG: &str = r#"{
+        "commandArgs": ["cc", "-c", "src/a.c", "-o", "out/a.o"],
+        "environmentVariables": [{ "name": "PATH", "value": "/bin" }],
+        "platform": { "properties": [{ "name": "OSFamily", "value": "Linux" }] },
+        "inputs": [
+            {
+                "path": "src/a.c",
+                "digest": { "hash": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", "sizeBytes": "10", "hashFunctionName": "SHA-256" }
+            },
+            {
+                "path": "src/empty.h",
+                "digest": { "hash": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", "sizeBytes": "0", "hashFunctionName": "SHA-256" }
+            }
+        ],
+        "listedOutputs": ["out/a.o"],
+        "remotable": true,
+        "cacheable": true,
+        "mnemonic": "CppCompile",
+        "actualOutputs": [
+            {
+                "path": "out/a.o",
+                "digest": { "hash": "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb", "sizeBytes": "20", "hashFunctionName": "SHA-256" }
+            }
+        ],
+        "runner": "linux-sandbox",
+        "exitCode": 0
+    }"#;
+
+    fn env() -> Vec<EnvironmentVariable> {
+        vec![EnvironmentVariable {
+            name: "PATH".to_owned(),
+            value: "/bin".to_owned(),
+        }]
+    }
+
+    fn linux() -> Option<ProtoPlatform> {
+        Some(ProtoPlatform {
+            properties: vec![Property {
+                name: "OSFamily".to_owned(),
+                value: "Linux".to_owned(),
+            }],
+        })
+    }
+
+    fn args() -> Vec<String> {
+        ["cc", "-c", "src/a.c", "-o", "out/a.o"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect()
+    }
+
+    fn digest(hash: &str, size_bytes: i64, hash_function_name: &str) -> Option<ProtoDigest> {
+        Some(ProtoDigest {
+            hash: hash.to_owned(),
+            size_bytes,
+            hash_function_name: hash_function_name.to_owned(),
+        })
+    }
+
+    fn file(path: &str, digest: Option<ProtoDigest>) -> File {
+        File {
+            path: path.to_owned(),
+            digest,
+        }
+    }
+
+    /// Asserts that `map` has the same actions as [`JSON_LOG`].
+    fn assert_matches_json(map: &Map<'_>) {
+        let (json, _) = parse_exec_log(JSON_LOG.as_bytes()).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["out/a.o"].0, json["out/a.o"].0);
+    }
+
+    #[test]
+    fn compact_log_matches_json_log() {
+        let entry = |id, entry| ExecLogEntry {
+            id,
+            entry: Some(entry),
+        };
+        let log = encode(&[
+            entry(
+                0,
+                Entry::Invocation(Invocation {
+                    hash_function_name: "SHA-256".to_owned(),
+                }),
+            ),
+            entry(1, Entry::File(file("src/a.c", digest(HASH_A, 10, "")))),
+            // Empty files don't have digests:
+            entry(2, Entry::File(file("src/empty.h", None))),
+            entry(
+                3,
+                Entry::InputSet(InputSet {
+                    input_ids: vec![2],
+                    ..Default::default()
+                }),
+            ),
+            // The inputs are in a nested set, and one is listed twice:
+            entry(
+                4,
+                Entry::InputSet(InputSet {
+                    file_ids: vec![1],
+                    transitive_set_ids: vec![3],
+                    input_ids: vec![2],
+                    ..Default::default()
+                }),
+            ),
+            entry(5, Entry::File(file("out/a.o", digest(HASH_B, 20, "")))),
+            entry(
+                0,
+                Entry::Spawn(Spawn {
+                    args: args(),
+                    env_vars: env(),
+                    platform: linux(),
+                    input_set_id: 4,
+                    outputs: vec![Output {
+                        kind: Some(OutputKind::OutputId(5)),
+                    }],
+                    mnemonic: "CppCompile".to_owned(),
+                    runner: "linux-sandbox".to_owned(),
+                    remotable: true,
+                    cacheable: true,
+                    ..Default::default()
+                }),
+            ),
+        ]);
+
+        let (map, _) = compact_log_to_hashmap(log.as_slice(), &ProgressBar::hidden()).unwrap();
+        assert_matches_json(&map);
+    }
+
+    #[test]
+    fn binary_log_matches_json_log() {
+        let log = encode(&[SpawnExec {
+            command_args: args(),
+            environment_variables: env(),
+            platform: linux(),
+            inputs: vec![
+                file("src/a.c", digest(HASH_A, 10, "SHA-256")),
+                file(
+                    "src/empty.h",
+                    digest(
+                        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                        0,
+                        "SHA-256",
+                    ),
+                ),
+            ],
+            listed_outputs: vec!["out/a.o".to_owned()],
+            remotable: true,
+            cacheable: true,
+            mnemonic: "CppCompile".to_owned(),
+            actual_outputs: vec![file("out/a.o", digest(HASH_B, 20, "SHA-256"))],
+            runner: "linux-sandbox".to_owned(),
+            ..Default::default()
+        }]);
+
+        let (map, _) = binary_log_to_hashmap(log.as_slice(), &ProgressBar::hidden()).unwrap();
+        let output = &map["out/a.o"].0.actual_outputs[0];
+        assert_eq!(
+            output.digest,
+            Digest {
+                hash: Hash::from_hex(HASH_B).unwrap(),
+                size_bytes: 20,
+                hash_function_name: "SHA-256",
+            }
+        );
+        assert_matches_json(&map);
+    }
+}